@@ -0,0 +1,50 @@
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+
+struct TerrainSplatParams {
+    // World units per texture repeat, four layers per vector
+    tile_sizes: array<vec4<f32>, 2>,
+}
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(100) var<uniform> params: TerrainSplatParams;
+@group(#{MATERIAL_BIND_GROUP}) @binding(101) var layer_textures: texture_2d_array<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(102) var layer_sampler: sampler;
+@group(#{MATERIAL_BIND_GROUP}) @binding(103) var splat_0: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(104) var splat_sampler: sampler;
+@group(#{MATERIAL_BIND_GROUP}) @binding(105) var splat_1: texture_2d<f32>;
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    // The splat maps stretch over the cell, like its UVs
+    var weights = array<vec4<f32>, 2>(
+        textureSample(splat_0, splat_sampler, in.uv),
+        textureSample(splat_1, splat_sampler, in.uv),
+    );
+
+    var color = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var i = 0u; i < 8u; i++) {
+        let weight = weights[i / 4u][i % 4u];
+        let uv = in.world_position.xz / params.tile_sizes[i / 4u][i % 4u];
+        color += textureSample(layer_textures, layer_sampler, uv, i).rgb * weight;
+        total += weight;
+    }
+    color /= max(total, 0.0001);
+
+    pbr_input.material.base_color = vec4<f32>(color, 1.0) * pbr_input.material.base_color;
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -13,4 +13,16 @@ impl Colors {
     pub const AXIS_Z_SOFT: Srgba = BLUE_200;
     pub const GRID_MAJOR: Srgba = GRAY_500;
     pub const GRID_MINOR: Srgba = GRAY_600;
+    pub const BRUSH: Srgba = AMBER_300;
+    pub const WATER: Srgba = SKY_500;
+    pub const SPLINE_POINT: Srgba = ORANGE_400;
+    pub const SPLINE_HANDLE: Srgba = ORANGE_200;
+    pub const SELECTED: Srgba = YELLOW_300;
+    pub const OVERLAY_LOW: Srgba = BLUE_600;
+    pub const OVERLAY_MID: Srgba = LIME_400;
+    pub const OVERLAY_HIGH: Srgba = RED_600;
+    /// Stand-ins for splat layers without a texture
+    pub const SPLAT_LAYERS: [Srgba; 8] = [
+        GREEN_700, AMBER_200, STONE_500, SLATE_50, LIME_800, YELLOW_900, ZINC_700, TEAL_600,
+    ];
 }
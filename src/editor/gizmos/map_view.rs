@@ -0,0 +1,48 @@
+use bevy::prelude::*;
+
+use bevy::camera::visibility::RenderLayers;
+use bevy::color::palettes::css::WHITE;
+
+use crate::spline::Spline;
+
+/// Render layer of the map view camera. It draws [MapViewGizmos] only, while the default
+/// gizmo group stays on layer 0 with the 3D viewport.
+pub const MAP_VIEW_LAYER: usize = 1;
+
+/// Gizmos drawn in the map view, placed with [map_position].
+#[derive(Default, Reflect, GizmoConfigGroup)]
+pub struct MapViewGizmos;
+
+pub struct MapViewGizmosPlugin;
+
+impl Plugin for MapViewGizmosPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_gizmo_config(
+            MapViewGizmos,
+            GizmoConfig {
+                render_layers: RenderLayers::layer(MAP_VIEW_LAYER),
+                ..default()
+            },
+        );
+        app.add_systems(Update, draw_splines);
+    }
+}
+
+/// The map view looks straight down, with north (-Z) up
+pub fn map_position(world_pos: Vec3) -> Vec2 {
+    vec2(world_pos.x, -world_pos.z)
+}
+
+/// World XZ under a map view position
+pub fn map_to_world_xz(map_pos: Vec2) -> Vec2 {
+    vec2(map_pos.x, -map_pos.y)
+}
+
+fn draw_splines(query: Query<(&Spline, &Transform)>, mut gizmos: Gizmos<MapViewGizmos>) {
+    for (spline, xform) in &query {
+        gizmos.linestrip_2d(
+            spline.draw_points().map(|p| map_position(*xform * p)),
+            WHITE,
+        );
+    }
+}
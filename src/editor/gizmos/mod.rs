@@ -1,3 +1,9 @@
 mod grid_floor;
+mod map_view;
 
 pub use grid_floor::GridFloorPlugin;
+pub use map_view::MAP_VIEW_LAYER;
+pub use map_view::MapViewGizmos;
+pub use map_view::MapViewGizmosPlugin;
+pub use map_view::map_position;
+pub use map_view::map_to_world_xz;
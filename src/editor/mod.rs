@@ -5,8 +5,17 @@ mod components;
 mod gizmos;
 mod selection;
 mod selection_actions;
+mod spline_draw;
+mod spline_edit;
 mod terrain_cell_preview;
+mod terrain_erosion;
+mod terrain_export;
+mod terrain_overlay;
+mod terrain_raycast;
+mod terrain_sculpt;
+mod terrain_splat;
 mod ui;
+mod water;
 
 pub use selection::Selectable;
 
@@ -14,10 +23,19 @@ use camera_rig_orbital::CameraRigOrbital;
 use camera_rig_topdown::CameraRigTopdown;
 use colors::Colors;
 use gizmos::GridFloorPlugin;
+use gizmos::MapViewGizmosPlugin;
 use selection::SelectionPlugin;
 use selection_actions::SelectionActionsPlugin;
+use spline_draw::SplineDrawPlugin;
+use spline_edit::SplineEditPlugin;
 use terrain_cell_preview::TerrainCellPreviewPlugin;
+use terrain_erosion::TerrainErosionPlugin;
+use terrain_export::TerrainExportPlugin;
+use terrain_overlay::TerrainOverlayPlugin;
+use terrain_sculpt::TerrainSculptPlugin;
+use terrain_splat::TerrainSplatPlugin;
 use ui::EditorGuiPlugin;
+use water::WaterPlugin;
 
 use bevy::app::Plugin;
 
@@ -32,7 +50,17 @@ impl Plugin for EditorPlugin {
         app.add_plugins(CameraRigTopdown);
         app.add_plugins(SelectionPlugin);
         app.add_plugins(SelectionActionsPlugin);
+        app.add_plugins(SplineEditPlugin);
+        app.add_plugins(SplineDrawPlugin);
         app.add_plugins(GridFloorPlugin);
+        app.add_plugins(MapViewGizmosPlugin);
         app.add_plugins(TerrainCellPreviewPlugin);
+        // Reads the crunch config
+        app.add_plugins(TerrainErosionPlugin);
+        app.add_plugins(TerrainExportPlugin);
+        app.add_plugins(TerrainSculptPlugin);
+        app.add_plugins(TerrainOverlayPlugin);
+        app.add_plugins(TerrainSplatPlugin);
+        app.add_plugins(WaterPlugin);
     }
 }
@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 
 use super::selection_actions::SelectionActionState;
+use super::spline_draw::SplineDraw;
+use super::spline_draw::spline_draw_tool;
+use super::ui::hotkeys_enabled;
 
 /// Marker component for selectable entities.
 #[derive(Component, Default)]
@@ -17,7 +20,13 @@ pub struct SelectionPlugin;
 
 impl Plugin for SelectionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, update_selection);
+        // Before the draw tool takes Esc to cancel, so that doesn't clear the selection too
+        app.add_systems(
+            Update,
+            update_selection
+                .run_if(hotkeys_enabled)
+                .before(spline_draw_tool),
+        );
     }
 }
 
@@ -26,8 +35,9 @@ fn update_selection(
     query: Query<(Entity, Option<&Selected>), With<Selectable>>,
     keyb: Res<ButtonInput<KeyCode>>,
     op: Res<SelectionActionState>,
+    draw: Res<SplineDraw>,
 ) {
-    if *op != SelectionActionState::None {
+    if *op != SelectionActionState::None || draw.enabled {
         return;
     }
 
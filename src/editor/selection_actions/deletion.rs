@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 
 use crate::editor::selection::WithSelected;
+use crate::editor::ui::hotkeys_enabled;
 
 use super::SelectionActionState;
 
@@ -8,7 +9,7 @@ pub struct DeletionPlugin;
 
 impl Plugin for DeletionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, update);
+        app.add_systems(Update, update.run_if(hotkeys_enabled));
     }
 }
 
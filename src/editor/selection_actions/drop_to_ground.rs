@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+
+use bevy::input::common_conditions::input_just_pressed;
+
+use crate::editor::selection::WithSelected;
+use crate::editor::terrain_raycast::TerrainRaycast;
+use crate::editor::ui::hotkeys_enabled;
+use crate::editor::water::WaterBody;
+
+use super::SelectionActionState;
+
+/// Only top-level entities, whose Transform is a world position. Water bodies sit at the
+/// terrain origin and follow their shape instead.
+type Droppable = (WithSelected, Without<ChildOf>, Without<WaterBody>);
+
+pub struct DropToGroundPlugin;
+
+impl Plugin for DropToGroundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            drop_selection_to_ground.run_if(input_just_pressed(KeyCode::End).and(hotkeys_enabled)),
+        );
+    }
+}
+
+/// Moves the selected entities straight down (or up) onto the terrain
+pub fn drop_selection_to_ground(
+    raycast: TerrainRaycast,
+    mut selection: Query<&mut Transform, Droppable>,
+    selection_state: Res<SelectionActionState>,
+) {
+    if *selection_state != SelectionActionState::None {
+        return;
+    }
+
+    for mut xform in selection.iter_mut() {
+        if let Some(ground) = raycast.ground_at(xform.translation.xz()) {
+            xform.translation = ground.position;
+        }
+    }
+}
@@ -1,9 +1,11 @@
 mod deletion;
+pub mod drop_to_ground;
 pub mod transform_action;
 
 use bevy::prelude::*;
 
 use deletion::DeletionPlugin;
+use drop_to_ground::DropToGroundPlugin;
 use transform_action::TransformActionsPlugin;
 
 pub struct SelectionActionsPlugin;
@@ -13,6 +15,7 @@ impl Plugin for SelectionActionsPlugin {
         app.insert_resource(SelectionActionState::None);
         app.add_plugins(TransformActionsPlugin);
         app.add_plugins(DeletionPlugin);
+        app.add_plugins(DropToGroundPlugin);
     }
 }
 
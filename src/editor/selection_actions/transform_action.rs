@@ -10,6 +10,8 @@ use crate::editor::Selectable;
 use crate::editor::camera_rig_orbital::CurrentCamera;
 use crate::editor::components::ViewportRenderTarget;
 use crate::editor::selection::WithSelected;
+use crate::editor::terrain_raycast::TerrainRaycast;
+use crate::editor::ui::hotkeys_enabled;
 
 /// Transform operations for selected entities - Move, rotate, scale
 #[derive(Resource, Debug, Default, PartialEq, Clone, Copy)]
@@ -19,6 +21,7 @@ pub enum TransformAction {
     Move {
         axis_lock: AxisLock,
         op_origin: Vec3,
+        snap: SurfaceSnap,
     },
     Rotate {
         axis_lock: AxisLock,
@@ -40,6 +43,28 @@ impl std::fmt::Display for TransformAction {
     }
 }
 
+/// Moving onto the terrain surface, cycled with T during a move
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceSnap {
+    #[default]
+    Off,
+    #[display("Terrain")]
+    Surface,
+    /// Up axis follows the terrain normal
+    #[display("Terrain, aligned")]
+    Aligned,
+}
+
+impl SurfaceSnap {
+    fn next(self) -> Self {
+        match self {
+            SurfaceSnap::Off => SurfaceSnap::Surface,
+            SurfaceSnap::Surface => SurfaceSnap::Aligned,
+            SurfaceSnap::Aligned => SurfaceSnap::Off,
+        }
+    }
+}
+
 #[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
 pub enum AxisLock {
     #[default]
@@ -69,11 +94,14 @@ pub struct TransformActionsPlugin;
 impl Plugin for TransformActionsPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(TransformAction::default());
-        app.add_systems(Update, (op_switcher, op_runner).chain());
+        app.add_systems(
+            Update,
+            (op_switcher.run_if(hotkeys_enabled), op_runner).chain(),
+        );
     }
 }
 
-fn op_switcher(
+pub(crate) fn op_switcher(
     mut commands: Commands,
     mut op: ResMut<TransformAction>,
     mut selection: Query<QXformOpPossible, WithSelected>,
@@ -118,6 +146,7 @@ fn op_switcher(
         *op = TransformAction::Move {
             axis_lock: AxisLock::default(),
             op_origin: selection_bb_center(&selection),
+            snap: SurfaceSnap::default(),
         };
     } else if kb.just_pressed(KeyCode::KeyR) {
         let Some(original_cursor_pos) = window.cursor_position() else {
@@ -137,6 +166,11 @@ fn op_switcher(
     }
 
     update_axis_lock(&mut op, &kb);
+    if let TransformAction::Move { snap, .. } = op.as_mut()
+        && kb.just_pressed(KeyCode::KeyT)
+    {
+        *snap = snap.next();
+    }
 }
 
 fn op_runner(
@@ -144,6 +178,7 @@ fn op_runner(
     q_selection: Query<QXformOp, WithSelected>,
     q_camera: Query<(&Camera, &Transform, &GlobalTransform, &ViewportRenderTarget), WithCurrentCam>,
     q_windows: Query<&Window, With<PrimaryWindow>>,
+    raycast: TerrainRaycast,
     mut gizmos: Gizmos,
 ) {
     let Ok(window) = q_windows.single() else {
@@ -160,6 +195,7 @@ fn op_runner(
             TransformAction::Move {
                 axis_lock,
                 op_origin,
+                ..
             }
             | TransformAction::Rotate {
                 axis_lock,
@@ -174,6 +210,7 @@ fn op_runner(
             TransformAction::Move {
                 axis_lock,
                 op_origin: original_pos,
+                snap,
             } => op_move(
                 q_selection,
                 camera,
@@ -182,6 +219,8 @@ fn op_runner(
                 original_pos,
                 cursor_pos,
                 axis_lock,
+                *snap,
+                &raycast,
                 gizmos,
             ),
             TransformAction::Rotate {
@@ -342,6 +381,8 @@ fn op_move(
     original_pos: &Vec3,
     cursor_pos: Vec2,
     axis_lock: &AxisLock,
+    snap: SurfaceSnap,
+    raycast: &TerrainRaycast,
     mut gizmos: Gizmos,
 ) {
     gizmos.circle(Isometry3d::from_translation(*original_pos), 0.5, CYAN_100);
@@ -357,12 +398,20 @@ fn op_move(
         AxisLock::PlaneZ => Dir3::Z.as_vec3(),
     };
     let plane = InfinitePlane3d::new(axis);
-    let Some(pos) = plane_line_intersect(cursor_pos, camera, camera_global, &plane, original_pos)
+    // Unlocked snapping follows the terrain under the cursor, not the plane
+    let terrain_pos = match (snap, axis_lock) {
+        (SurfaceSnap::Surface | SurfaceSnap::Aligned, AxisLock::Free) => {
+            raycast.cursor_hit().map(|hit| hit.position)
+        }
+        _ => None,
+    };
+    let Some(pos) = terrain_pos
+        .or_else(|| plane_line_intersect(cursor_pos, camera, camera_global, &plane, original_pos))
     else {
         return;
     };
 
-    for (mut xform, ..) in q_selection.iter_mut() {
+    for (mut xform, og_xform) in q_selection.iter_mut() {
         match axis_lock {
             AxisLock::X => xform.translation.x = pos.x,
             AxisLock::Y => xform.translation.y = pos.y,
@@ -371,6 +420,19 @@ fn op_move(
                 xform.translation = pos;
             }
         }
+
+        xform.rotation = og_xform.0.rotation;
+        // Locked to Y moves off the ground on purpose
+        if snap == SurfaceSnap::Off || *axis_lock == AxisLock::Y {
+            continue;
+        }
+        let Some(ground) = raycast.ground_at(xform.translation.xz()) else {
+            continue;
+        };
+        xform.translation = ground.position;
+        if snap == SurfaceSnap::Aligned {
+            xform.rotation = Quat::from_rotation_arc(Vec3::Y, ground.normal) * xform.rotation;
+        }
     }
 
     gizmos.circle(Isometry3d::from_translation(pos), 0.5, RED_100);
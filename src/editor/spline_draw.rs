@@ -0,0 +1,191 @@
+use bevy::prelude::*;
+
+use bevy::window::PrimaryWindow;
+
+use crate::editor::Colors;
+use crate::editor::Selectable;
+use crate::editor::components::ViewportRenderTarget;
+use crate::editor::gizmos::MapViewGizmos;
+use crate::editor::gizmos::map_position;
+use crate::editor::gizmos::map_to_world_xz;
+use crate::editor::selection_actions::SelectionActionState;
+use crate::editor::terrain_raycast::TerrainRaycast;
+use crate::editor::terrain_sculpt::TerrainSculpt;
+use crate::editor::terrain_splat::TerrainPaint;
+use crate::editor::ui::hotkeys_enabled;
+use crate::editor::water::TerrainWater;
+use crate::spline::ControlPoint;
+use crate::spline::Spline;
+use crate::spline::SplineKind;
+
+/// Radius of the marker under the cursor
+const CURSOR_RADIUS: f32 = 0.5;
+
+pub struct SplineDrawPlugin;
+
+impl Plugin for SplineDrawPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SplineDraw>();
+        app.add_systems(
+            Update,
+            (toggle_spline_draw, spline_draw_tool)
+                .chain()
+                .run_if(hotkeys_enabled),
+        );
+    }
+}
+
+/// Draw spline tool: LMB appends a point on the terrain, Backspace removes the last one,
+/// Enter makes the spline and Esc cancels.
+#[derive(Resource, Debug, Default)]
+pub struct SplineDraw {
+    pub enabled: bool,
+    /// Kind of the spline made on Enter
+    pub kind: SplineKind,
+    /// World space, in the order they were placed
+    points: Vec<Vec3>,
+}
+
+impl SplineDraw {
+    pub fn num_points(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn cancel(&mut self) {
+        self.enabled = false;
+        self.points.clear();
+    }
+
+    /// Curve through the placed points, at the first one
+    fn spline(&self) -> Option<(Spline, Transform)> {
+        let origin = *self.points.first()?;
+        let points = self
+            .points
+            .iter()
+            .map(|&p| ControlPoint::new(p - origin))
+            .collect();
+        // Converting from Catmull-Rom gives smooth tangents, and B-splines through the points
+        let mut spline = Spline::new(SplineKind::CatmullRom, points)?;
+        spline.set_kind(self.kind);
+        Some((spline, Transform::from_translation(origin)))
+    }
+}
+
+fn toggle_spline_draw(
+    mut draw: ResMut<SplineDraw>,
+    mut sculpt: ResMut<TerrainSculpt>,
+    mut paint: ResMut<TerrainPaint>,
+    mut water: ResMut<TerrainWater>,
+    selection_state: Res<SelectionActionState>,
+    kb: Res<ButtonInput<KeyCode>>,
+) {
+    if *selection_state != SelectionActionState::None || !kb.just_pressed(KeyCode::KeyN) {
+        return;
+    }
+    if draw.enabled {
+        draw.cancel();
+        return;
+    }
+    draw.enabled = true;
+    // One tool at a time
+    sculpt.enabled = false;
+    paint.enabled = false;
+    water.flood_fill = false;
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn spline_draw_tool(
+    mut commands: Commands,
+    mut draw: ResMut<SplineDraw>,
+    sculpt: Res<TerrainSculpt>,
+    paint: Res<TerrainPaint>,
+    water: Res<TerrainWater>,
+    raycast: TerrainRaycast,
+    q_map_camera: Query<(&Camera, &GlobalTransform, &ViewportRenderTarget), With<Camera2d>>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    selection_state: Res<SelectionActionState>,
+    mb: Res<ButtonInput<MouseButton>>,
+    kb: Res<ButtonInput<KeyCode>>,
+    mut gizmos: Gizmos,
+    mut map_gizmos: Gizmos<MapViewGizmos>,
+) {
+    if !draw.enabled
+        || sculpt.enabled
+        || paint.enabled
+        || water.flood_fill
+        || *selection_state != SelectionActionState::None
+    {
+        return;
+    }
+
+    if kb.just_pressed(KeyCode::Escape) {
+        draw.cancel();
+        return;
+    }
+    if kb.just_pressed(KeyCode::Backspace) {
+        draw.points.pop();
+    }
+    if kb.just_pressed(KeyCode::Enter) {
+        match draw.spline() {
+            Some((spline, xform)) => {
+                commands.spawn((Name::new("Spline"), spline, xform, Selectable));
+                draw.cancel();
+            }
+            None => info!("A {} needs more points", draw.kind),
+        }
+        return;
+    }
+
+    let cursor_pos = match q_windows.single() {
+        Ok(window) => cursor_position(&raycast, &q_map_camera, window),
+        Err(_) => None,
+    };
+    if let Some(pos) = cursor_pos
+        && mb.just_pressed(MouseButton::Left)
+    {
+        draw.points.push(pos);
+    }
+
+    let preview: Vec<Vec3> = draw.points.iter().copied().chain(cursor_pos).collect();
+    gizmos.linestrip(preview.iter().copied(), Colors::SPLINE_POINT);
+    map_gizmos.linestrip_2d(
+        preview.iter().map(|p| map_position(*p)),
+        Colors::SPLINE_POINT,
+    );
+    if let Some(pos) = cursor_pos {
+        gizmos.sphere(
+            Isometry3d::from_translation(pos),
+            CURSOR_RADIUS,
+            Colors::BRUSH,
+        );
+        map_gizmos.circle_2d(map_position(pos), CURSOR_RADIUS, Colors::BRUSH);
+    }
+}
+
+/// Where a click would place a point: on the terrain, or the grid plane without one
+fn cursor_position(
+    raycast: &TerrainRaycast,
+    q_map_camera: &Query<(&Camera, &GlobalTransform, &ViewportRenderTarget), With<Camera2d>>,
+    window: &Window,
+) -> Option<Vec3> {
+    let map_pos = q_map_camera
+        .iter()
+        .find_map(|(camera, camera_global, render_target)| {
+            let cursor_pos = render_target.cursor_position(window)?;
+            camera.viewport_to_world_2d(camera_global, cursor_pos).ok()
+        });
+    if let Some(map_pos) = map_pos {
+        let xz = map_to_world_xz(map_pos);
+        let height = raycast
+            .ground_at(xz)
+            .map_or(0.0, |ground| ground.position.y);
+        return Some(vec3(xz.x, height, xz.y));
+    }
+
+    let ray = raycast.cursor_ray()?;
+    if let Some(hit) = raycast.cast_ray(ray) {
+        return Some(hit.position);
+    }
+    let distance = ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))?;
+    Some(ray.get_point(distance))
+}
@@ -0,0 +1,311 @@
+use bevy::prelude::*;
+
+use bevy::window::PrimaryWindow;
+
+use crate::editor::Colors;
+use crate::editor::Selectable;
+use crate::editor::camera_rig_orbital::CurrentCamera;
+use crate::editor::components::ViewportRenderTarget;
+use crate::editor::selection::Selected;
+use crate::editor::selection_actions::SelectionActionState;
+use crate::editor::selection_actions::transform_action::op_switcher;
+use crate::editor::spline_draw::SplineDraw;
+use crate::editor::terrain_sculpt::TerrainSculpt;
+use crate::editor::terrain_splat::TerrainPaint;
+use crate::editor::water::TerrainWater;
+use crate::spline::ControlPoint;
+use crate::spline::Spline;
+use crate::spline::SplineKind;
+
+/// Clicks this many pixels from a handle pick it
+const PICK_RADIUS: f32 = 12.0;
+/// Handle size relative to its distance from the camera
+const HANDLE_SCALE: f32 = 0.008;
+/// Handles and points closer than this are in sync
+const SYNC_EPSILON: f32 = 1e-4;
+
+type SplineOrTransformChanged = Or<(Changed<Spline>, Changed<Transform>)>;
+
+pub struct SplineEditPlugin;
+
+impl Plugin for SplineEditPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, pick_handles.before(op_switcher));
+        app.add_systems(
+            Update,
+            (
+                spawn_handles,
+                handles_to_splines,
+                splines_to_handles,
+                draw_handles,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Which part of a control point a handle entity moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HandleKind {
+    Point,
+    In,
+    Out,
+}
+
+/// Selectable stand-in for one control point or tangent handle of a spline. Its
+/// translation is in world space, so the regular transform actions move it.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplineHandle {
+    pub index: usize,
+    pub kind: HandleKind,
+}
+
+impl SplineHandle {
+    /// Spline space
+    fn position(&self, point: &ControlPoint) -> Vec3 {
+        match self.kind {
+            HandleKind::Point => point.position,
+            HandleKind::In => point.position + point.handle_in,
+            HandleKind::Out => point.position + point.handle_out,
+        }
+    }
+
+    /// `point` with this handle at `position`, in spline space
+    fn moved(&self, point: &ControlPoint, position: Vec3) -> ControlPoint {
+        match self.kind {
+            // Tangents are relative, so they come along
+            HandleKind::Point => ControlPoint { position, ..*point },
+            HandleKind::In => ControlPoint {
+                handle_in: position - point.position,
+                ..*point
+            },
+            HandleKind::Out => ControlPoint {
+                handle_out: position - point.position,
+                ..*point
+            },
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+#[relationship(relationship_target = SplineHandles)]
+pub struct SplineHandleOf(pub Entity);
+
+/// Handle entities of a spline, despawned along with it.
+#[derive(Component, Debug)]
+#[relationship_target(relationship = SplineHandleOf, linked_spawn)]
+pub struct SplineHandles(Vec<Entity>);
+
+/// Tangent handles only where the spline kind uses them. Bezier end points only get the
+/// one that shapes the curve.
+fn handles_for(spline: &Spline) -> Vec<SplineHandle> {
+    let last = spline.points().len() - 1;
+    (0..=last)
+        .flat_map(|index| {
+            let (tangent_in, tangent_out) = match spline.kind() {
+                SplineKind::Bezier => (index > 0, index < last),
+                SplineKind::Hermite => (false, true),
+                SplineKind::CatmullRom | SplineKind::BSpline | SplineKind::Polyline => {
+                    (false, false)
+                }
+            };
+            [
+                Some(HandleKind::Point),
+                tangent_in.then_some(HandleKind::In),
+                tangent_out.then_some(HandleKind::Out),
+            ]
+            .into_iter()
+            .flatten()
+            .map(move |kind| SplineHandle { index, kind })
+        })
+        .collect()
+}
+
+/// (Re)spawns handles when a spline has none, or its points or kind changed.
+fn spawn_handles(
+    mut commands: Commands,
+    q_splines: Query<(Entity, &Spline, &Transform, Option<&SplineHandles>)>,
+    q_handles: Query<&SplineHandle>,
+) {
+    for (entity, spline, xform, handles) in q_splines.iter() {
+        let expected = handles_for(spline);
+        let current: Vec<_> = handles
+            .iter()
+            .flat_map(|handles| q_handles.iter_many(&handles.0))
+            .copied()
+            .collect();
+        if current == expected {
+            continue;
+        }
+        for &handle in handles.iter().flat_map(|handles| &handles.0) {
+            commands.entity(handle).despawn();
+        }
+
+        for handle in expected {
+            let point = &spline.points()[handle.index];
+            let name = match handle.kind {
+                HandleKind::Point => format!("Spline point {}", handle.index),
+                kind => format!("Spline handle {} {kind:?}", handle.index),
+            };
+            commands.spawn((
+                Name::new(name),
+                handle,
+                SplineHandleOf(entity),
+                Transform::from_translation(*xform * handle.position(point)),
+                Selectable,
+            ));
+        }
+    }
+}
+
+/// Writes moved handles back into their spline's points
+fn handles_to_splines(
+    q_handles: Query<(&SplineHandle, &SplineHandleOf, &Transform), Changed<Transform>>,
+    mut q_splines: Query<(&mut Spline, &Transform), Without<SplineHandle>>,
+) {
+    // Points first, so tangents moved along with their point stay put relative to it
+    let mut moved: Vec<_> = q_handles.iter().collect();
+    moved.sort_by_key(|(handle, ..)| handle.kind);
+
+    for (handle, SplineHandleOf(spline_entity), handle_xform) in moved {
+        let Ok((mut spline, xform)) = q_splines.get_mut(*spline_entity) else {
+            continue;
+        };
+        let Some(point) = spline.points().get(handle.index).copied() else {
+            continue;
+        };
+        let local = xform
+            .compute_affine()
+            .inverse()
+            .transform_point3(handle_xform.translation);
+        if !local.abs_diff_eq(handle.position(&point), SYNC_EPSILON) {
+            spline.set_point(handle.index, handle.moved(&point, local));
+        }
+    }
+}
+
+/// Keeps handles on their points when the spline moves or changes
+fn splines_to_handles(
+    q_splines: Query<(&Spline, &Transform, &SplineHandles), SplineOrTransformChanged>,
+    mut q_handles: Query<(&SplineHandle, &mut Transform), Without<Spline>>,
+) {
+    for (spline, xform, handles) in q_splines.iter() {
+        for &entity in &handles.0 {
+            let Ok((handle, mut handle_xform)) = q_handles.get_mut(entity) else {
+                continue;
+            };
+            let Some(point) = spline.points().get(handle.index) else {
+                continue;
+            };
+            let position = *xform * handle.position(point);
+            if !handle_xform.translation.abs_diff_eq(position, SYNC_EPSILON) {
+                handle_xform.translation = position;
+            }
+        }
+    }
+}
+
+/// Selects the handle under the cursor. Shift adds to the selection.
+#[allow(clippy::too_many_arguments)]
+fn pick_handles(
+    mut commands: Commands,
+    q_handles: Query<(Entity, &Transform), With<SplineHandle>>,
+    q_selected: Query<Entity, With<Selected>>,
+    q_camera: Query<(&Camera, &GlobalTransform, &ViewportRenderTarget), With<CurrentCamera>>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    selection_state: Res<SelectionActionState>,
+    sculpt: Res<TerrainSculpt>,
+    paint: Res<TerrainPaint>,
+    water: Res<TerrainWater>,
+    draw: Res<SplineDraw>,
+    mb: Res<ButtonInput<MouseButton>>,
+    kb: Res<ButtonInput<KeyCode>>,
+) {
+    if !mb.just_pressed(MouseButton::Left)
+        || *selection_state != SelectionActionState::None
+        || sculpt.enabled
+        || paint.enabled
+        || water.flood_fill
+        || draw.enabled
+    {
+        return;
+    }
+    let Ok(window) = q_windows.single() else {
+        return;
+    };
+    let Some((camera, camera_global, cursor_pos)) =
+        q_camera
+            .iter()
+            .find_map(|(camera, camera_global, render_target)| {
+                let cursor_pos = render_target.cursor_position(window)?;
+                Some((camera, camera_global, cursor_pos))
+            })
+    else {
+        return;
+    };
+
+    let picked = q_handles
+        .iter()
+        .filter_map(|(entity, xform)| {
+            let screen_pos = camera
+                .world_to_viewport(camera_global, xform.translation)
+                .ok()?;
+            Some((entity, screen_pos.distance(cursor_pos)))
+        })
+        .filter(|(_, distance)| *distance < PICK_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    let Some((picked, _)) = picked else {
+        return;
+    };
+
+    if !kb.pressed(KeyCode::ShiftLeft) {
+        for entity in q_selected.iter() {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+    commands.entity(picked).insert(Selected);
+}
+
+fn draw_handles(
+    q_handles: Query<(&SplineHandle, &SplineHandleOf, &Transform, Has<Selected>)>,
+    q_splines: Query<(&Spline, &Transform), Without<SplineHandle>>,
+    q_camera: Query<&GlobalTransform, With<CurrentCamera>>,
+    mut gizmos: Gizmos,
+) {
+    for (handle, SplineHandleOf(spline_entity), xform, selected) in q_handles.iter() {
+        let position = xform.translation;
+        let Some(distance) = q_camera
+            .iter()
+            .map(|cam_xform| cam_xform.translation().distance(position))
+            .reduce(f32::min)
+        else {
+            return;
+        };
+
+        let (color, radius) = match handle.kind {
+            HandleKind::Point => (Colors::SPLINE_POINT, distance * HANDLE_SCALE),
+            HandleKind::In | HandleKind::Out => {
+                (Colors::SPLINE_HANDLE, distance * HANDLE_SCALE * 0.6)
+            }
+        };
+        let color = match selected {
+            true => Colors::SELECTED,
+            false => color,
+        };
+        gizmos.sphere(Isometry3d::from_translation(position), radius, color);
+
+        if handle.kind == HandleKind::Point {
+            continue;
+        }
+        let Ok((spline, spline_xform)) = q_splines.get(*spline_entity) else {
+            continue;
+        };
+        if let Some(point) = spline.points().get(handle.index) {
+            gizmos.line(
+                *spline_xform * point.position,
+                position,
+                Colors::SPLINE_HANDLE,
+            );
+        }
+    }
+}
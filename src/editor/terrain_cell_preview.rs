@@ -1,53 +1,368 @@
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 
 use bevy::prelude::*;
 
 use bevy::pbr::wireframe::Wireframe;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::tasks::futures::check_ready;
 
+use worldedit::terrain_processing::CacheCellEntry;
+use worldedit::terrain_processing::HeightmapBundle;
+use worldedit::terrain_processing::PROJECT_DIR;
 use worldedit::terrain_processing::TerrainMesh;
-use worldedit::terrain_processing::terrain_cruncher;
+use worldedit::terrain_processing::WorldSettings;
+use worldedit::terrain_processing::erosion::ErosionSettings;
+use worldedit::terrain_processing::generator::NoiseSettings;
+use worldedit::terrain_processing::heightmap_import::HeightmapSource;
+use worldedit::terrain_processing::terrain_cruncher::CrunchConfig;
+use worldedit::terrain_processing::terrain_cruncher::CrunchError;
+use worldedit::terrain_processing::terrain_cruncher::CrunchPlan;
+use worldedit::terrain_processing::terrain_cruncher::CrunchedCell;
 
-use worldedit::terrain_processing::CACHE_DIR;
-use worldedit::terrain_processing::NUM_CELLS;
-use worldedit::terrain_processing::WORLD_SIZE;
+use crate::editor::camera_rig_orbital::CurrentCamera;
+use crate::editor::terrain_sculpt::SCULPT_LAYER_FILE;
+
+/// Distance at which cells drop from LOD 0 to 1. Each following level doubles it.
+const LOD_BASE_DISTANCE: f32 = 384.0;
 
 pub struct TerrainCellPreviewPlugin;
 
 impl Plugin for TerrainCellPreviewPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup);
+        let source = load_project_file(HeightmapSource::FILE_NAME, HeightmapSource::load)
+            .unwrap_or_default();
+        app.insert_resource(TerrainCrunchConfig(CrunchConfig {
+            source_path: source.path,
+            import: source.import,
+            world: load_project_file(WorldSettings::FILE_NAME, WorldSettings::load)
+                .unwrap_or_default(),
+            generator: load_project_file(NoiseSettings::FILE_NAME, NoiseSettings::load),
+            erosion: load_project_file(ErosionSettings::FILE_NAME, ErosionSettings::load),
+            layers: vec![Path::new(PROJECT_DIR).join(SCULPT_LAYER_FILE)],
+            ..default()
+        }));
+        app.init_resource::<TerrainPreviewStatus>();
+        app.init_resource::<TerrainCrunchProgress>();
+        app.add_systems(
+            Update,
+            (
+                start_crunch.run_if(resource_changed::<TerrainCrunchConfig>),
+                poll_prepare_task,
+                poll_cell_tasks,
+                update_cell_lods,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Crunch settings used for the terrain preview. Changing them crunches the terrain again.
+#[derive(Resource, Debug)]
+pub struct TerrainCrunchConfig(pub CrunchConfig);
+
+/// Last terrain preview error, shown in the editor.
+#[derive(Resource, Debug, Default)]
+pub struct TerrainPreviewStatus {
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CrunchStage {
+    #[default]
+    Idle,
+    /// Reading the source heightmap
+    Preparing,
+    /// Eroding the source heightmap
+    Eroding,
+    /// Cell tasks are running
+    Crunching,
+    Done,
+    Failed,
+}
+
+/// Progress of the background terrain crunch.
+#[derive(Resource, Debug, Default)]
+pub struct TerrainCrunchProgress {
+    pub stage: CrunchStage,
+    pub num_cells: u32,
+    pub num_done: u32,
+    pub num_regenerated: u32,
+    /// Finished erosion iterations, while [CrunchStage::Eroding]
+    pub iteration: u32,
+    pub num_iterations: u32,
+}
+
+impl TerrainCrunchProgress {
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self.stage,
+            CrunchStage::Preparing | CrunchStage::Eroding | CrunchStage::Crunching
+        )
+    }
+
+    /// 0.0 - 1.0
+    pub fn fraction(&self) -> f32 {
+        let (done, total) = match self.stage {
+            CrunchStage::Eroding => (self.iteration, self.num_iterations),
+            _ => (self.num_done, self.num_cells),
+        };
+        if total == 0 {
+            return 0.0;
+        }
+        done as f32 / total as f32
+    }
+}
+
+/// Heightmap the terrain cells were built from. Available once the crunch is done.
+#[derive(Resource, Debug)]
+pub struct TerrainHeightmap(pub HeightmapBundle);
+
+/// The last finished crunch, for reading derived maps from its cache.
+#[derive(Resource)]
+pub struct TerrainCrunchPlan(pub Arc<CrunchPlan>);
+
+/// A spawned terrain cell.
+#[derive(Component, Debug)]
+pub struct TerrainCell {
+    /// Bounding box in cell mesh space
+    pub bounds: (Vec3, Vec3),
+    /// Meshes for each LOD level, most detailed first
+    pub lods: Vec<Handle<Mesh>>,
+    pub mesh: TerrainMesh,
+}
+
+/// Where the heightmap's origin ends up in the scene. The terrain is centred on the scene
+/// origin.
+pub fn terrain_origin(world: &WorldSettings) -> Vec3 {
+    let half_size = world.world_size as f32 / 2.0;
+    vec3(-half_size, 0.0, -half_size)
+}
+
+/// Erosion iterations done so far, and the plan
+#[derive(Component)]
+struct PrepareTask(Arc<AtomicU32>, Task<Result<CrunchPlan, CrunchError>>);
+
+/// Crunched cell and its LOD meshes
+type CellTaskOutput = Result<(CrunchedCell, Vec<Mesh>), CrunchError>;
+
+#[derive(Component)]
+struct CellTask(Task<CellTaskOutput>);
+
+/// Any task of a running crunch
+type IsCrunchTask = Or<(With<PrepareTask>, With<CellTask>)>;
+
+/// The crunch in progress. Removed once all cells are done.
+#[derive(Resource)]
+struct ActiveCrunch {
+    plan: Arc<CrunchPlan>,
+    entries: Vec<Option<CacheCellEntry>>,
+    failed: bool,
+}
+
+/// Reads a project settings file, `None` if it is missing or broken.
+pub fn load_project_file<T>(name: &str, load: fn(&Path) -> std::io::Result<T>) -> Option<T> {
+    let path = Path::new(PROJECT_DIR).join(name);
+    match load(&path) {
+        Ok(value) => Some(value),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("Ignoring '{}': {e}", path.display());
+            None
+        }
+    }
+}
+
+fn start_crunch(
+    mut commands: Commands,
+    config: Res<TerrainCrunchConfig>,
+    mut progress: ResMut<TerrainCrunchProgress>,
+    mut status: ResMut<TerrainPreviewStatus>,
+    q_cells: Query<Entity, With<TerrainCell>>,
+    q_tasks: Query<Entity, IsCrunchTask>,
+) {
+    // Replaces the previous terrain, and any crunch still running, so no cells from the old
+    // plan turn up later
+    for entity in q_cells.iter().chain(q_tasks.iter()) {
+        commands.entity(entity).despawn();
     }
+    commands.remove_resource::<TerrainHeightmap>();
+    commands.remove_resource::<TerrainCrunchPlan>();
+    commands.remove_resource::<ActiveCrunch>();
+    status.error = None;
+
+    let config = config.0.clone();
+    let num_iterations = config.erosion.map_or(0, |erosion| erosion.iterations);
+    let iteration = Arc::new(AtomicU32::new(0));
+    let task_iteration = iteration.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        CrunchPlan::prepare_with_progress(config, |i| task_iteration.store(i, Ordering::Relaxed))
+    });
+    commands.spawn(PrepareTask(iteration, task));
+
+    *progress = TerrainCrunchProgress {
+        stage: CrunchStage::Preparing,
+        num_iterations,
+        ..default()
+    };
 }
 
-fn setup(
+fn poll_prepare_task(
     mut commands: Commands,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut q_tasks: Query<(Entity, &mut PrepareTask)>,
+    mut progress: ResMut<TerrainCrunchProgress>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let iteration = task.0.load(Ordering::Relaxed);
+        if iteration > 0 {
+            progress.stage = CrunchStage::Eroding;
+            progress.iteration = iteration;
+        }
+        let Some(result) = check_ready(&mut task.1) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+
+        let plan = match result {
+            Ok(plan) => Arc::new(plan),
+            Err(e) => {
+                error!("Terrain crunch failed: {e}");
+                status.error = Some(e.to_string());
+                progress.stage = CrunchStage::Failed;
+                continue;
+            }
+        };
+
+        let pool = AsyncComputeTaskPool::get();
+        for i in 0..plan.num_cells() {
+            let plan = plan.clone();
+            let task = pool.spawn(async move {
+                let cell = plan.crunch_cell(i)?;
+                let lods = (0..cell.mesh.num_lods())
+                    .map(|lod| cell.mesh.bevy_mesh_lod(lod))
+                    .collect();
+                Ok((cell, lods))
+            });
+            commands.spawn(CellTask(task));
+        }
+
+        *progress = TerrainCrunchProgress {
+            stage: CrunchStage::Crunching,
+            num_cells: plan.num_cells(),
+            ..default()
+        };
+        commands.insert_resource(ActiveCrunch {
+            entries: vec![None; plan.num_cells() as usize],
+            plan,
+            failed: false,
+        });
+    }
+}
+
+fn poll_cell_tasks(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut CellTask)>,
     mut meshes: ResMut<Assets<Mesh>>,
+    active: Option<ResMut<ActiveCrunch>>,
+    mut progress: ResMut<TerrainCrunchProgress>,
+    mut status: ResMut<TerrainPreviewStatus>,
 ) {
-    terrain_cruncher::crunch_terrain();
+    let Some(mut active) = active else {
+        return;
+    };
+    let origin = terrain_origin(&active.plan.config().world);
+
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let Some(result) = check_ready(&mut task.0) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        progress.num_done += 1;
 
-    for i in 0..NUM_CELLS {
-        let cell_name = format!("cell_{i:03}");
-        let cell_path = PathBuf::from(CACHE_DIR)
-            .join(&cell_name)
-            .with_extension(TerrainMesh::FILE_EXT);
-        let cell = TerrainMesh::load(&cell_path).unwrap();
+        let (cell, lod_meshes) = match result {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Terrain crunch failed: {e}");
+                status.error = Some(e.to_string());
+                active.failed = true;
+                continue;
+            }
+        };
 
-        let mesh = meshes.add(cell.bevy_mesh());
+        if cell.regenerated {
+            progress.num_regenerated += 1;
+        }
+        active.entries[cell.index as usize] = Some(cell.entry);
+
+        let lods: Vec<_> = lod_meshes.into_iter().map(|m| meshes.add(m)).collect();
         commands.spawn((
-            Mesh3d(mesh),
-            MeshMaterial3d(materials.add(StandardMaterial {
-                base_color: Color::WHITE,
-                //base_color_texture: Some(cell_diff),
-                //unlit: true,
-                ..default()
-            })),
-            Transform::default().with_translation(vec3(
-                -(WORLD_SIZE as f32 / 2.0),
-                0.,
-                -(WORLD_SIZE as f32 / 2.0),
-            )),
+            Mesh3d(lods[0].clone()),
+            TerrainCell {
+                bounds: cell.mesh.bounds(),
+                lods,
+                mesh: cell.mesh,
+            },
+            Transform::default().with_translation(origin),
             Wireframe,
         ));
     }
+
+    if progress.num_done < progress.num_cells {
+        return;
+    }
+
+    commands.remove_resource::<ActiveCrunch>();
+    if active.failed {
+        progress.stage = CrunchStage::Failed;
+        return;
+    }
+
+    let entries = active.entries.iter().flatten().copied().collect();
+    if let Err(e) = active.plan.finish(entries) {
+        error!("Terrain crunch failed: {e}");
+        status.error = Some(e.to_string());
+        progress.stage = CrunchStage::Failed;
+        return;
+    }
+
+    info!(
+        "Terrain cache: regenerated {}/{} cells",
+        progress.num_regenerated, progress.num_cells
+    );
+    progress.stage = CrunchStage::Done;
+    commands.insert_resource(TerrainHeightmap(active.plan.heightmap_bundle().clone()));
+    commands.insert_resource(TerrainCrunchPlan(active.plan.clone()));
+}
+
+/// Picks each cell's LOD by its distance to the nearest scene camera.
+fn update_cell_lods(
+    q_camera: Query<&GlobalTransform, With<CurrentCamera>>,
+    mut q_cells: Query<(&TerrainCell, &GlobalTransform, &mut Mesh3d)>,
+) {
+    for (cell, cell_xform, mut mesh) in q_cells.iter_mut() {
+        let (min, max) = cell.bounds;
+        let min = cell_xform.transform_point(min);
+        let max = cell_xform.transform_point(max);
+
+        let Some(distance) = q_camera
+            .iter()
+            .map(|cam_xform| {
+                let pos = cam_xform.translation();
+                pos.distance(pos.clamp(min, max))
+            })
+            .reduce(f32::min)
+        else {
+            return;
+        };
+
+        let lod = (distance / LOD_BASE_DISTANCE).max(1.0).log2().floor() as usize;
+        let handle = &cell.lods[lod.min(cell.lods.len() - 1)];
+        if mesh.0 != *handle {
+            mesh.0 = handle.clone();
+        }
+    }
 }
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use worldedit::terrain_processing::PROJECT_DIR;
+use worldedit::terrain_processing::erosion::ErosionSettings;
+
+use crate::editor::terrain_cell_preview::TerrainCrunchConfig;
+use crate::editor::terrain_cell_preview::TerrainPreviewStatus;
+
+pub struct TerrainErosionPlugin;
+
+impl Plugin for TerrainErosionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainErosion>();
+        app.add_systems(Update, apply_erosion);
+    }
+}
+
+/// Erosion settings being edited. Applying them saves them to the project and crunches the
+/// terrain again.
+#[derive(Resource, Debug)]
+pub struct TerrainErosion {
+    pub enabled: bool,
+    /// Kept while erosion is off, so turning it back on restores them
+    pub settings: ErosionSettings,
+    /// Set to apply once, cleared when applied
+    pub requested: bool,
+}
+
+impl FromWorld for TerrainErosion {
+    fn from_world(world: &mut World) -> Self {
+        let erosion = world
+            .get_resource::<TerrainCrunchConfig>()
+            .and_then(|config| config.0.erosion);
+        Self {
+            enabled: erosion.is_some(),
+            settings: erosion.unwrap_or_default(),
+            requested: false,
+        }
+    }
+}
+
+impl TerrainErosion {
+    /// What the crunch config gets when applied
+    pub fn crunch_settings(&self) -> Option<ErosionSettings> {
+        self.enabled.then_some(self.settings)
+    }
+}
+
+fn apply_erosion(
+    mut erosion: ResMut<TerrainErosion>,
+    mut config: ResMut<TerrainCrunchConfig>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    if !erosion.requested {
+        return;
+    }
+    erosion.requested = false;
+
+    let settings = erosion.crunch_settings();
+    if let Some(settings) = &settings
+        && let Err(e) = settings.validate()
+    {
+        status.error = Some(e);
+        return;
+    }
+
+    // No file means no erosion
+    let path = Path::new(PROJECT_DIR).join(ErosionSettings::FILE_NAME);
+    let saved = match &settings {
+        Some(settings) => settings.save(&path),
+        None => std::fs::remove_file(&path).or_else(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        }),
+    };
+    if let Err(e) = saved {
+        error!("Failed to save '{}': {e}", path.display());
+        status.error = Some(e.to_string());
+    }
+
+    if config.0.erosion != settings {
+        config.0.erosion = settings;
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::tasks::futures::check_ready;
+
+use worldedit::terrain_processing::PROJECT_DIR;
+use worldedit::terrain_processing::heightmap_export;
+use worldedit::terrain_processing::heightmap_export::ExportError;
+use worldedit::terrain_processing::heightmap_export::ExportFormat;
+use worldedit::terrain_processing::heightmap_export::ExportMetadata;
+
+use crate::editor::terrain_cell_preview::TerrainHeightmap;
+use crate::editor::terrain_cell_preview::TerrainPreviewStatus;
+
+/// Exported heightmaps go here, next to their sidecars
+const EXPORT_DIR: &str = "export";
+
+pub struct TerrainExportPlugin;
+
+impl Plugin for TerrainExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainExport>();
+        app.add_systems(Update, (start_export, poll_export_task).chain());
+    }
+}
+
+/// Writes the terrain heightmap to `<project>/export/heightmap.<ext>` when requested.
+#[derive(Resource, Debug, Default)]
+pub struct TerrainExport {
+    pub format: ExportFormat,
+    /// Set to export once, cleared when the export starts
+    pub requested: bool,
+    /// Where the last export went
+    pub last_path: Option<PathBuf>,
+    running: bool,
+}
+
+#[derive(Component)]
+struct ExportTask(PathBuf, Task<Result<ExportMetadata, ExportError>>);
+
+impl TerrainExport {
+    pub fn path(&self) -> PathBuf {
+        Path::new(PROJECT_DIR)
+            .join(EXPORT_DIR)
+            .join("heightmap")
+            .with_extension(self.format.extension())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
+
+fn start_export(
+    mut commands: Commands,
+    mut export: ResMut<TerrainExport>,
+    heightmap: Option<Res<TerrainHeightmap>>,
+) {
+    if !export.requested || export.running {
+        return;
+    }
+    export.requested = false;
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+
+    let path = export.path();
+    let format = export.format;
+    let h_bundle = heightmap.0.clone();
+    let task_path = path.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        if let Some(dir) = task_path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| ExportError::Io(dir.to_owned(), e))?;
+        }
+        heightmap_export::export(&task_path, &h_bundle, format)
+    });
+    commands.spawn(ExportTask(path, task));
+    export.running = true;
+}
+
+fn poll_export_task(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut ExportTask)>,
+    mut export: ResMut<TerrainExport>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let Some(result) = check_ready(&mut task.1) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        export.running = false;
+
+        match result {
+            Ok(metadata) => {
+                info!(
+                    "Exported {} x {} heightmap to '{}'",
+                    metadata.width,
+                    metadata.height,
+                    task.0.display()
+                );
+                export.last_path = Some(task.0.clone());
+            }
+            Err(e) => {
+                error!("Heightmap export failed: {e}");
+                status.error = Some(e.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use bevy::prelude::*;
+
+use bevy::mesh::VertexAttributeValues;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::tasks::futures::check_ready;
+
+use worldedit::terrain_processing::analysis::AnalysisMap;
+use worldedit::terrain_processing::heightmap::GrayF32Image;
+use worldedit::terrain_processing::terrain_cruncher::CrunchError;
+
+use crate::editor::Colors;
+use crate::editor::terrain_cell_preview::TerrainCell;
+use crate::editor::terrain_cell_preview::TerrainCrunchPlan;
+use crate::editor::terrain_cell_preview::TerrainPreviewStatus;
+
+pub struct TerrainOverlayPlugin;
+
+impl Plugin for TerrainOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainOverlay>();
+        app.add_systems(
+            Update,
+            (request_overlay, poll_overlay_task, color_cells).chain(),
+        );
+    }
+}
+
+/// Analysis map shown on the terrain cells as vertex colors.
+#[derive(Resource, Debug, Default)]
+pub struct TerrainOverlay {
+    pub kind: Option<AnalysisMap>,
+    /// Loaded map, normalized to 0..1
+    map: Option<(AnalysisMap, GrayF32Image)>,
+}
+
+impl TerrainOverlay {
+    pub fn is_loading(&self) -> bool {
+        self.kind.is_some() && self.map.as_ref().map(|(kind, _)| *kind) != self.kind
+    }
+}
+
+#[derive(Component)]
+struct OverlayTask(AnalysisMap, Task<Result<GrayF32Image, CrunchError>>);
+
+fn request_overlay(
+    mut commands: Commands,
+    mut overlay: ResMut<TerrainOverlay>,
+    plan: Option<Res<TerrainCrunchPlan>>,
+    q_tasks: Query<&OverlayTask>,
+) {
+    let (Some(kind), Some(plan)) = (overlay.kind, plan) else {
+        return;
+    };
+    // The terrain was crunched again
+    if plan.is_changed() && overlay.map.is_some() {
+        overlay.map = None;
+    }
+    if !overlay.is_loading() || q_tasks.iter().any(|task| task.0 == kind) {
+        return;
+    }
+
+    let plan = plan.0.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let map = plan.analysis_map(kind)?;
+        Ok(normalized(kind, map))
+    });
+    commands.spawn(OverlayTask(kind, task));
+}
+
+fn poll_overlay_task(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut OverlayTask)>,
+    mut overlay: ResMut<TerrainOverlay>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let Some(result) = check_ready(&mut task.1) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+
+        match result {
+            Ok(map) => overlay.map = Some((task.0, map)),
+            Err(e) => {
+                error!("Terrain overlay failed: {e}");
+                status.error = Some(e.to_string());
+                overlay.kind = None;
+            }
+        }
+    }
+}
+
+/// Stretches `map` to 0..1 for display
+fn normalized(kind: AnalysisMap, mut map: GrayF32Image) -> GrayF32Image {
+    let max_abs = map.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+    let value = |v: f32| match kind {
+        AnalysisMap::Slope => v / std::f32::consts::FRAC_PI_2,
+        // Flat is the middle, the steepest hollow and ridge the ends
+        AnalysisMap::Curvature if max_abs > 0.0 => 0.5 + v / (2.0 * max_abs),
+        AnalysisMap::Curvature => 0.5,
+        // Rivers gather thousands of texels, most texels a handful
+        AnalysisMap::FlowAccumulation if max_abs > 1.0 => v.ln() / max_abs.ln(),
+        AnalysisMap::FlowAccumulation => 0.0,
+        AnalysisMap::AmbientOcclusion => v,
+    };
+    map.iter_mut().for_each(|v| *v = value(*v).clamp(0.0, 1.0));
+    map
+}
+
+fn color_cells(
+    overlay: Res<TerrainOverlay>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    q_cells: Query<Ref<TerrainCell>>,
+) {
+    let map = overlay
+        .map
+        .as_ref()
+        .filter(|(kind, _)| overlay.kind == Some(*kind))
+        .map(|(_, map)| map);
+
+    for cell in q_cells.iter() {
+        // Sculpting replaces the cell meshes
+        if !overlay.is_changed() && !cell.is_changed() {
+            continue;
+        }
+
+        // The map covers the whole world
+        let world_size = cell.mesh.world().world_size as f32;
+        for handle in &cell.lods {
+            let Some(mesh) = meshes.get_mut(handle) else {
+                continue;
+            };
+            let Some(map) = map else {
+                mesh.remove_attribute(Mesh::ATTRIBUTE_COLOR);
+                continue;
+            };
+            let Some(VertexAttributeValues::Float32x3(positions)) =
+                mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+            else {
+                continue;
+            };
+
+            let texel_size = world_size / (map.width() - 1).max(1) as f32;
+            let colors: Vec<[f32; 4]> = positions
+                .iter()
+                .map(|p| {
+                    let texel = (vec2(p[0], p[2]) / texel_size).round().as_uvec2();
+                    let v = map
+                        .get_pixel(texel.x.min(map.width() - 1), texel.y.min(map.height() - 1))[0];
+                    overlay_color(v).to_f32_array()
+                })
+                .collect();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        }
+    }
+}
+
+/// Low values cold, high values hot
+fn overlay_color(v: f32) -> LinearRgba {
+    let color = if v < 0.5 {
+        Colors::OVERLAY_LOW.mix(&Colors::OVERLAY_MID, v * 2.0)
+    } else {
+        Colors::OVERLAY_MID.mix(&Colors::OVERLAY_HIGH, v * 2.0 - 1.0)
+    };
+    color.into()
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use bevy::ecs::system::SystemParam;
+use bevy::window::PrimaryWindow;
+
+use worldedit::terrain_processing::HeightmapBundle;
+
+use crate::editor::camera_rig_orbital::CurrentCamera;
+use crate::editor::components::ViewportRenderTarget;
+use crate::editor::terrain_cell_preview::TerrainHeightmap;
+use crate::editor::terrain_cell_preview::terrain_origin;
+
+/// Rays give up after this far
+pub const MAX_RAY_DISTANCE: f32 = 16384.0;
+
+/// Where a ray meets the terrain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainHit {
+    /// World space
+    pub position: Vec3,
+    /// Heightmap space, origin at texel `(0, 0)`
+    pub local_position: Vec3,
+    pub normal: Vec3,
+    /// Index of the terrain cell that was hit
+    pub cell: u32,
+    /// Along the ray
+    pub distance: f32,
+}
+
+/// Casts rays against the terrain heightmap, for placing things on the ground.
+#[derive(SystemParam)]
+pub struct TerrainRaycast<'w, 's> {
+    heightmap: Option<Res<'w, TerrainHeightmap>>,
+    q_camera: Query<
+        'w,
+        's,
+        (
+            &'static Camera,
+            &'static GlobalTransform,
+            &'static ViewportRenderTarget,
+        ),
+        With<CurrentCamera>,
+    >,
+    q_windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+impl TerrainRaycast<'_, '_> {
+    /// `None` until the terrain is crunched
+    pub fn heightmap(&self) -> Option<&HeightmapBundle> {
+        self.heightmap.as_ref().map(|heightmap| &heightmap.0)
+    }
+
+    /// `ray` is in world space
+    pub fn cast_ray(&self, ray: Ray3d) -> Option<TerrainHit> {
+        let h_bundle = self.heightmap()?;
+        let origin = terrain_origin(h_bundle.world());
+        let local_ray = Ray3d::new(ray.origin - origin, ray.direction);
+        let local_position = h_bundle.raycast(local_ray, MAX_RAY_DISTANCE)?;
+
+        Some(TerrainHit {
+            position: local_position + origin,
+            local_position,
+            normal: h_bundle.normal_at(local_position.xz()),
+            cell: h_bundle.world().cell_index(local_position.xz())?,
+            distance: local_position.distance(local_ray.origin),
+        })
+    }
+
+    /// Terrain straight above or below a world position
+    pub fn ground_at(&self, position: Vec2) -> Option<TerrainHit> {
+        let above = position.extend(MAX_RAY_DISTANCE / 2.0).xzy();
+        self.cast_ray(Ray3d::new(above, Dir3::NEG_Y))
+    }
+
+    /// Ray through the cursor, from whichever viewport the cursor is over
+    pub fn cursor_ray(&self) -> Option<Ray3d> {
+        let window = self.q_windows.single().ok()?;
+        cursor_ray(&self.q_camera, window)
+    }
+
+    pub fn cursor_hit(&self) -> Option<TerrainHit> {
+        self.cast_ray(self.cursor_ray()?)
+    }
+}
+
+fn cursor_ray(
+    q_camera: &Query<(&Camera, &GlobalTransform, &ViewportRenderTarget), With<CurrentCamera>>,
+    window: &Window,
+) -> Option<Ray3d> {
+    let (camera, camera_global, cursor_pos) =
+        q_camera
+            .iter()
+            .find_map(|(camera, camera_global, render_target)| {
+                let cursor_pos = render_target.cursor_position(window)?;
+                Some((camera, camera_global, cursor_pos))
+            })?;
+    camera.viewport_to_world(camera_global, cursor_pos).ok()
+}
@@ -0,0 +1,268 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::tasks::futures::check_ready;
+
+use worldedit::terrain_processing::HeightLayer;
+use worldedit::terrain_processing::HeightmapBundle;
+use worldedit::terrain_processing::LayerBlend;
+use worldedit::terrain_processing::PROJECT_DIR;
+use worldedit::terrain_processing::sculpt::Brush;
+use worldedit::terrain_processing::sculpt::BrushTool;
+use worldedit::terrain_processing::sculpt::apply_brush;
+
+use crate::editor::Colors;
+use crate::editor::selection_actions::SelectionActionState;
+use crate::editor::terrain_cell_preview::TerrainCell;
+use crate::editor::terrain_cell_preview::TerrainHeightmap;
+use crate::editor::terrain_cell_preview::TerrainPreviewStatus;
+use crate::editor::terrain_cell_preview::terrain_origin;
+use crate::editor::terrain_raycast::TerrainRaycast;
+use crate::editor::ui::hotkeys_enabled;
+
+/// Brush strokes go into this layer, created on the first stroke.
+const SCULPT_LAYER_NAME: &str = "Sculpt";
+/// The sculpt layer is saved here, in the project directory, after each stroke.
+pub const SCULPT_LAYER_FILE: &str = "sculpt.wlayer";
+
+pub struct TerrainSculptPlugin;
+
+impl Plugin for TerrainSculptPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainSculpt>();
+        app.add_systems(
+            Update,
+            (
+                toggle_sculpt_mode.run_if(hotkeys_enabled),
+                sculpt_terrain,
+                apply_stroke,
+                rebuild_dirty_cells,
+                save_sculpt_layer,
+                poll_save_task,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Sculpt mode and brush settings.
+#[derive(Resource, Debug, Default)]
+pub struct TerrainSculpt {
+    pub enabled: bool,
+    pub brush: Brush,
+    /// Brush and heightmap position to apply this frame
+    stroke: Option<(Brush, Vec2)>,
+    /// The sculpt layer changed since it was last saved
+    unsaved: bool,
+    saving: bool,
+}
+
+#[derive(Component)]
+struct SaveLayerTask(PathBuf, Task<std::io::Result<()>>);
+
+/// Cell whose heights changed, rebuilt at the end of the frame.
+#[derive(Component)]
+struct TerrainCellDirty;
+
+fn toggle_sculpt_mode(
+    mut sculpt: ResMut<TerrainSculpt>,
+    selection_state: Res<SelectionActionState>,
+    kb: Res<ButtonInput<KeyCode>>,
+) {
+    if *selection_state == SelectionActionState::None && kb.just_pressed(KeyCode::KeyB) {
+        sculpt.enabled = !sculpt.enabled;
+    }
+}
+
+/// Draws the brush, and queues a stroke while the left mouse button is held
+fn sculpt_terrain(
+    mut sculpt: ResMut<TerrainSculpt>,
+    raycast: TerrainRaycast,
+    selection_state: Res<SelectionActionState>,
+    kb: Res<ButtonInput<KeyCode>>,
+    mb: Res<ButtonInput<MouseButton>>,
+    mut gizmos: Gizmos,
+) {
+    if !sculpt.enabled || *selection_state != SelectionActionState::None {
+        return;
+    }
+    let (Some(h_bundle), Some(hit)) = (raycast.heightmap(), raycast.cursor_hit()) else {
+        return;
+    };
+    let hit = hit.local_position;
+
+    draw_brush(&mut gizmos, h_bundle, sculpt.brush.radius, hit.xz());
+
+    let pick_height = kb.pressed(KeyCode::ControlLeft);
+    if mb.just_pressed(MouseButton::Left) {
+        match sculpt.brush.tool {
+            BrushTool::Flatten => sculpt.brush.target_height = hit.y,
+            BrushTool::SetHeight if pick_height => sculpt.brush.target_height = hit.y,
+            _ => (),
+        }
+    }
+    if !mb.pressed(MouseButton::Left) || pick_height {
+        return;
+    }
+
+    let mut brush = sculpt.brush;
+    if kb.pressed(KeyCode::ShiftLeft) {
+        brush.tool = match brush.tool {
+            BrushTool::Raise => BrushTool::Lower,
+            BrushTool::Lower => BrushTool::Raise,
+            tool => tool,
+        };
+    }
+    sculpt.stroke = Some((brush, hit.xz()));
+}
+
+/// Only system writing the heightmap, so hovering doesn't mark it changed
+fn apply_stroke(
+    mut commands: Commands,
+    mut sculpt: ResMut<TerrainSculpt>,
+    heightmap: Option<ResMut<TerrainHeightmap>>,
+    q_cells: Query<(Entity, &TerrainCell)>,
+    time: Res<Time>,
+) {
+    let Some((brush, center)) = sculpt.stroke.take() else {
+        return;
+    };
+    let Some(mut heightmap) = heightmap else {
+        return;
+    };
+    let h_bundle = &mut heightmap.0;
+
+    let layer = sculpt_layer(h_bundle);
+    let Some(area) = apply_brush(h_bundle, layer, &brush, center, time.delta_secs()) else {
+        return;
+    };
+    sculpt.unsaved = true;
+    // Filtered samples reach a texel further
+    let area = area.inflate(h_bundle.texel_size().max_element());
+
+    let cell_size = h_bundle.world().cell_size as f32;
+    for (entity, cell) in q_cells.iter() {
+        // Border normals read one vertex into the neighbours
+        let margin = cell.mesh.vertex_spacing();
+        let min = cell.mesh.position().as_vec2() - margin;
+        let max = min + cell_size + margin * 2.0;
+        if area.min.cmplt(max).all() && area.max.cmpgt(min).all() {
+            commands.entity(entity).insert(TerrainCellDirty);
+        }
+    }
+}
+
+/// Index of the sculpt layer, added on top if there is none yet
+fn sculpt_layer(h_bundle: &mut HeightmapBundle) -> usize {
+    if let Some(index) = h_bundle
+        .layers()
+        .iter()
+        .position(|layer| layer.name == SCULPT_LAYER_NAME)
+    {
+        return index;
+    }
+    let layer = HeightLayer::new(SCULPT_LAYER_NAME, LayerBlend::Add, h_bundle.size());
+    h_bundle
+        .push_layer(layer)
+        .expect("new layer matches the heightmap size")
+}
+
+/// Brush outline following the terrain
+pub fn draw_brush(gizmos: &mut Gizmos, h_bundle: &HeightmapBundle, radius: f32, center: Vec2) {
+    const SEGMENTS: usize = 48;
+    const LIFT: f32 = 0.5;
+
+    let origin = terrain_origin(h_bundle.world());
+    let outline = (0..=SEGMENTS).map(|i| {
+        let angle = i as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+        let p = center + Vec2::from_angle(angle) * radius;
+        origin + p.extend(h_bundle.sample(p) + LIFT).xzy()
+    });
+    gizmos.linestrip(outline, Colors::BRUSH);
+
+    let center_pos = origin + center.extend(h_bundle.sample(center) + LIFT).xzy();
+    gizmos.line(center_pos, center_pos + Vec3::Y * 2.0, Colors::BRUSH);
+}
+
+fn rebuild_dirty_cells(
+    mut commands: Commands,
+    heightmap: Option<Res<TerrainHeightmap>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut q_cells: Query<(Entity, &mut TerrainCell), With<TerrainCellDirty>>,
+) {
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+
+    for (entity, mut cell) in q_cells.iter_mut() {
+        let cell = &mut *cell;
+        cell.mesh.apply_height(&|p| heightmap.0.sample(p));
+        cell.bounds = cell.mesh.bounds();
+        for (lod, handle) in cell.lods.iter().enumerate() {
+            if let Some(mesh) = meshes.get_mut(handle) {
+                *mesh = cell.mesh.bevy_mesh_lod(lod as u32);
+            }
+        }
+        commands.entity(entity).remove::<TerrainCellDirty>();
+    }
+}
+
+/// Saves the sculpt layer once the stroke ends
+fn save_sculpt_layer(
+    mut commands: Commands,
+    mut sculpt: ResMut<TerrainSculpt>,
+    heightmap: Option<Res<TerrainHeightmap>>,
+    mb: Res<ButtonInput<MouseButton>>,
+) {
+    if !sculpt.unsaved || sculpt.saving || mb.pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+    let Some(layer) = heightmap
+        .0
+        .layers()
+        .iter()
+        .find(|layer| layer.name == SCULPT_LAYER_NAME)
+    else {
+        return;
+    };
+
+    let path = Path::new(PROJECT_DIR).join(SCULPT_LAYER_FILE);
+    let layer = layer.clone();
+    let task_path = path.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        // Don't leave a half written layer behind if the editor quits mid-save
+        let tmp_path = task_path.with_extension("tmp");
+        layer.save(&tmp_path)?;
+        std::fs::rename(&tmp_path, &task_path)
+    });
+    commands.spawn(SaveLayerTask(path, task));
+    sculpt.unsaved = false;
+    sculpt.saving = true;
+}
+
+fn poll_save_task(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut SaveLayerTask)>,
+    mut sculpt: ResMut<TerrainSculpt>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let Some(result) = check_ready(&mut task.1) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        sculpt.saving = false;
+
+        if let Err(e) = result {
+            error!("Failed to save '{}': {e}", task.0.display());
+            status.error = Some(e.to_string());
+        }
+    }
+}
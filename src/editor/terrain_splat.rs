@@ -0,0 +1,591 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use bevy::asset::RenderAssetUsages;
+use bevy::color::ColorToPacked;
+use bevy::image::ImageAddressMode;
+use bevy::image::ImageSampler;
+use bevy::image::ImageSamplerDescriptor;
+use bevy::pbr::ExtendedMaterial;
+use bevy::pbr::MaterialExtension;
+use bevy::render::render_resource::AsBindGroup;
+use bevy::render::render_resource::Extent3d;
+use bevy::render::render_resource::ShaderType;
+use bevy::render::render_resource::TextureDimension;
+use bevy::render::render_resource::TextureFormat;
+use bevy::shader::ShaderRef;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::tasks::futures::check_ready;
+
+use worldedit::terrain_processing::PROJECT_DIR;
+use worldedit::terrain_processing::WorldSettings;
+use worldedit::terrain_processing::splat::MAX_SPLAT_LAYERS;
+use worldedit::terrain_processing::splat::SplatBrush;
+use worldedit::terrain_processing::splat::SplatLayer;
+use worldedit::terrain_processing::splat::SplatMap;
+use worldedit::terrain_processing::splat::paint_splat;
+
+use crate::editor::Colors;
+use crate::editor::selection_actions::SelectionActionState;
+use crate::editor::terrain_cell_preview::TerrainCell;
+use crate::editor::terrain_cell_preview::TerrainHeightmap;
+use crate::editor::terrain_cell_preview::TerrainPreviewStatus;
+use crate::editor::terrain_cell_preview::load_project_file;
+use crate::editor::terrain_raycast::TerrainRaycast;
+use crate::editor::terrain_sculpt::TerrainSculpt;
+use crate::editor::terrain_sculpt::draw_brush;
+use crate::editor::ui::hotkeys_enabled;
+
+/// Splat map texels per cell row
+const SPLAT_SIZE: u32 = 128;
+/// Layer textures are resized to this, to fit in one texture array
+const LAYER_TEXTURE_SIZE: u32 = 256;
+const SHADER_PATH: &str = "shaders/terrain_splat.wgsl";
+/// Painted splat maps are saved in here, in the project directory, one file per cell
+const SPLAT_DIR: &str = "splat";
+
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainSplatExtension>;
+
+pub struct TerrainSplatPlugin;
+
+impl Plugin for TerrainSplatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<TerrainMaterial>::default());
+        app.init_resource::<TerrainPaint>();
+        app.add_systems(
+            Update,
+            (
+                init_splat_layers.run_if(resource_added::<TerrainHeightmap>),
+                apply_layer_changes,
+                poll_layer_textures,
+                apply_terrain_material,
+                regenerate_splat_maps,
+                toggle_paint_mode.run_if(hotkeys_enabled),
+                paint_terrain,
+                save_splat_maps,
+                poll_save_task,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Blends the layer textures by the cell's splat maps, on top of [StandardMaterial].
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct TerrainSplatExtension {
+    #[uniform(100)]
+    pub params: TerrainSplatParams,
+    #[texture(101, dimension = "2d_array")]
+    #[sampler(102)]
+    pub layers: Handle<Image>,
+    /// Layers 0-3
+    #[texture(103)]
+    #[sampler(104)]
+    pub splat_0: Handle<Image>,
+    /// Layers 4-7
+    #[texture(105)]
+    pub splat_1: Handle<Image>,
+}
+
+impl MaterialExtension for TerrainSplatExtension {
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+}
+
+#[derive(ShaderType, Reflect, Debug, Clone, Copy)]
+pub struct TerrainSplatParams {
+    /// World units per texture repeat, four layers per vector
+    pub tile_sizes: [Vec4; 2],
+}
+
+/// Texture layers shared by all cells.
+#[derive(Resource, Debug)]
+pub struct TerrainSplatLayers {
+    pub layers: Vec<SplatLayer>,
+    /// Layer textures, or plain colors for layers without one
+    textures: Handle<Image>,
+    /// Recompute every cell's splat map from the layer rules, dropping painted weights
+    pub regenerate: bool,
+    /// Set after editing [Self::layers] to reload their textures and save them
+    pub changed: bool,
+    /// Old index of every layer since the last apply, for moving painted weights along
+    remap: Option<Vec<Option<usize>>>,
+}
+
+impl TerrainSplatLayers {
+    /// Replaces the layers. `sources` holds the old index of each new layer, or none for
+    /// an added one, so painted weights follow their layer.
+    pub fn set_layers(&mut self, layers: Vec<SplatLayer>, sources: Vec<Option<usize>>) {
+        let sources = match self.remap.take() {
+            Some(previous) => sources
+                .into_iter()
+                .map(|source| source.and_then(|i| previous.get(i).copied().flatten()))
+                .collect(),
+            None => sources,
+        };
+        self.layers = layers;
+        self.remap = Some(sources);
+        self.changed = true;
+    }
+}
+
+/// Splat map of a cell using [TerrainMaterial].
+#[derive(Component, Debug)]
+pub struct TerrainCellSplat {
+    pub map: SplatMap,
+    images: [Handle<Image>; 2],
+    /// Painted since it was last saved
+    unsaved: bool,
+}
+
+/// Layer textures being read, replacing [TerrainSplatLayers::textures] once done
+#[derive(Component)]
+struct LayerTexturesTask(Task<Image>);
+
+#[derive(Component)]
+struct SaveSplatTask(Task<Result<(), (PathBuf, std::io::Error)>>);
+
+/// Splat paint mode and brush settings.
+#[derive(Resource, Debug, Default)]
+pub struct TerrainPaint {
+    pub enabled: bool,
+    pub brush: SplatBrush,
+}
+
+/// Project layers, or default ones for the terrain's height range. Runs for every new
+/// terrain, as the height range may have changed.
+fn init_splat_layers(
+    mut commands: Commands,
+    heightmap: Res<TerrainHeightmap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let layers =
+        load_project_file(SplatLayer::FILE_NAME, SplatLayer::load_all).unwrap_or_else(|| {
+            let world = heightmap.0.world();
+            SplatLayer::default_layers(world.world_height, world.world_height_offset)
+        });
+    let textures = images.add(layer_texture_array(vec![None; MAX_SPLAT_LAYERS]));
+    commands.spawn(LayerTexturesTask(load_layer_textures(&layers)));
+    commands.insert_resource(TerrainSplatLayers {
+        layers,
+        textures,
+        regenerate: false,
+        changed: false,
+        remap: None,
+    });
+}
+
+/// Reloads edited layers into every cell's material and saves them to the project.
+/// Painted weights move along with their layers.
+fn apply_layer_changes(
+    mut commands: Commands,
+    splat_layers: Option<ResMut<TerrainSplatLayers>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut q_cells: Query<(&MeshMaterial3d<TerrainMaterial>, &mut TerrainCellSplat)>,
+    q_tasks: Query<Entity, With<LayerTexturesTask>>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    let Some(mut splat_layers) = splat_layers else {
+        return;
+    };
+    if !splat_layers.changed {
+        return;
+    }
+    splat_layers.changed = false;
+
+    // Only the latest layers' textures are wanted
+    for entity in q_tasks.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.spawn(LayerTexturesTask(load_layer_textures(&splat_layers.layers)));
+
+    let remap = splat_layers
+        .remap
+        .take()
+        .filter(|sources| !sources.iter().copied().eq((0..sources.len()).map(Some)));
+    let tile_sizes = tile_sizes(&splat_layers.layers);
+    for (material, mut splat) in q_cells.iter_mut() {
+        if let Some(material) = materials.get_mut(material) {
+            material.extension.params.tile_sizes = tile_sizes;
+        }
+        if let Some(sources) = &remap {
+            splat.map.remap_layers(sources);
+            splat.unsaved = true;
+            upload_splat(&mut images, &splat);
+        }
+    }
+
+    let path = Path::new(PROJECT_DIR).join(SplatLayer::FILE_NAME);
+    if let Err(e) = SplatLayer::save_all(&path, &splat_layers.layers) {
+        error!("Failed to save '{}': {e}", path.display());
+        status.error = Some(e.to_string());
+    }
+}
+
+/// World units per texture repeat, as the shader takes them
+fn tile_sizes(layers: &[SplatLayer]) -> [Vec4; 2] {
+    let mut tile_sizes = [Vec4::ONE; 2];
+    for (i, layer) in layers.iter().take(MAX_SPLAT_LAYERS).enumerate() {
+        tile_sizes[i / 4][i % 4] = layer.tile_size;
+    }
+    tile_sizes
+}
+
+fn poll_layer_textures(
+    mut commands: Commands,
+    splat_layers: Option<Res<TerrainSplatLayers>>,
+    mut images: ResMut<Assets<Image>>,
+    mut q_tasks: Query<(Entity, &mut LayerTexturesTask)>,
+) {
+    let Some(splat_layers) = splat_layers else {
+        return;
+    };
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let Some(image) = check_ready(&mut task.0) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        if let Some(textures) = images.get_mut(&splat_layers.textures) {
+            *textures = image;
+        }
+    }
+}
+
+fn splat_map_path(index: u32) -> PathBuf {
+    Path::new(PROJECT_DIR)
+        .join(SPLAT_DIR)
+        .join(format!("cell_{index:03}"))
+        .with_extension(SplatMap::FILE_EXT)
+}
+
+/// World area of the cell starting at `position`, which its splat map is saved with
+fn cell_area(world: &WorldSettings, position: UVec2) -> URect {
+    URect::from_corners(position, position + world.cell_size)
+}
+
+/// Index of the cell starting at `position`
+fn cell_index(world: &WorldSettings, position: UVec2) -> u32 {
+    world
+        .cell_index(position.as_vec2())
+        .expect("cells are inside the world")
+}
+
+/// Reads the layer textures on the task pool
+fn load_layer_textures(layers: &[SplatLayer]) -> Task<Image> {
+    let paths: Vec<_> = layers
+        .iter()
+        .take(MAX_SPLAT_LAYERS)
+        .map(|layer| layer.texture.clone())
+        .collect();
+    AsyncComputeTaskPool::get().spawn(async move {
+        let mut textures = vec![None; MAX_SPLAT_LAYERS];
+        for (texture, path) in textures.iter_mut().zip(paths) {
+            *texture = path.and_then(|path| load_layer_texture(&Path::new(PROJECT_DIR).join(path)));
+        }
+        layer_texture_array(textures)
+    })
+}
+
+/// All layer textures stacked into one array texture, with mipmaps. Layers without a
+/// texture get their plain color.
+fn layer_texture_array(textures: Vec<Option<image::RgbaImage>>) -> Image {
+    let size = LAYER_TEXTURE_SIZE;
+    let mip_levels = size.ilog2() + 1;
+    let mut data = Vec::with_capacity((size * size * 4) as usize * MAX_SPLAT_LAYERS * 4 / 3);
+    // Each layer's full mip chain in turn
+    for (i, texture) in textures.into_iter().enumerate() {
+        for level in 0..mip_levels {
+            let level_size = size >> level;
+            match &texture {
+                Some(texture) => {
+                    let mip = image::imageops::resize(
+                        texture,
+                        level_size,
+                        level_size,
+                        image::imageops::FilterType::Triangle,
+                    );
+                    data.extend_from_slice(mip.as_raw());
+                }
+                None => {
+                    let color = Colors::SPLAT_LAYERS[i].to_u8_array();
+                    let texels = (level_size * level_size) as usize;
+                    data.extend(std::iter::repeat_n(color, texels).flatten());
+                }
+            }
+        }
+    }
+
+    let mut image = Image::new_uninit(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: MAX_SPLAT_LAYERS as u32,
+        },
+        TextureDimension::D2,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.texture_descriptor.mip_level_count = mip_levels;
+    image.data = Some(data);
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    });
+    image
+}
+
+fn load_layer_texture(path: &Path) -> Option<image::RgbaImage> {
+    let texture = match image::open(path) {
+        Ok(texture) => texture,
+        Err(e) => {
+            warn!("Ignoring layer texture '{}': {e}", path.display());
+            return None;
+        }
+    };
+    let texture = texture.resize_exact(
+        LAYER_TEXTURE_SIZE,
+        LAYER_TEXTURE_SIZE,
+        image::imageops::FilterType::Triangle,
+    );
+    Some(texture.to_rgba8())
+}
+
+fn splat_image(data: Vec<u8>) -> Image {
+    Image::new(
+        Extent3d {
+            width: SPLAT_SIZE,
+            height: SPLAT_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    )
+}
+
+/// Gives new cells a splat map from the layer rules
+fn apply_terrain_material(
+    mut commands: Commands,
+    splat_layers: Option<Res<TerrainSplatLayers>>,
+    heightmap: Option<Res<TerrainHeightmap>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    q_cells: Query<(Entity, &TerrainCell), Without<TerrainCellSplat>>,
+) {
+    let (Some(splat_layers), Some(heightmap)) = (splat_layers, heightmap) else {
+        return;
+    };
+
+    let tile_sizes = tile_sizes(&splat_layers.layers);
+    let world = heightmap.0.world();
+
+    for (entity, cell) in q_cells.iter() {
+        // Painted maps are kept over terrain changes, until reset from the rules. Maps
+        // saved for another cell layout are left to be overwritten.
+        let area = cell_area(world, *cell.mesh.position());
+        let path = splat_map_path(cell_index(world, *cell.mesh.position()));
+        let saved = match SplatMap::load(&path) {
+            Ok((map, saved_area)) => {
+                Some(map).filter(|map| map.size() == SPLAT_SIZE && saved_area == area)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Ignoring '{}': {e}", path.display());
+                None
+            }
+        };
+        let map = saved.unwrap_or_else(|| {
+            SplatMap::from_rules(
+                &heightmap.0,
+                &splat_layers.layers,
+                *cell.mesh.position(),
+                SPLAT_SIZE,
+            )
+        });
+        let images = map.to_rgba8().map(|data| images.add(splat_image(data)));
+        let material = materials.add(TerrainMaterial {
+            base: StandardMaterial {
+                base_color: Color::WHITE,
+                perceptual_roughness: 0.9,
+                ..default()
+            },
+            extension: TerrainSplatExtension {
+                params: TerrainSplatParams { tile_sizes },
+                layers: splat_layers.textures.clone(),
+                splat_0: images[0].clone(),
+                splat_1: images[1].clone(),
+            },
+        });
+        commands.entity(entity).insert((
+            TerrainCellSplat {
+                map,
+                images,
+                unsaved: false,
+            },
+            MeshMaterial3d(material),
+        ));
+    }
+}
+
+fn regenerate_splat_maps(
+    splat_layers: Option<ResMut<TerrainSplatLayers>>,
+    heightmap: Option<Res<TerrainHeightmap>>,
+    mut images: ResMut<Assets<Image>>,
+    mut q_cells: Query<(&TerrainCell, &mut TerrainCellSplat)>,
+) {
+    let (Some(mut splat_layers), Some(heightmap)) = (splat_layers, heightmap) else {
+        return;
+    };
+    if !splat_layers.regenerate {
+        return;
+    }
+    splat_layers.regenerate = false;
+
+    for (cell, mut splat) in q_cells.iter_mut() {
+        splat.map = SplatMap::from_rules(
+            &heightmap.0,
+            &splat_layers.layers,
+            *cell.mesh.position(),
+            SPLAT_SIZE,
+        );
+        splat.unsaved = true;
+        upload_splat(&mut images, &splat);
+    }
+}
+
+fn upload_splat(images: &mut Assets<Image>, splat: &TerrainCellSplat) {
+    for (handle, data) in splat.images.iter().zip(splat.map.to_rgba8()) {
+        if let Some(image) = images.get_mut(handle) {
+            image.data = Some(data);
+        }
+    }
+}
+
+fn toggle_paint_mode(
+    mut paint: ResMut<TerrainPaint>,
+    mut sculpt: ResMut<TerrainSculpt>,
+    selection_state: Res<SelectionActionState>,
+    kb: Res<ButtonInput<KeyCode>>,
+) {
+    if *selection_state == SelectionActionState::None && kb.just_pressed(KeyCode::KeyP) {
+        paint.enabled = !paint.enabled;
+        // One brush at a time
+        if paint.enabled {
+            sculpt.enabled = false;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn paint_terrain(
+    paint: Res<TerrainPaint>,
+    sculpt: Res<TerrainSculpt>,
+    raycast: TerrainRaycast,
+    mut q_cells: Query<(&TerrainCell, &mut TerrainCellSplat)>,
+    mut images: ResMut<Assets<Image>>,
+    selection_state: Res<SelectionActionState>,
+    mb: Res<ButtonInput<MouseButton>>,
+    time: Res<Time>,
+    mut gizmos: Gizmos,
+) {
+    if !paint.enabled || sculpt.enabled || *selection_state != SelectionActionState::None {
+        return;
+    }
+    let (Some(h_bundle), Some(hit)) = (raycast.heightmap(), raycast.cursor_hit()) else {
+        return;
+    };
+    let hit = hit.local_position;
+
+    draw_brush(&mut gizmos, h_bundle, paint.brush.radius, hit.xz());
+    if !mb.pressed(MouseButton::Left) {
+        return;
+    }
+
+    let cell_size = h_bundle.world().cell_size as f32;
+    for (cell, mut splat) in q_cells.iter_mut() {
+        let origin = cell.mesh.position().as_vec2();
+        let min = origin - paint.brush.radius;
+        let max = origin + cell_size + paint.brush.radius;
+        if hit.xz().cmplt(min).any() || hit.xz().cmpgt(max).any() {
+            continue;
+        }
+        let painted = paint_splat(
+            &mut splat.map,
+            origin,
+            cell_size,
+            &paint.brush,
+            hit.xz(),
+            time.delta_secs(),
+        );
+        if painted {
+            splat.unsaved = true;
+            upload_splat(&mut images, &splat);
+        }
+    }
+}
+
+/// Saves painted splat maps once the stroke ends
+fn save_splat_maps(
+    mut commands: Commands,
+    heightmap: Option<Res<TerrainHeightmap>>,
+    mut q_cells: Query<(&TerrainCell, &mut TerrainCellSplat)>,
+    q_tasks: Query<(), With<SaveSplatTask>>,
+    mb: Res<ButtonInput<MouseButton>>,
+) {
+    if mb.pressed(MouseButton::Left) || !q_tasks.is_empty() {
+        return;
+    }
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+
+    let world = heightmap.0.world();
+    let maps: Vec<_> = q_cells
+        .iter_mut()
+        .filter(|(_, splat)| splat.unsaved)
+        .map(|(cell, mut splat)| {
+            splat.unsaved = false;
+            let position = *cell.mesh.position();
+            let path = splat_map_path(cell_index(world, position));
+            (path, cell_area(world, position), splat.map.clone())
+        })
+        .collect();
+    if maps.is_empty() {
+        return;
+    }
+
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let dir = Path::new(PROJECT_DIR).join(SPLAT_DIR);
+        std::fs::create_dir_all(&dir).map_err(|e| (dir, e))?;
+        for (path, area, map) in maps {
+            map.save(&path, area).map_err(|e| (path, e))?;
+        }
+        Ok(())
+    });
+    commands.spawn(SaveSplatTask(task));
+}
+
+fn poll_save_task(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut SaveSplatTask)>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let Some(result) = check_ready(&mut task.0) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+
+        if let Err((path, e)) = result {
+            error!("Failed to save '{}': {e}", path.display());
+            status.error = Some(e.to_string());
+        }
+    }
+}
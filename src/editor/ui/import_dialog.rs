@@ -0,0 +1,548 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::tasks::futures::check_ready;
+use bevy_egui::egui;
+
+use worldedit::terrain_processing::PROJECT_DIR;
+use worldedit::terrain_processing::heightmap::GrayF32Image;
+use worldedit::terrain_processing::heightmap_export;
+use worldedit::terrain_processing::heightmap_import;
+use worldedit::terrain_processing::heightmap_import::Endian;
+use worldedit::terrain_processing::heightmap_import::HeightRange;
+use worldedit::terrain_processing::heightmap_import::HeightmapSource;
+use worldedit::terrain_processing::heightmap_import::ImportFormat;
+use worldedit::terrain_processing::heightmap_import::ImportSettings;
+use worldedit::terrain_processing::heightmap_import::RawFormat;
+use worldedit::terrain_processing::heightmap_import::RawSample;
+use worldedit::terrain_processing::heightmap_ops;
+use worldedit::terrain_processing::heightmap_ops::ResampleFilter;
+use worldedit::terrain_processing::heightmap_ops::Rotation;
+use worldedit::terrain_processing::terrain_cruncher::DEFAULT_SOURCE;
+
+use crate::editor::terrain_cell_preview::TerrainCrunchConfig;
+use crate::editor::terrain_cell_preview::TerrainCrunchProgress;
+
+/// Longest side of the preview thumbnail
+const PREVIEW_SIZE: u32 = 256;
+/// Written by "Combine tiles", then used as the source
+const COMBINED_FILE_NAME: &str = "combined_heightmap.exr";
+
+/// State of the heightmap import window.
+#[derive(Resource, Default)]
+pub struct ImportDialog {
+    pub open: bool,
+    /// Edited copy of the crunch config's source
+    draft: Option<HeightmapSource>,
+    preview: Option<SourcePreview>,
+    /// Preview being decoded in the background
+    preview_task: Option<Task<Result<PreviewImage, String>>>,
+    tiles: TileSettings,
+    /// Tiles being combined in the background, giving the combined file
+    combine_task: Option<Task<Result<PathBuf, String>>>,
+    message: Option<String>,
+}
+
+/// Source as read, before the layout is applied
+struct SourcePreview {
+    path: PathBuf,
+    size: UVec2,
+    texture: egui::TextureHandle,
+}
+
+/// Decoded [SourcePreview], before its texture is uploaded
+struct PreviewImage {
+    path: PathBuf,
+    size: UVec2,
+    image: egui::ColorImage,
+}
+
+#[derive(Clone)]
+struct TileSettings {
+    /// One path per line, row by row
+    paths: String,
+    columns: u32,
+    overlap: u32,
+}
+
+impl Default for TileSettings {
+    fn default() -> Self {
+        Self {
+            paths: String::new(),
+            columns: 2,
+            overlap: 1,
+        }
+    }
+}
+
+pub fn import_dialog_ui(ctx: &egui::Context, world: &mut World) {
+    if !world.resource::<ImportDialog>().open {
+        return;
+    }
+    let config = &world.resource::<TerrainCrunchConfig>().0;
+    let world_size = config.world.world_size;
+    let has_generator = config.generator.is_some();
+    let current = HeightmapSource {
+        path: config.source_path.clone(),
+        import: config.import,
+    };
+    let crunching = world.resource::<TerrainCrunchProgress>().is_running();
+
+    let mut dialog = world.resource_mut::<ImportDialog>();
+    let dialog = &mut *dialog;
+    let draft = dialog.draft.get_or_insert(current);
+
+    let mut open = true;
+    let mut load_preview =
+        dialog.preview.is_none() && dialog.preview_task.is_none() && dialog.message.is_none();
+    let mut combine = false;
+    let mut apply = false;
+
+    egui::Window::new("Import heightmap")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let mut path = draft.path.to_string_lossy().into_owned();
+                if ui.text_edit_singleline(&mut path).changed() {
+                    draft.path = PathBuf::from(path);
+                }
+                ui.menu_button("Samples", |ui| {
+                    for sample in sample_files() {
+                        let name = sample.file_name().unwrap_or_default().to_string_lossy();
+                        if ui.button(name).clicked() {
+                            draft.path = sample.clone();
+                            load_preview = true;
+                        }
+                    }
+                });
+                load_preview |= ui.button("Load").clicked();
+                if dialog.preview_task.is_some() {
+                    ui.spinner();
+                }
+            });
+
+            format_ui(ui, &mut draft.import);
+            ui.separator();
+            let source_size = dialog.preview.as_ref().map(|preview| preview.size);
+            layout_ui(ui, &mut draft.import, source_size, world_size);
+            ui.separator();
+            preview_ui(ui, dialog.preview.as_ref(), &draft.import, world_size);
+
+            ui.collapsing("Combine tiles", |ui| {
+                ui.label("Tile paths, one per line, row by row:");
+                ui.text_edit_multiline(&mut dialog.tiles.paths);
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut dialog.tiles.columns).range(1..=64))
+                        .on_hover_text("Tiles per row");
+                    ui.label("columns");
+                    ui.add(egui::DragValue::new(&mut dialog.tiles.overlap).range(0..=16))
+                        .on_hover_text("Texels neighbouring tiles share along their edges");
+                    ui.label("overlap");
+                    let combining = dialog.combine_task.is_some();
+                    combine = ui
+                        .add_enabled(!combining, egui::Button::new("Combine"))
+                        .clicked();
+                    if combining {
+                        ui.spinner();
+                    }
+                });
+            });
+
+            if let Some(message) = &dialog.message {
+                ui.colored_label(egui::Color32::YELLOW, message);
+            }
+            if has_generator {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    "The project's noise generator is used instead of the source heightmap",
+                );
+            }
+            ui.horizontal(|ui| {
+                apply = ui
+                    .add_enabled(!crunching, egui::Button::new("Apply"))
+                    .on_hover_text("Save as the project's source and crunch the terrain again")
+                    .clicked();
+            });
+        });
+
+    if combine {
+        let tiles = dialog.tiles.clone();
+        let import = draft.import;
+        dialog.combine_task =
+            Some(AsyncComputeTaskPool::get().spawn(async move { combine_tiles(&tiles, &import) }));
+    }
+    if let Some(task) = &mut dialog.combine_task {
+        ctx.request_repaint();
+        if let Some(result) = check_ready(task) {
+            dialog.combine_task = None;
+            match result {
+                Ok(path) => {
+                    // The combined map is a float image holding the tiles' own heights
+                    draft.path = path;
+                    draft.import.format = ImportFormat::Image;
+                    draft.import.range = HeightRange::Source;
+                    load_preview = true;
+                }
+                Err(e) => dialog.message = Some(e),
+            }
+        }
+    }
+    if load_preview {
+        let path = draft.path.clone();
+        let import = draft.import;
+        // Replaces any preview still loading
+        dialog.preview_task = Some(
+            AsyncComputeTaskPool::get().spawn(async move { load_source_preview(&path, &import) }),
+        );
+    }
+    if let Some(task) = &mut dialog.preview_task {
+        ctx.request_repaint();
+        if let Some(result) = check_ready(task) {
+            dialog.preview_task = None;
+            match result {
+                Ok(preview) => {
+                    // A crop made for another source may not fit this one
+                    if let Some(crop) = &mut draft.import.layout.crop {
+                        *crop = clamp_crop(*crop, preview.size);
+                    }
+                    let texture = ctx.load_texture(
+                        "import_preview",
+                        preview.image,
+                        egui::TextureOptions::LINEAR,
+                    );
+                    dialog.preview = Some(SourcePreview {
+                        path: preview.path,
+                        size: preview.size,
+                        texture,
+                    });
+                    dialog.message = None;
+                }
+                Err(e) => {
+                    dialog.preview = None;
+                    dialog.message = Some(e);
+                }
+            }
+        }
+    }
+    let source = apply.then(|| draft.clone());
+    if !open {
+        // Start from the config next time
+        *dialog = ImportDialog {
+            tiles: std::mem::take(&mut dialog.tiles),
+            ..default()
+        };
+    }
+    let Some(source) = source else {
+        return;
+    };
+
+    let path = Path::new(PROJECT_DIR).join(HeightmapSource::FILE_NAME);
+    if let Err(e) = source.save(&path) {
+        error!("Failed to save '{}': {e}", path.display());
+    }
+    let mut config = world.resource_mut::<TerrainCrunchConfig>();
+    config.0.source_path = source.path;
+    config.0.import = source.import;
+}
+
+fn sample_files() -> Vec<PathBuf> {
+    let Some(dir) = Path::new(DEFAULT_SOURCE).parent() else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+fn format_ui(ui: &mut egui::Ui, import: &mut ImportSettings) {
+    ui.horizontal(|ui| {
+        let raw = |sample| {
+            ImportFormat::Raw(RawFormat {
+                width: 1025,
+                height: 1025,
+                sample,
+                endian: Endian::Little,
+            })
+        };
+        let selected_text = match import.format {
+            ImportFormat::Image => "Image",
+            ImportFormat::Raw(RawFormat {
+                sample: RawSample::U16,
+                ..
+            }) => "Raw 16-bit",
+            ImportFormat::Raw(RawFormat {
+                sample: RawSample::F32,
+                ..
+            }) => "Raw 32-bit float",
+        };
+        egui::ComboBox::from_label("Format")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut import.format, ImportFormat::Image, "Image");
+                for (sample, name) in [
+                    (RawSample::U16, "Raw 16-bit"),
+                    (RawSample::F32, "Raw 32-bit float"),
+                ] {
+                    let selected =
+                        matches!(import.format, ImportFormat::Raw(raw) if raw.sample == sample);
+                    if ui.selectable_label(selected, name).clicked() && !selected {
+                        import.format = raw(sample);
+                    }
+                }
+            });
+
+        if let ImportFormat::Raw(raw) = &mut import.format {
+            ui.add(egui::DragValue::new(&mut raw.width).range(1..=65536));
+            ui.label("x");
+            ui.add(egui::DragValue::new(&mut raw.height).range(1..=65536));
+            let mut big_endian = raw.endian == Endian::Big;
+            if ui.checkbox(&mut big_endian, "Big endian").changed() {
+                raw.endian = match big_endian {
+                    true => Endian::Big,
+                    false => Endian::Little,
+                };
+            }
+        }
+    });
+
+    ui.horizontal(|ui| {
+        let selected_text = match import.range {
+            HeightRange::Source => "Source values",
+            HeightRange::Normalize => "Normalize",
+            HeightRange::Custom { .. } => "Custom",
+        };
+        egui::ComboBox::from_label("Height range")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut import.range, HeightRange::Source, "Source values");
+                ui.selectable_value(&mut import.range, HeightRange::Normalize, "Normalize");
+                let custom = matches!(import.range, HeightRange::Custom { .. });
+                if ui.selectable_label(custom, "Custom").clicked() && !custom {
+                    import.range = HeightRange::Custom { min: 0.0, max: 1.0 };
+                }
+            });
+        if let HeightRange::Custom { min, max } = &mut import.range {
+            ui.add(egui::DragValue::new(min).speed(0.01).prefix("min: "));
+            ui.add(egui::DragValue::new(max).speed(0.01).prefix("max: "));
+        }
+    });
+}
+
+fn layout_ui(
+    ui: &mut egui::Ui,
+    import: &mut ImportSettings,
+    source_size: Option<UVec2>,
+    world_size: u32,
+) {
+    let layout = &mut import.layout;
+
+    ui.horizontal(|ui| {
+        let mut crop = layout.crop.is_some();
+        // The crop is edited in source texels, known once the preview is loaded
+        let Some(source_size) = source_size else {
+            ui.add_enabled(false, egui::Checkbox::new(&mut crop, "Crop"))
+                .on_disabled_hover_text("Load a preview to crop the source");
+            return;
+        };
+        ui.checkbox(&mut crop, "Crop");
+        match (crop, layout.crop) {
+            (true, None) => layout.crop = Some(URect::from_corners(UVec2::ZERO, source_size)),
+            (false, Some(_)) => layout.crop = None,
+            _ => (),
+        }
+        if let Some(rect) = &mut layout.crop {
+            let (mut min, mut size) = (rect.min, rect.size());
+            ui.add(
+                egui::DragValue::new(&mut min.x)
+                    .range(0..=source_size.x - 1)
+                    .prefix("x: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut min.y)
+                    .range(0..=source_size.y - 1)
+                    .prefix("y: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut size.x)
+                    .range(1..=source_size.x)
+                    .prefix("w: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut size.y)
+                    .range(1..=source_size.y)
+                    .prefix("h: "),
+            );
+            let max = (min + size).min(source_size);
+            *rect = URect::from_corners(min, max);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("Rotation")
+            .selected_text(layout.rotation.to_string())
+            .show_ui(ui, |ui| {
+                for rotation in Rotation::ALL {
+                    ui.selectable_value(&mut layout.rotation, rotation, rotation.to_string());
+                }
+            });
+        ui.add(egui::DragValue::new(&mut layout.offset.x).prefix("offset x: "));
+        ui.add(egui::DragValue::new(&mut layout.offset.y).prefix("y: "));
+    });
+
+    ui.horizontal(|ui| {
+        // One texel per world unit, like generated terrain
+        let fit = UVec2::splat(world_size + 1);
+        let selected_text = match layout.resample {
+            None => "Keep size".to_string(),
+            Some((size, _)) if size == fit => "Fit world".to_string(),
+            Some((size, _)) => format!("{} x {}", size.x, size.y),
+        };
+        egui::ComboBox::from_label("Resample")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                let filter = layout
+                    .resample
+                    .map(|(_, filter)| filter)
+                    .unwrap_or_default();
+                ui.selectable_value(&mut layout.resample, None, "Keep size");
+                ui.selectable_value(&mut layout.resample, Some((fit, filter)), "Fit world")
+                    .on_hover_text(format!("{} x {} texels, one per metre", fit.x, fit.y));
+            });
+        if let Some((size, filter)) = &mut layout.resample {
+            ui.add(egui::DragValue::new(&mut size.x).range(2..=16385));
+            ui.label("x");
+            ui.add(egui::DragValue::new(&mut size.y).range(2..=16385));
+            egui::ComboBox::from_id_salt("resample_filter")
+                .selected_text(filter.to_string())
+                .show_ui(ui, |ui| {
+                    for option in ResampleFilter::ALL {
+                        ui.selectable_value(filter, option, option.to_string());
+                    }
+                });
+        }
+    });
+}
+
+/// `crop` moved and shrunk to fit a `source_size` source, at least one texel big
+fn clamp_crop(crop: URect, source_size: UVec2) -> URect {
+    let min = crop.min.min(source_size - UVec2::ONE);
+    let max = crop.max.min(source_size).max(min + UVec2::ONE);
+    URect::from_corners(min, max)
+}
+
+/// Thumbnail with the crop outlined, and how the result maps onto the world
+fn preview_ui(
+    ui: &mut egui::Ui,
+    preview: Option<&SourcePreview>,
+    import: &ImportSettings,
+    world_size: u32,
+) {
+    let Some(preview) = preview else {
+        ui.label("No preview");
+        return;
+    };
+
+    ui.label(format!("Preview of {}", preview.path.display()));
+    let texture_size = preview.texture.size_vec2();
+    let response = ui.add(egui::Image::new((preview.texture.id(), texture_size)));
+    if let Some(crop) = import.layout.crop {
+        let scale = response.rect.size() / egui::vec2(preview.size.x as f32, preview.size.y as f32);
+        let to_screen = |p: UVec2| response.rect.min + egui::vec2(p.x as f32, p.y as f32) * scale;
+        let rect = egui::Rect::from_min_max(to_screen(crop.min), to_screen(crop.max));
+        ui.painter().rect_stroke(
+            rect,
+            0.0,
+            egui::Stroke::new(1.5, egui::Color32::YELLOW),
+            egui::StrokeKind::Outside,
+        );
+    }
+
+    let output = import.layout.output_size(preview.size);
+    let metres_per_texel = world_size as f32 / (output.max(UVec2::splat(2)) - 1).as_vec2();
+    ui.label(format!(
+        "Source: {} x {} px",
+        preview.size.x, preview.size.y
+    ));
+    ui.label(format!(
+        "Heightmap: {} x {} texels over {world_size} x {world_size} m",
+        output.x, output.y
+    ));
+    ui.label(format!(
+        "1 texel = {:.2} x {:.2} m",
+        metres_per_texel.x, metres_per_texel.y
+    ));
+    if output.x != output.y {
+        ui.colored_label(
+            egui::Color32::YELLOW,
+            "Not square: the heightmap is stretched to fit the world",
+        );
+    }
+}
+
+/// Decodes the source and shrinks it to a thumbnail. Runs in the background.
+fn load_source_preview(path: &Path, import: &ImportSettings) -> Result<PreviewImage, String> {
+    // Stretched for display, without the layout
+    let settings = ImportSettings {
+        range: HeightRange::Normalize,
+        layout: default(),
+        ..*import
+    };
+    let map = heightmap_import::import(path, &settings).map_err(|e| e.to_string())?;
+    let size = UVec2::from(map.dimensions());
+
+    let scale = PREVIEW_SIZE as f32 / size.max_element() as f32;
+    let thumb_size = (size.as_vec2() * scale.min(1.0))
+        .round()
+        .as_uvec2()
+        .max(UVec2::ONE);
+    let thumb = heightmap_ops::resample(&map, thumb_size, ResampleFilter::Bilinear);
+    let pixels: Vec<u8> = thumb
+        .iter()
+        .map(|h| (h.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect();
+    let image =
+        egui::ColorImage::from_gray([thumb_size.x as usize, thumb_size.y as usize], &pixels);
+
+    Ok(PreviewImage {
+        path: path.to_owned(),
+        size,
+        image,
+    })
+}
+
+/// Stitches the listed tiles into one float image in the project directory
+fn combine_tiles(tiles: &TileSettings, import: &ImportSettings) -> Result<PathBuf, String> {
+    // Ranges and layouts apply to the combined map, not each tile
+    let settings = ImportSettings {
+        range: HeightRange::Source,
+        layout: default(),
+        ..*import
+    };
+    let maps = tiles
+        .paths
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| heightmap_import::import(Path::new(line), &settings))
+        .collect::<Result<Vec<GrayF32Image>, _>>()
+        .map_err(|e| e.to_string())?;
+    let combined = heightmap_ops::combine_tiles(&maps, tiles.columns, tiles.overlap)
+        .map_err(|e| e.to_string())?;
+
+    let path = Path::new(PROJECT_DIR).join(COMBINED_FILE_NAME);
+    heightmap_export::save_exr(&path, &combined).map_err(|e| e.to_string())?;
+    info!("Combined {} tiles into '{}'", maps.len(), path.display());
+    Ok(path)
+}
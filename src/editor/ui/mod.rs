@@ -1,8 +1,11 @@
+pub mod import_dialog;
 mod panes;
+pub mod splat_layers_dialog;
 mod ui_tiling;
 
 use bevy::prelude::*;
 use bevy_egui::EguiPlugin;
+use bevy_egui::input::EguiWantsInput;
 use ui_tiling::UiTilingPlugin;
 
 #[derive(Debug)]
@@ -12,9 +15,17 @@ impl Plugin for EditorGuiPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(EguiPlugin::default());
         app.add_plugins(UiTilingPlugin);
+        app.init_resource::<import_dialog::ImportDialog>();
+        app.init_resource::<splat_layers_dialog::SplatLayersDialog>();
 
         app.add_plugins(panes::OutlinerPanePlugin);
         app.add_plugins(panes::ViewportPanePlugin);
         app.add_plugins(panes::MapViewPanePlugin);
     }
 }
+
+/// Run condition for hotkeys: false while egui has the keyboard, so typing into a text field
+/// doesn't also switch tools or act on the selection.
+pub fn hotkeys_enabled(egui_wants_input: Res<EguiWantsInput>) -> bool {
+    !egui_wants_input.wants_any_keyboard_input()
+}
@@ -1,12 +1,16 @@
 use bevy::prelude::*;
 
+use bevy::camera::visibility::RenderLayers;
+
 use bevy_egui::EguiContexts;
 use bevy_egui::egui;
 use bevy_egui::egui::Frame;
 
 use super::EditorPane;
+use super::viewport3d::spline_draw_ui;
 use crate::editor::camera_rig_topdown::CameraRigTopdown;
 use crate::editor::components::ViewportRenderTarget;
+use crate::editor::gizmos::MAP_VIEW_LAYER;
 use crate::editor::selection::WithSelected;
 use crate::editor::selection_actions::transform_action::TransformAction;
 use crate::editor::ui::ui_tiling::TileTree;
@@ -38,8 +42,11 @@ impl MapViewPane {
     ) {
         let render_target = ViewportRenderTarget::new(&mut contexts, images);
         let rt_texture_id = contexts.image_id(&render_target.img).unwrap();
-        CameraRigTopdown::spawn_with_name(&mut commands, "MapView Camera")
-            .insert((render_target, BelongsToMapView));
+        CameraRigTopdown::spawn_with_name(&mut commands, "MapView Camera").insert((
+            render_target,
+            BelongsToMapView,
+            RenderLayers::layer(MAP_VIEW_LAYER),
+        ));
         tree.register_pane(TilingPane::MapView(Self { rt_texture_id }));
     }
 }
@@ -58,6 +65,9 @@ impl EditorPane for MapViewPane {
                     ui.horizontal(|ui| {
                         selection_ui(ui, world);
                     });
+                    ui.horizontal(|ui| {
+                        spline_draw_ui(ui, world);
+                    });
                 });
 
                 let rect = ui.available_rect_before_wrap();
@@ -9,6 +9,7 @@ use egui_extras::TableBuilder;
 use super::EditorPane;
 use crate::editor::Selectable;
 use crate::editor::selection::Selected;
+use crate::editor::spline_edit::SplineHandle;
 use crate::editor::ui::ui_tiling::TileTree;
 use crate::editor::ui::ui_tiling::TilingPane;
 
@@ -23,6 +24,9 @@ impl Plugin for OutlinerPanePlugin {
 
 type SelectableQuery<'a> = (Entity, Option<&'a Name>, Option<&'a Selected>);
 
+/// Spline handles are picked in the viewport instead
+type OutlinerFilter = (With<Selectable>, Without<SplineHandle>);
+
 #[derive(Debug)]
 pub struct OutlinerPane;
 
@@ -40,7 +44,7 @@ impl EditorPane for OutlinerPane {
         world: &mut World,
         commands: &mut Commands,
     ) -> egui_tiles::UiResponse {
-        let mut query = world.query_filtered::<SelectableQuery, With<Selectable>>();
+        let mut query = world.query_filtered::<SelectableQuery, OutlinerFilter>();
 
         let entities = query.iter(world);
         outliner_ui(ui, commands, entities);
@@ -56,7 +60,7 @@ impl EditorPane for OutlinerPane {
 fn outliner_ui(
     ui: &mut Ui,
     commands: &mut Commands,
-    entities: QueryIter<SelectableQuery, With<Selectable>>,
+    entities: QueryIter<SelectableQuery, OutlinerFilter>,
 ) {
     let tablebuilder = TableBuilder::new(ui).column(Column::auto());
 
@@ -4,13 +4,43 @@ use bevy_egui::EguiContexts;
 use bevy_egui::egui;
 use bevy_egui::egui::Frame;
 
+use worldedit::terrain_processing::analysis::AnalysisMap;
+use worldedit::terrain_processing::erosion::HydraulicErosion;
+use worldedit::terrain_processing::erosion::ThermalErosion;
+use worldedit::terrain_processing::heightmap_export::ExportFormat;
+use worldedit::terrain_processing::sculpt::BrushFalloff;
+use worldedit::terrain_processing::sculpt::BrushTool;
+use worldedit::terrain_processing::water::WaterShape;
+
 use super::EditorPane;
+use crate::editor::Selectable;
 use crate::editor::camera_rig_orbital::CameraRigOrbital;
 use crate::editor::components::ViewportRenderTarget;
 use crate::editor::selection::WithSelected;
+use crate::editor::selection_actions::drop_to_ground::drop_selection_to_ground;
 use crate::editor::selection_actions::transform_action::TransformAction;
+use crate::editor::spline_draw::SplineDraw;
+use crate::editor::terrain_cell_preview::CrunchStage;
+use crate::editor::terrain_cell_preview::TerrainCrunchConfig;
+use crate::editor::terrain_cell_preview::TerrainCrunchProgress;
+use crate::editor::terrain_cell_preview::TerrainHeightmap;
+use crate::editor::terrain_cell_preview::TerrainPreviewStatus;
+use crate::editor::terrain_cell_preview::terrain_origin;
+use crate::editor::terrain_erosion::TerrainErosion;
+use crate::editor::terrain_export::TerrainExport;
+use crate::editor::terrain_overlay::TerrainOverlay;
+use crate::editor::terrain_sculpt::TerrainSculpt;
+use crate::editor::terrain_splat::TerrainPaint;
+use crate::editor::terrain_splat::TerrainSplatLayers;
+use crate::editor::ui::import_dialog::ImportDialog;
+use crate::editor::ui::splat_layers_dialog::SplatLayersDialog;
 use crate::editor::ui::ui_tiling::TileTree;
 use crate::editor::ui::ui_tiling::TilingPane;
+use crate::editor::water::TerrainWater;
+use crate::editor::water::WaterBody;
+use crate::spline::Spline;
+use crate::spline::SplineKind;
+use crate::spline_mesh::SplineMesh;
 
 #[derive(Component)]
 struct BelongsToViewport3d;
@@ -58,6 +88,18 @@ impl EditorPane for ViewportPane {
                     ui.horizontal(|ui| {
                         selection_ui(ui, world);
                     });
+                    terrain_status_ui(ui, world);
+                    erosion_ui(ui, world);
+                    ui.horizontal(|ui| {
+                        overlay_ui(ui, world);
+                    });
+                    sculpt_ui(ui, world);
+                    paint_ui(ui, world);
+                    water_ui(ui, world);
+                    ui.horizontal(|ui| {
+                        spline_draw_ui(ui, world);
+                    });
+                    spline_ui(ui, world);
                 });
 
                 let rect = ui.available_rect_before_wrap();
@@ -91,6 +133,544 @@ fn camera_controls_ui(ui: &mut egui::Ui, _world: &mut World) {
     ui.label("Shift + MMB: Rotate camera");
 }
 
+fn terrain_status_ui(ui: &mut egui::Ui, world: &mut World) {
+    let progress = world.resource::<TerrainCrunchProgress>();
+    if progress.is_running() {
+        let text = match progress.stage {
+            CrunchStage::Preparing => "Terrain: reading heightmap".to_string(),
+            CrunchStage::Eroding => format!(
+                "Terrain: eroding {}/{} iterations",
+                progress.iteration, progress.num_iterations
+            ),
+            _ => format!(
+                "Terrain: {}/{} cells",
+                progress.num_done, progress.num_cells
+            ),
+        };
+        ui.add(egui::ProgressBar::new(progress.fraction()).text(text));
+    }
+
+    let status = world.resource::<TerrainPreviewStatus>();
+    if let Some(error) = &status.error {
+        ui.colored_label(egui::Color32::RED, format!("Terrain: {error}"));
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("Import heightmap…").clicked() {
+            world.resource_mut::<ImportDialog>().open = true;
+        }
+        export_ui(ui, world);
+    });
+}
+
+fn export_ui(ui: &mut egui::Ui, world: &mut World) {
+    let has_terrain = world.contains_resource::<TerrainHeightmap>();
+    let mut export = world.resource_mut::<TerrainExport>();
+
+    let mut format = export.format;
+    egui::ComboBox::from_id_salt("export_format")
+        .selected_text(format.to_string())
+        .show_ui(ui, |ui| {
+            for option in ExportFormat::ALL {
+                ui.selectable_value(&mut format, option, option.to_string());
+            }
+        });
+    if format != export.format {
+        export.format = format;
+    }
+
+    let enabled = has_terrain && !export.is_running();
+    let response = ui
+        .add_enabled(enabled, egui::Button::new("Export heightmap"))
+        .on_hover_text(format!(
+            "Write to '{}', with a .json sidecar",
+            export.path().display()
+        ));
+    if response.clicked() {
+        export.requested = true;
+    }
+    if export.is_running() {
+        ui.spinner();
+    } else if let Some(path) = &export.last_path {
+        ui.label(format!("Exported to '{}'", path.display()));
+    }
+}
+
+fn erosion_ui(ui: &mut egui::Ui, world: &mut World) {
+    let crunching = world.resource::<TerrainCrunchProgress>().is_running();
+    let applied = world.resource::<TerrainCrunchConfig>().0.erosion;
+    let mut erosion = world.resource_mut::<TerrainErosion>();
+
+    let mut enabled = erosion.enabled;
+    let mut settings = erosion.settings;
+    let mut apply = false;
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut enabled, "Erosion");
+        if enabled {
+            ui.add(egui::DragValue::new(&mut settings.seed).prefix("seed: "));
+            ui.add(
+                egui::DragValue::new(&mut settings.iterations)
+                    .range(1..=4096)
+                    .prefix("iterations: "),
+            );
+            ui.checkbox(&mut settings.flow_map, "Flow map");
+            ui.checkbox(&mut settings.sediment_map, "Sediment map");
+        }
+        let changed = enabled.then_some(settings) != applied;
+        apply = ui
+            .add_enabled(changed && !crunching, egui::Button::new("Apply erosion"))
+            .on_hover_text("Save the erosion settings and crunch the terrain again")
+            .clicked();
+    });
+
+    if enabled {
+        ui.horizontal(|ui| {
+            let mut hydraulic = settings.hydraulic.is_some();
+            ui.checkbox(&mut hydraulic, "Hydraulic");
+            if hydraulic != settings.hydraulic.is_some() {
+                settings.hydraulic = hydraulic.then(HydraulicErosion::default);
+            }
+            if let Some(hydraulic) = &mut settings.hydraulic {
+                ui.add(
+                    egui::DragValue::new(&mut hydraulic.droplets)
+                        .range(1..=1 << 16)
+                        .prefix("droplets: "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut hydraulic.radius)
+                        .range(1..=8)
+                        .prefix("radius: "),
+                );
+                ui.add(egui::Slider::new(&mut hydraulic.inertia, 0.0..=1.0).text("inertia"));
+                ui.add(egui::Slider::new(&mut hydraulic.erosion, 0.0..=1.0).text("erosion"));
+                ui.add(egui::Slider::new(&mut hydraulic.deposition, 0.0..=1.0).text("deposition"));
+                ui.add(
+                    egui::Slider::new(&mut hydraulic.evaporation, 0.0..=1.0).text("evaporation"),
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut thermal = settings.thermal.is_some();
+            ui.checkbox(&mut thermal, "Thermal");
+            if thermal != settings.thermal.is_some() {
+                settings.thermal = thermal.then(ThermalErosion::default);
+            }
+            if let Some(thermal) = &mut settings.thermal {
+                ui.add(
+                    egui::Slider::new(&mut thermal.talus, 0.0001..=0.05)
+                        .logarithmic(true)
+                        .text("talus"),
+                );
+                ui.add(egui::Slider::new(&mut thermal.rate, 0.0..=1.0).text("rate"));
+            }
+        });
+    }
+
+    if erosion.enabled != enabled {
+        erosion.enabled = enabled;
+    }
+    if erosion.settings != settings {
+        erosion.settings = settings;
+    }
+    if apply {
+        erosion.requested = true;
+    }
+}
+
+fn overlay_ui(ui: &mut egui::Ui, world: &mut World) {
+    let has_terrain = world.contains_resource::<TerrainHeightmap>();
+    let mut overlay = world.resource_mut::<TerrainOverlay>();
+
+    // Changing the overlay recolors every cell, so only touch it on a new pick
+    let mut kind = overlay.kind;
+    let selected_text = match kind {
+        Some(kind) => kind.to_string(),
+        None => "None".to_string(),
+    };
+    ui.add_enabled_ui(has_terrain, |ui| {
+        egui::ComboBox::from_label("Overlay")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut kind, None, "None");
+                for option in AnalysisMap::ALL {
+                    ui.selectable_value(&mut kind, Some(option), option.to_string());
+                }
+            });
+    });
+    if kind != overlay.kind {
+        overlay.kind = kind;
+    }
+    if overlay.is_loading() {
+        ui.spinner();
+    }
+}
+
+fn sculpt_ui(ui: &mut egui::Ui, world: &mut World) {
+    let has_terrain = world.contains_resource::<TerrainHeightmap>();
+    let mut sculpt = world.resource_mut::<TerrainSculpt>();
+
+    ui.horizontal(|ui| {
+        ui.add_enabled(
+            has_terrain,
+            egui::Checkbox::new(&mut sculpt.enabled, "Sculpt (B)"),
+        );
+        if !sculpt.enabled {
+            return;
+        }
+
+        let brush = &mut sculpt.brush;
+        egui::ComboBox::from_id_salt("sculpt_tool")
+            .selected_text(brush.tool.to_string())
+            .show_ui(ui, |ui| {
+                for tool in BrushTool::ALL {
+                    ui.selectable_value(&mut brush.tool, tool, tool.to_string());
+                }
+            });
+        egui::ComboBox::from_id_salt("sculpt_falloff")
+            .selected_text(brush.falloff.to_string())
+            .show_ui(ui, |ui| {
+                for falloff in BrushFalloff::ALL {
+                    ui.selectable_value(&mut brush.falloff, falloff, falloff.to_string());
+                }
+            });
+        ui.add(
+            egui::Slider::new(&mut brush.radius, 1.0..=512.0)
+                .logarithmic(true)
+                .text("radius"),
+        );
+        ui.add(
+            egui::Slider::new(&mut brush.strength, 0.1..=200.0)
+                .logarithmic(true)
+                .text("strength"),
+        );
+
+        match brush.tool {
+            BrushTool::SetHeight => {
+                ui.add(egui::DragValue::new(&mut brush.target_height).suffix(" m"));
+                ui.label("Ctrl + LMB: pick height");
+            }
+            BrushTool::Raise | BrushTool::Lower => {
+                ui.label("Shift: invert");
+            }
+            _ => (),
+        }
+        ui.label("LMB: paint");
+    });
+}
+
+fn paint_ui(ui: &mut egui::Ui, world: &mut World) {
+    let Some(layer_names) = world.get_resource::<TerrainSplatLayers>().map(|splat| {
+        splat
+            .layers
+            .iter()
+            .map(|layer| layer.name.clone())
+            .collect::<Vec<_>>()
+    }) else {
+        return;
+    };
+
+    let mut disable_sculpt = false;
+    let mut regenerate = false;
+    let mut edit_layers = false;
+    let mut paint = world.resource_mut::<TerrainPaint>();
+    ui.horizontal(|ui| {
+        if ui.checkbox(&mut paint.enabled, "Paint (P)").changed() {
+            disable_sculpt = paint.enabled;
+        }
+        if !paint.enabled {
+            return;
+        }
+
+        let brush = &mut paint.brush;
+        let selected_text = layer_names.get(brush.layer).cloned().unwrap_or_default();
+        egui::ComboBox::from_id_salt("paint_layer")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                for (i, name) in layer_names.iter().enumerate() {
+                    ui.selectable_value(&mut brush.layer, i, name);
+                }
+            });
+        egui::ComboBox::from_id_salt("paint_falloff")
+            .selected_text(brush.falloff.to_string())
+            .show_ui(ui, |ui| {
+                for falloff in BrushFalloff::ALL {
+                    ui.selectable_value(&mut brush.falloff, falloff, falloff.to_string());
+                }
+            });
+        ui.add(
+            egui::Slider::new(&mut brush.radius, 1.0..=512.0)
+                .logarithmic(true)
+                .text("radius"),
+        );
+        ui.add(
+            egui::Slider::new(&mut brush.strength, 0.1..=20.0)
+                .logarithmic(true)
+                .text("strength"),
+        );
+        edit_layers = ui.button("Layers…").clicked();
+        regenerate = ui
+            .button("Reset from rules")
+            .on_hover_text("Recompute all splat maps from the layer rules, losing painted weights")
+            .clicked();
+        ui.label("LMB: paint");
+    });
+
+    if disable_sculpt {
+        world.resource_mut::<TerrainSculpt>().enabled = false;
+    }
+    if regenerate {
+        world.resource_mut::<TerrainSplatLayers>().regenerate = true;
+    }
+    if edit_layers {
+        world.resource_mut::<SplatLayersDialog>().open = true;
+    }
+}
+
+fn water_ui(ui: &mut egui::Ui, world: &mut World) {
+    let origin = world
+        .get_resource::<TerrainHeightmap>()
+        .map(|heightmap| terrain_origin(heightmap.0.world()));
+
+    let mut water_settings = world.resource::<TerrainWater>().settings;
+    let mut flood_fill = world.resource::<TerrainWater>().flood_fill;
+    let mut river_width = world.resource::<TerrainWater>().river_width;
+    let mut lake_level = world.resource::<TerrainWater>().lake_level;
+    let mut save = false;
+    let mut make_lake = false;
+    let mut make_river = false;
+
+    let mut q_splines = world.query_filtered::<(), (With<Spline>, WithSelected)>();
+    let has_spline = q_splines.iter(world).next().is_some();
+
+    ui.horizontal(|ui| {
+        ui.label("Sea level");
+        let response = ui.add(
+            egui::DragValue::new(&mut water_settings.sea_level)
+                .speed(0.5)
+                .suffix(" m"),
+        );
+        // Save once a drag settles, not every frame of it
+        save |= response.drag_stopped() || (response.changed() && !response.dragged());
+        save |= ui
+            .checkbox(&mut water_settings.show_sea, "Show sea")
+            .changed();
+
+        ui.add_enabled(
+            origin.is_some(),
+            egui::Checkbox::new(&mut flood_fill, "Flood fill (L)"),
+        )
+        .on_hover_text("LMB: fill the basin under the cursor with a lake");
+
+        ui.add_enabled_ui(origin.is_some() && has_spline, |ui| {
+            make_lake = ui.button("Lake from spline").clicked();
+        });
+        ui.add(
+            egui::DragValue::new(&mut lake_level)
+                .speed(0.5)
+                .prefix("level: ")
+                .suffix(" m"),
+        );
+        ui.add_enabled_ui(origin.is_some() && has_spline, |ui| {
+            make_river = ui.button("River from spline").clicked();
+        });
+        ui.add(
+            egui::DragValue::new(&mut river_width)
+                .range(0.5..=1000.0)
+                .prefix("width: ")
+                .suffix(" m"),
+        );
+    });
+    selected_water_ui(ui, world);
+
+    let mut water = world.resource_mut::<TerrainWater>();
+    if water.settings != water_settings {
+        water.settings = water_settings;
+    }
+    if water.river_width != river_width {
+        water.river_width = river_width;
+    }
+    if water.lake_level != lake_level {
+        water.lake_level = lake_level;
+    }
+    if water.flood_fill != flood_fill {
+        water.flood_fill = flood_fill;
+        if flood_fill {
+            world.resource_mut::<TerrainSculpt>().enabled = false;
+            world.resource_mut::<TerrainPaint>().enabled = false;
+        }
+    }
+    if save {
+        world.resource::<TerrainWater>().save_settings();
+    }
+
+    let Some(origin) = origin else {
+        return;
+    };
+    if !make_lake && !make_river {
+        return;
+    }
+    let mut q_splines = world.query_filtered::<(Entity, &Spline, &Transform), WithSelected>();
+    let bodies: Vec<_> = q_splines
+        .iter(world)
+        .map(|(entity, spline, xform)| match make_lake {
+            true => WaterBody::lake_from_spline(entity, spline, xform, origin, lake_level),
+            false => WaterBody::river_from_spline(entity, spline, xform, origin, river_width),
+        })
+        .collect();
+    for body in bodies {
+        world.spawn((
+            Name::new(body.name()),
+            body,
+            Transform::from_translation(origin),
+            Selectable,
+        ));
+    }
+}
+
+/// Level and width of the selected lakes and rivers
+fn selected_water_ui(ui: &mut egui::Ui, world: &mut World) {
+    let mut q_bodies = world.query_filtered::<&WaterBody, WithSelected>();
+    let Some(current) = q_bodies.iter(world).find_map(|body| match body.shape {
+        WaterShape::Lake { level, .. } => Some((true, level)),
+        WaterShape::River { width, .. } => Some((false, width)),
+        WaterShape::Basin(_) => None,
+    }) else {
+        return;
+    };
+
+    let (is_lake, mut value) = current;
+    ui.horizontal(|ui| {
+        if is_lake {
+            ui.label("Selected lake level");
+            ui.add(egui::DragValue::new(&mut value).speed(0.5).suffix(" m"));
+        } else {
+            ui.label("Selected river width");
+            ui.add(
+                egui::DragValue::new(&mut value)
+                    .range(0.5..=1000.0)
+                    .suffix(" m"),
+            );
+        }
+    });
+    if value == current.1 {
+        return;
+    }
+
+    let mut q_bodies = world.query_filtered::<&mut WaterBody, WithSelected>();
+    for mut body in q_bodies.iter_mut(world) {
+        match &mut body.shape {
+            WaterShape::Lake { level, .. } if is_lake => *level = value,
+            WaterShape::River { width, .. } if !is_lake => *width = value,
+            _ => (),
+        }
+    }
+}
+
+/// Shared with the map view
+pub(super) fn spline_draw_ui(ui: &mut egui::Ui, world: &mut World) {
+    let mut draw = world.resource_mut::<SplineDraw>();
+
+    let mut enabled = draw.enabled;
+    let mut kind = draw.kind;
+    ui.checkbox(&mut enabled, "Draw spline (N)")
+        .on_hover_text("LMB: add a point, Backspace: remove the last, Enter: finish, Esc: cancel");
+    egui::ComboBox::from_id_salt("spline_draw_kind")
+        .selected_text(kind.to_string())
+        .show_ui(ui, |ui| {
+            for option in SplineKind::ALL {
+                ui.selectable_value(&mut kind, option, option.to_string());
+            }
+        });
+    if draw.enabled {
+        ui.label(format!("{} points", draw.num_points()));
+    }
+
+    if draw.kind != kind {
+        draw.kind = kind;
+    }
+    if draw.enabled == enabled {
+        return;
+    }
+    if !enabled {
+        draw.cancel();
+        return;
+    }
+    draw.enabled = true;
+    world.resource_mut::<TerrainSculpt>().enabled = false;
+    world.resource_mut::<TerrainPaint>().enabled = false;
+    world.resource_mut::<TerrainWater>().flood_fill = false;
+}
+
+fn spline_ui(ui: &mut egui::Ui, world: &mut World) {
+    let mut q_splines = world.query_filtered::<(&Spline, Option<&SplineMesh>), WithSelected>();
+    let Some((current, mesh)) = q_splines
+        .iter(world)
+        .next()
+        .map(|(spline, mesh)| (spline.kind(), mesh.cloned()))
+    else {
+        return;
+    };
+
+    let mut kind = current;
+    let had_mesh = mesh.is_some();
+    let mut has_mesh = had_mesh;
+    let mut width = mesh.as_ref().map(SplineMesh::width);
+    let mut width_changed = false;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("Spline type")
+            .selected_text(kind.to_string())
+            .show_ui(ui, |ui| {
+                for option in SplineKind::ALL {
+                    ui.selectable_value(&mut kind, option, option.to_string());
+                }
+            })
+            .response
+            .on_hover_text("Converts the selected splines, keeping their shape where it can");
+        ui.checkbox(&mut has_mesh, "Road mesh");
+        if let Some(width) = &mut width {
+            width_changed = ui
+                .add(
+                    egui::DragValue::new(width)
+                        .range(0.5..=100.0)
+                        .prefix("width: ")
+                        .suffix(" m"),
+                )
+                .on_hover_text("Stretches the selected meshes' cross-sections, shoulders included")
+                .changed();
+        }
+    });
+
+    if kind != current {
+        let mut q_splines = world.query_filtered::<&mut Spline, WithSelected>();
+        for mut spline in q_splines.iter_mut(world) {
+            spline.set_kind(kind);
+        }
+    }
+    if let Some(width) = width
+        && width_changed
+    {
+        let mut q_meshes = world.query_filtered::<&mut SplineMesh, WithSelected>();
+        for mut mesh in q_meshes.iter_mut(world) {
+            mesh.set_width(width);
+        }
+    }
+    if has_mesh == had_mesh {
+        return;
+    }
+    let mut q_splines =
+        world.query_filtered::<(Entity, Has<SplineMesh>), (With<Spline>, WithSelected)>();
+    let entities: Vec<_> = q_splines.iter(world).collect();
+    for (entity, already_has_mesh) in entities {
+        let mut entity = world.entity_mut(entity);
+        // Meshes already there keep their own profiles
+        if !has_mesh {
+            entity.remove::<SplineMesh>();
+        } else if !already_has_mesh {
+            entity.insert(SplineMesh::road(8.0));
+        }
+    }
+}
+
 fn selection_ui(ui: &mut egui::Ui, world: &mut World) {
     let mut selection = world.query_filtered::<Entity, WithSelected>();
 
@@ -113,19 +693,31 @@ fn selection_ui(ui: &mut egui::Ui, world: &mut World) {
 }
 
 fn xform_ops_ui(ui: &mut egui::Ui, world: &mut World) {
-    let op = world.resource::<TransformAction>();
+    let op = *world.resource::<TransformAction>();
 
-    ui.label(format!("{}", *op));
+    ui.label(format!("{op}"));
 
     match op {
         TransformAction::None => {
             ui.label("G: Move");
             ui.label("R: Rotate");
             ui.label("S: Scale");
+            let has_terrain = world.contains_resource::<TerrainHeightmap>();
+            let drop = ui
+                .add_enabled(has_terrain, egui::Button::new("Drop to ground (End)"))
+                .clicked();
+            if drop && let Err(e) = world.run_system_cached(drop_selection_to_ground) {
+                error!("Drop to ground failed: {e}");
+            }
+        }
+        TransformAction::Move {
+            axis_lock, snap, ..
+        } => {
+            ui.label("Esc: cancel selection");
+            ui.label(format!("axis: {axis_lock}"));
+            ui.label(format!("T: snap: {snap}"));
         }
-        TransformAction::Move { axis_lock, .. }
-        | TransformAction::Rotate { axis_lock, .. }
-        | TransformAction::Scale(axis_lock) => {
+        TransformAction::Rotate { axis_lock, .. } | TransformAction::Scale(axis_lock) => {
             ui.label("Esc: cancel selection");
             ui.label(format!("axis: {axis_lock}"));
         }
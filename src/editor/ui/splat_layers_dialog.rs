@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use bevy_egui::egui;
+
+use worldedit::terrain_processing::splat::MAX_SPLAT_LAYERS;
+use worldedit::terrain_processing::splat::SplatLayer;
+use worldedit::terrain_processing::splat::SplatRule;
+
+use crate::editor::terrain_splat::TerrainSplatLayers;
+
+/// State of the splat layer window.
+#[derive(Resource, Default)]
+pub struct SplatLayersDialog {
+    pub open: bool,
+    /// Edited copy of the terrain's layers, each with its index in the terrain's list
+    draft: Option<Vec<(Option<usize>, SplatLayer)>>,
+}
+
+pub fn splat_layers_dialog_ui(ctx: &egui::Context, world: &mut World) {
+    if !world.resource::<SplatLayersDialog>().open {
+        return;
+    }
+    let Some(current) = world
+        .get_resource::<TerrainSplatLayers>()
+        .map(|splat| splat.layers.clone())
+    else {
+        return;
+    };
+
+    let mut dialog = world.resource_mut::<SplatLayersDialog>();
+    let dialog = &mut *dialog;
+    let draft = dialog.draft.get_or_insert_with(|| {
+        current
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, layer)| (Some(i), layer))
+            .collect()
+    });
+
+    let mut open = true;
+    let mut apply = false;
+    let mut regenerate = false;
+
+    egui::Window::new("Splat layers")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label("Later layers paint over earlier ones");
+            let mut remove = None;
+            let mut move_up = None;
+            for (i, (_, layer)) in draft.iter_mut().enumerate() {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut layer.name);
+                    if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                        move_up = Some(i);
+                    }
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                layer_ui(ui, i, layer);
+            }
+            if let Some(i) = remove {
+                draft.remove(i);
+            }
+            if let Some(i) = move_up {
+                draft.swap(i - 1, i);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let can_add = draft.len() < MAX_SPLAT_LAYERS;
+                if ui
+                    .add_enabled(can_add, egui::Button::new("Add layer"))
+                    .clicked()
+                {
+                    let name = format!("Layer {}", draft.len() + 1);
+                    draft.push((None, SplatLayer::new(name, SplatRule::ANY)));
+                }
+                let edited = !draft.iter().map(|(_, layer)| layer).eq(&current);
+                apply = ui
+                    .add_enabled(edited, egui::Button::new("Apply"))
+                    .on_hover_text("Use these layers and save them to the project")
+                    .clicked();
+                regenerate = ui
+                    .button("Reset from rules")
+                    .on_hover_text(
+                        "Recompute all splat maps from the layer rules, losing painted weights",
+                    )
+                    .clicked();
+            });
+        });
+
+    let layers = (apply || regenerate).then(|| draft.clone());
+    if !open {
+        // Start from the terrain's layers next time
+        *dialog = SplatLayersDialog::default();
+    } else if layers.is_some() {
+        // Picked up again from the applied layers, at their new indices
+        dialog.draft = None;
+    }
+    let Some(layers) = layers else {
+        return;
+    };
+
+    let (sources, layers): (Vec<_>, Vec<_>) = layers.into_iter().unzip();
+    let mut splat = world.resource_mut::<TerrainSplatLayers>();
+    if splat.layers != layers {
+        splat.set_layers(layers, sources);
+    }
+    if regenerate {
+        splat.regenerate = true;
+    }
+}
+
+fn layer_ui(ui: &mut egui::Ui, index: usize, layer: &mut SplatLayer) {
+    egui::Grid::new(("splat_layer", index))
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Texture");
+            let mut texture = layer
+                .texture
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let response = ui
+                .text_edit_singleline(&mut texture)
+                .on_hover_text("Relative to the project directory. Empty for a plain color.");
+            if response.changed() {
+                layer.texture = (!texture.is_empty()).then(|| PathBuf::from(texture));
+            }
+            ui.end_row();
+
+            ui.label("Tile size");
+            ui.add(
+                egui::DragValue::new(&mut layer.tile_size)
+                    .range(0.1..=1024.0)
+                    .speed(0.1)
+                    .suffix(" m"),
+            );
+            ui.end_row();
+
+            let rule = &mut layer.rule;
+            ui.label("Height");
+            ui.horizontal(|ui| {
+                bound_ui(ui, "min", &mut rule.min_height, f32::NEG_INFINITY);
+                bound_ui(ui, "max", &mut rule.max_height, f32::INFINITY);
+                ui.add(
+                    egui::DragValue::new(&mut rule.height_blend)
+                        .range(0.0..=f32::MAX)
+                        .prefix("blend: ")
+                        .suffix(" m"),
+                );
+            });
+            ui.end_row();
+
+            ui.label("Slope");
+            ui.horizontal(|ui| {
+                ui.label("min");
+                ui.drag_angle(&mut rule.min_slope);
+                let mut limited = rule.max_slope.is_finite();
+                ui.checkbox(&mut limited, "max");
+                if limited != rule.max_slope.is_finite() {
+                    rule.max_slope = match limited {
+                        true => std::f32::consts::FRAC_PI_2,
+                        false => f32::INFINITY,
+                    };
+                }
+                if limited {
+                    ui.drag_angle(&mut rule.max_slope);
+                }
+                ui.label("blend");
+                ui.drag_angle(&mut rule.slope_blend);
+            });
+            ui.end_row();
+        });
+}
+
+/// Height bound that can be switched off, which is `unbounded`
+fn bound_ui(ui: &mut egui::Ui, label: &str, value: &mut f32, unbounded: f32) {
+    let mut limited = value.is_finite();
+    ui.checkbox(&mut limited, label);
+    if limited != value.is_finite() {
+        *value = if limited { 0.0 } else { unbounded };
+    }
+    if limited {
+        ui.add(egui::DragValue::new(value).suffix(" m"));
+    }
+}
@@ -13,11 +13,13 @@ use bevy_egui::egui::Frame;
 use bevy_egui::egui::Ui;
 use egui_tiles::{Behavior, Container, SimplificationOptions, Tile, TileId, Tiles, Tree};
 
+use super::import_dialog::import_dialog_ui;
 use super::panes::EditorPane;
 
 use super::panes::MapViewPane;
 use super::panes::OutlinerPane;
 use super::panes::ViewportPane;
+use super::splat_layers_dialog::splat_layers_dialog_ui;
 
 #[derive(Debug, Resource)]
 pub enum TilingPane {
@@ -120,6 +122,8 @@ fn draw_editor_ui(world: &mut World) {
             tree.0.ui(&mut behavior, ui);
         });
     });
+    import_dialog_ui(ctx, world);
+    splat_layers_dialog_ui(ctx, world);
 
     queue.apply(world);
 }
@@ -0,0 +1,400 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::Task;
+use bevy::tasks::futures::check_ready;
+
+use worldedit::terrain_processing::PROJECT_DIR;
+use worldedit::terrain_processing::water::WaterSettings;
+use worldedit::terrain_processing::water::WaterShape;
+use worldedit::terrain_processing::water::flood_fill_lake;
+
+use crate::editor::Colors;
+use crate::editor::Selectable;
+use crate::editor::selection_actions::SelectionActionState;
+use crate::editor::terrain_cell_preview::TerrainCrunchConfig;
+use crate::editor::terrain_cell_preview::TerrainHeightmap;
+use crate::editor::terrain_cell_preview::TerrainPreviewStatus;
+use crate::editor::terrain_cell_preview::load_project_file;
+use crate::editor::terrain_cell_preview::terrain_origin;
+use crate::editor::terrain_raycast::TerrainRaycast;
+use crate::editor::terrain_sculpt::TerrainSculpt;
+use crate::editor::terrain_sculpt::draw_brush;
+use crate::editor::terrain_splat::TerrainPaint;
+use crate::editor::ui::hotkeys_enabled;
+use crate::spline::Spline;
+
+/// Flood fills larger than this are taken as leaking into the open
+const MAX_LAKE_TEXELS: usize = 1 << 20;
+/// The sea plane reaches this many world sizes across, past the terrain edges
+const SEA_EXTENT: f32 = 3.0;
+/// Curve samples per spline segment when a water body follows a spline
+const SPLINE_SAMPLES: usize = 16;
+
+type SplineOrTransformChanged = Or<(Changed<Spline>, Changed<Transform>)>;
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TerrainWater {
+            settings: load_project_file(WaterSettings::FILE_NAME, WaterSettings::load)
+                .unwrap_or_default(),
+            flood_fill: false,
+            river_width: 8.0,
+            lake_level: 0.0,
+            unsaved: false,
+        });
+        app.add_systems(Startup, (setup_water, load_water_bodies));
+        app.add_systems(
+            Update,
+            (
+                update_sea,
+                follow_source_splines,
+                build_water_meshes,
+                toggle_flood_fill.run_if(hotkeys_enabled),
+                flood_fill_tool,
+                save_water_bodies,
+                poll_save_task,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Sea level and water tool settings.
+#[derive(Resource, Debug)]
+pub struct TerrainWater {
+    pub settings: WaterSettings,
+    /// Click on the terrain to fill the basin there with a lake
+    pub flood_fill: bool,
+    /// Width of rivers made from splines
+    pub river_width: f32,
+    /// World height of lakes made from splines
+    pub lake_level: f32,
+    /// Water bodies changed since they were last saved
+    unsaved: bool,
+}
+
+impl TerrainWater {
+    pub fn save_settings(&self) {
+        let path = Path::new(PROJECT_DIR).join(WaterSettings::FILE_NAME);
+        if let Err(e) = self.settings.save(&path) {
+            error!("Failed to save '{}': {e}", path.display());
+        }
+    }
+}
+
+/// Lake or river. Its entity is placed at the terrain origin.
+#[derive(Component, Debug, Clone)]
+pub struct WaterBody {
+    pub shape: WaterShape,
+    /// Spline the shape follows. Not saved, so loaded bodies stay where they are.
+    pub source: Option<Entity>,
+}
+
+/// Water body read from the project file, so it doesn't need saving as it spawns
+#[derive(Component)]
+struct LoadedWaterBody;
+
+impl WaterBody {
+    /// Lake inside a spline, with its surface at `level`
+    pub fn lake_from_spline(
+        entity: Entity,
+        spline: &Spline,
+        xform: &Transform,
+        origin: Vec3,
+        level: f32,
+    ) -> Self {
+        let path = spline_path(spline, xform, origin);
+        Self {
+            shape: WaterShape::Lake {
+                outline: path.iter().map(|p| p.xz()).collect(),
+                level,
+            },
+            source: Some(entity),
+        }
+    }
+
+    pub fn river_from_spline(
+        entity: Entity,
+        spline: &Spline,
+        xform: &Transform,
+        origin: Vec3,
+        width: f32,
+    ) -> Self {
+        Self {
+            shape: WaterShape::River {
+                path: spline_path(spline, xform, origin),
+                width,
+            },
+            source: Some(entity),
+        }
+    }
+
+    /// Follows the new shape of the source spline, keeping level and width
+    fn rebuild(&mut self, spline: &Spline, xform: &Transform, origin: Vec3) {
+        let Some(entity) = self.source else {
+            return;
+        };
+        *self = match self.shape {
+            WaterShape::Lake { level, .. } => {
+                Self::lake_from_spline(entity, spline, xform, origin, level)
+            }
+            WaterShape::River { width, .. } => {
+                Self::river_from_spline(entity, spline, xform, origin, width)
+            }
+            WaterShape::Basin(_) => return,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.shape {
+            WaterShape::Basin(_) | WaterShape::Lake { .. } => "Lake",
+            WaterShape::River { .. } => "River",
+        }
+    }
+}
+
+#[derive(Component)]
+struct SaveWaterTask(PathBuf, Task<std::io::Result<()>>);
+
+fn spline_path(spline: &Spline, xform: &Transform, origin: Vec3) -> Vec<Vec3> {
+    spline
+        .curve()
+        .iter_positions(SPLINE_SAMPLES)
+        .map(|p| *xform * p - origin)
+        .collect()
+}
+
+#[derive(Resource)]
+struct WaterMaterial(Handle<StandardMaterial>);
+
+#[derive(Component)]
+struct SeaPlane;
+
+fn setup_water(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: Colors::WATER.with_alpha(0.7).into(),
+        perceptual_roughness: 0.1,
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    });
+    commands.spawn((
+        Name::new("Sea"),
+        SeaPlane,
+        Mesh3d(meshes.add(Plane3d::default().mesh().size(1.0, 1.0))),
+        MeshMaterial3d(material.clone()),
+        Visibility::Hidden,
+    ));
+    commands.insert_resource(WaterMaterial(material));
+}
+
+fn update_sea(
+    water: Res<TerrainWater>,
+    heightmap: Option<Res<TerrainHeightmap>>,
+    mut q_sea: Query<(&mut Transform, &mut Visibility), With<SeaPlane>>,
+) {
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+    if !water.is_changed() && !heightmap.is_added() {
+        return;
+    }
+
+    let world_size = heightmap.0.world().world_size as f32;
+    for (mut xform, mut visibility) in q_sea.iter_mut() {
+        *xform = Transform::from_xyz(0.0, water.settings.sea_level, 0.0)
+            .with_scale(Vec3::splat(world_size * SEA_EXTENT));
+        *visibility = match water.settings.show_sea {
+            true => Visibility::Inherited,
+            false => Visibility::Hidden,
+        };
+    }
+}
+
+/// Water bodies saved with the project
+fn load_water_bodies(mut commands: Commands, config: Res<TerrainCrunchConfig>) {
+    let Some(shapes) = load_project_file(WaterShape::FILE_NAME, WaterShape::load_all) else {
+        return;
+    };
+    let origin = terrain_origin(&config.0.world);
+    for shape in shapes {
+        let body = WaterBody {
+            shape,
+            source: None,
+        };
+        commands.spawn((
+            Name::new(body.name()),
+            body,
+            Transform::from_translation(origin),
+            Selectable,
+            LoadedWaterBody,
+        ));
+    }
+}
+
+/// Reshapes water bodies whose spline was edited or moved
+fn follow_source_splines(
+    heightmap: Option<Res<TerrainHeightmap>>,
+    q_splines: Query<(&Spline, &Transform), SplineOrTransformChanged>,
+    mut q_bodies: Query<&mut WaterBody>,
+) {
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+    let origin = terrain_origin(heightmap.0.world());
+
+    for mut body in q_bodies.iter_mut() {
+        let Some((spline, xform)) = body.source.and_then(|entity| q_splines.get(entity).ok())
+        else {
+            continue;
+        };
+        body.rebuild(spline, xform, origin);
+    }
+}
+
+fn build_water_meshes(
+    mut commands: Commands,
+    heightmap: Option<Res<TerrainHeightmap>>,
+    material: Res<WaterMaterial>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut water: ResMut<TerrainWater>,
+    q_bodies: Query<(Entity, Ref<WaterBody>, Has<LoadedWaterBody>)>,
+    mut removed: RemovedComponents<WaterBody>,
+) {
+    // Bypassed so the flag alone doesn't rerun `update_sea`
+    if removed.read().count() > 0 {
+        water.bypass_change_detection().unsaved = true;
+    }
+    let Some(heightmap) = heightmap else {
+        return;
+    };
+    let texel_size = heightmap.0.texel_size();
+
+    for (entity, body, loaded) in q_bodies.iter() {
+        if !body.is_changed() && !heightmap.is_added() {
+            continue;
+        }
+        let edited = body.is_changed() && !(body.is_added() && loaded);
+        if edited && !water.unsaved {
+            water.bypass_change_detection().unsaved = true;
+        }
+        let mesh = body.shape.mesh(texel_size);
+        commands.entity(entity).insert((
+            Mesh3d(meshes.add(mesh.bevy_mesh())),
+            MeshMaterial3d(material.0.clone()),
+        ));
+    }
+}
+
+fn toggle_flood_fill(
+    mut water: ResMut<TerrainWater>,
+    mut sculpt: ResMut<TerrainSculpt>,
+    mut paint: ResMut<TerrainPaint>,
+    selection_state: Res<SelectionActionState>,
+    kb: Res<ButtonInput<KeyCode>>,
+) {
+    if *selection_state == SelectionActionState::None && kb.just_pressed(KeyCode::KeyL) {
+        water.flood_fill = !water.flood_fill;
+        // One tool at a time
+        if water.flood_fill {
+            sculpt.enabled = false;
+            paint.enabled = false;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn flood_fill_tool(
+    mut commands: Commands,
+    water: Res<TerrainWater>,
+    sculpt: Res<TerrainSculpt>,
+    paint: Res<TerrainPaint>,
+    raycast: TerrainRaycast,
+    selection_state: Res<SelectionActionState>,
+    mb: Res<ButtonInput<MouseButton>>,
+    mut gizmos: Gizmos,
+) {
+    if !water.flood_fill
+        || sculpt.enabled
+        || paint.enabled
+        || *selection_state != SelectionActionState::None
+    {
+        return;
+    }
+    let (Some(h_bundle), Some(hit)) = (raycast.heightmap(), raycast.cursor_hit()) else {
+        return;
+    };
+    let hit = hit.local_position;
+
+    draw_brush(
+        &mut gizmos,
+        h_bundle,
+        h_bundle.texel_size().x * 2.0,
+        hit.xz(),
+    );
+    if !mb.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let Some(extent) = flood_fill_lake(h_bundle, hit.xz(), MAX_LAKE_TEXELS) else {
+        info!("No basin to fill at {}", hit.xz());
+        return;
+    };
+    commands.spawn((
+        Name::new("Lake"),
+        WaterBody {
+            shape: WaterShape::Basin(extent),
+            source: None,
+        },
+        Transform::from_translation(terrain_origin(h_bundle.world())),
+        Selectable,
+    ));
+}
+
+/// Saves all water bodies once nothing is being dragged
+fn save_water_bodies(
+    mut commands: Commands,
+    mut water: ResMut<TerrainWater>,
+    q_bodies: Query<&WaterBody>,
+    q_tasks: Query<(), With<SaveWaterTask>>,
+    mb: Res<ButtonInput<MouseButton>>,
+) {
+    if !water.unsaved || mb.pressed(MouseButton::Left) || !q_tasks.is_empty() {
+        return;
+    }
+    water.bypass_change_detection().unsaved = false;
+
+    let shapes: Vec<_> = q_bodies.iter().map(|body| body.shape.clone()).collect();
+    let path = Path::new(PROJECT_DIR).join(WaterShape::FILE_NAME);
+    let task_path = path.clone();
+    let task =
+        AsyncComputeTaskPool::get().spawn(async move { WaterShape::save_all(&task_path, &shapes) });
+    commands.spawn(SaveWaterTask(path, task));
+}
+
+fn poll_save_task(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut SaveWaterTask)>,
+    mut status: ResMut<TerrainPreviewStatus>,
+) {
+    for (entity, mut task) in q_tasks.iter_mut() {
+        let Some(result) = check_ready(&mut task.1) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+
+        if let Err(e) = result {
+            error!("Failed to save '{}': {e}", task.0.display());
+            status.error = Some(e.to_string());
+        }
+    }
+}
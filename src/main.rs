@@ -1,5 +1,6 @@
 mod editor;
 mod spline;
+mod spline_mesh;
 
 use bevy::camera::visibility::RenderLayers;
 use bevy::math::vec3;
@@ -13,11 +14,14 @@ use bevy_egui::EguiGlobalSettings;
 use bevy_egui::PrimaryEguiContext;
 
 use editor::Selectable;
+use spline::ControlPoint;
 use spline::Spline;
+use spline::SplineKind;
 
 use bevy::pbr::wireframe::WireframePlugin;
 use editor::EditorPlugin;
 use spline::SplinePlugin;
+use spline_mesh::SplineMeshPlugin;
 
 fn main() {
     App::new()
@@ -43,6 +47,7 @@ fn main() {
         .add_plugins(HelloPlugin)
         .add_plugins(EditorPlugin)
         .add_plugins(SplinePlugin)
+        .add_plugins(SplineMeshPlugin)
         .run();
 }
 
@@ -57,17 +62,25 @@ impl Plugin for HelloPlugin {
 fn setup(mut commands: Commands, mut egui_global_settings: ResMut<EguiGlobalSettings>) {
     egui_global_settings.auto_create_primary_context = false;
 
-    let points = [[
-        vec3(-6., 0., 0.),
-        vec3(4., 0., 0.),
-        vec3(-4., 4., 0.),
-        vec3(6., 0., 0.),
-    ]];
-
-    let bezier = CubicBezier::new(points).to_curve().unwrap();
+    let bezier = Spline::new(
+        SplineKind::Bezier,
+        vec![
+            ControlPoint {
+                position: vec3(-6., 0., 0.),
+                handle_in: Vec3::ZERO,
+                handle_out: vec3(10., 0., 0.),
+            },
+            ControlPoint {
+                position: vec3(6., 0., 0.),
+                handle_in: vec3(-10., 4., 0.),
+                handle_out: Vec3::ZERO,
+            },
+        ],
+    )
+    .unwrap();
 
     commands.spawn((
-        Spline { curve: bezier },
+        bezier,
         Name::new("bezier"),
         Selectable,
         Transform::default().with_translation(Vec3::new(0.0, 1.0, 0.0)),
@@ -1,11 +1,378 @@
 use bevy::{color::palettes::css::WHITE, prelude::*};
 
+use bevy::math::cubic_splines::LinearSpline;
+use derive_more::Display;
+
+/// Arc length table samples per curve segment
+const LUT_SAMPLES_PER_SEGMENT: usize = 64;
+/// Longest drawn line piece
+const DRAW_STEP: f32 = 0.5;
+/// Most line pieces drawn per spline
+const MAX_DRAW_STEPS: f32 = 512.0;
+
+/// How a [Spline] interpolates its control points.
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SplineKind {
+    /// Through every point, shaped by both tangent handles
+    #[default]
+    Bezier,
+    /// Through every point, smooth on its own
+    #[display("Catmull-Rom")]
+    CatmullRom,
+    /// Smoothest, but only near its points, not through them
+    #[display("B-spline")]
+    BSpline,
+    /// Through every point, with one explicit tangent each
+    Hermite,
+    /// Straight lines between points
+    Polyline,
+}
+
+impl SplineKind {
+    pub const ALL: [Self; 5] = [
+        Self::Bezier,
+        Self::CatmullRom,
+        Self::BSpline,
+        Self::Hermite,
+        Self::Polyline,
+    ];
+}
+
+/// Authoring point of a [Spline], in spline space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControlPoint {
+    pub position: Vec3,
+    /// Tangent handles, relative to `position`. Bezier uses both, Hermite only the
+    /// outgoing one, the other kinds none.
+    pub handle_in: Vec3,
+    pub handle_out: Vec3,
+}
+
+impl ControlPoint {
+    /// Without tangents
+    pub fn new(position: Vec3) -> Self {
+        Self {
+            position,
+            handle_in: Vec3::ZERO,
+            handle_out: Vec3::ZERO,
+        }
+    }
+}
+
+/// Position and orientation at some distance along a [Spline], in spline space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplineFrame {
+    /// From the start, along the curve
+    pub distance: f32,
+    pub position: Vec3,
+    /// Unit length, in the direction of travel
+    pub tangent: Vec3,
+    /// Unit length, perpendicular to the tangent. Twists as little as possible along the
+    /// curve, starting out as close to +Y as it can.
+    pub up: Vec3,
+}
+
+/// Arc length table entry
+#[derive(Debug, Clone, Copy)]
+struct LutEntry {
+    /// Curve parameter
+    t: f32,
+    frame: SplineFrame,
+}
+
+/// Curve through its control points. The curve and its arc length table are rebuilt
+/// whenever the points change.
 #[derive(Component)]
 pub struct Spline {
-    //pub name: Name,
-    //pub selectable: Selectable,
-    //pub transform: Transform,
-    pub curve: CubicCurve<Vec3>,
+    kind: SplineKind,
+    points: Vec<ControlPoint>,
+    curve: CubicCurve<Vec3>,
+    lut: Vec<LutEntry>,
+}
+
+impl Spline {
+    /// `None` with too few points for `kind`: two, or four for B-splines
+    pub fn new(kind: SplineKind, points: Vec<ControlPoint>) -> Option<Self> {
+        let curve = build_curve(kind, &points)?;
+        let lut = build_lut(&curve);
+        Some(Self {
+            kind,
+            points,
+            curve,
+            lut,
+        })
+    }
+
+    pub fn kind(&self) -> SplineKind {
+        self.kind
+    }
+
+    pub fn points(&self) -> &[ControlPoint] {
+        &self.points
+    }
+
+    pub fn curve(&self) -> &CubicCurve<Vec3> {
+        &self.curve
+    }
+
+    pub fn set_point(&mut self, index: usize, point: ControlPoint) {
+        self.points[index] = point;
+        self.curve = build_curve(self.kind, &self.points).expect("points were valid before");
+        self.lut = build_lut(&self.curve);
+    }
+
+    /// Along the curve
+    pub fn length(&self) -> f32 {
+        self.lut.last().map_or(0.0, |entry| entry.frame.distance)
+    }
+
+    /// Frame `distance` along the curve, clamped to its ends
+    pub fn sample_at_distance(&self, distance: f32) -> SplineFrame {
+        let distance = distance.clamp(0.0, self.length());
+        let i = self
+            .lut
+            .partition_point(|entry| entry.frame.distance < distance);
+        let Some(before) = i.checked_sub(1).map(|i| self.lut[i]) else {
+            return self.lut[0].frame;
+        };
+        let after = self.lut[i];
+
+        let span = after.frame.distance - before.frame.distance;
+        let f = match span > 0.0 {
+            true => (distance - before.frame.distance) / span,
+            false => 0.0,
+        };
+        let t = before.t.lerp(after.t, f);
+        let position = self.curve.position(t);
+        let tangent = tangent_at(&self.curve, t);
+        SplineFrame {
+            distance,
+            position,
+            tangent,
+            up: transport_up(&before.frame, position, tangent),
+        }
+    }
+
+    /// Frames every `step` along the curve, from the start. The end is only included when
+    /// the length is a multiple of `step`.
+    pub fn iter_equidistant(&self, step: f32) -> impl Iterator<Item = SplineFrame> + '_ {
+        assert!(step > 0.0, "step must be positive, got {step}");
+        let num_steps = (self.length() / step + 1e-4).floor() as usize;
+        (0..=num_steps).map(move |i| self.sample_at_distance(i as f32 * step))
+    }
+
+    /// Points to draw the curve through, including its end
+    pub fn draw_points(&self) -> impl Iterator<Item = Vec3> + '_ {
+        let step = DRAW_STEP.max(self.length() / MAX_DRAW_STEPS);
+        let end = self.sample_at_distance(self.length());
+        self.iter_equidistant(step)
+            .chain(std::iter::once(end))
+            .map(|frame| frame.position)
+    }
+
+    /// Replaces the points with ones that make the same curve as closely as `kind` can.
+    /// Converting to Bezier is exact, and so is Hermite for smooth curves.
+    pub fn set_kind(&mut self, kind: SplineKind) {
+        if kind == self.kind {
+            return;
+        }
+        let segments = self.curve.segments();
+        let points = match kind {
+            SplineKind::Bezier => bezier_points(segments),
+            SplineKind::Hermite => bezier_points(segments)
+                .into_iter()
+                .map(|point| {
+                    // Hermite only has the one tangent, kinks get smoothed out
+                    let tangent = match point.handle_out == Vec3::ZERO {
+                        true => -point.handle_in,
+                        false => point.handle_out,
+                    };
+                    ControlPoint {
+                        handle_in: -tangent,
+                        handle_out: tangent,
+                        ..point
+                    }
+                })
+                .collect(),
+            SplineKind::CatmullRom | SplineKind::Polyline => {
+                knots(segments).into_iter().map(ControlPoint::new).collect()
+            }
+            SplineKind::BSpline => interpolating_b_spline(&knots(segments))
+                .into_iter()
+                .map(ControlPoint::new)
+                .collect(),
+        };
+        *self = Self::new(kind, points).expect("conversions keep enough points");
+    }
+}
+
+fn build_curve(kind: SplineKind, points: &[ControlPoint]) -> Option<CubicCurve<Vec3>> {
+    let positions = points.iter().map(|point| point.position);
+    match kind {
+        // One segment between each pair of neighbouring points
+        SplineKind::Bezier => CubicBezier::new(points.windows(2).map(|pair| {
+            let (a, b) = (pair[0], pair[1]);
+            [
+                a.position,
+                a.position + a.handle_out,
+                b.position + b.handle_in,
+                b.position,
+            ]
+        }))
+        .to_curve()
+        .ok(),
+        SplineKind::CatmullRom => CubicCardinalSpline::new_catmull_rom(positions)
+            .to_curve()
+            .ok(),
+        SplineKind::BSpline => CubicBSpline::new(positions).to_curve().ok(),
+        // Handles are a third of the velocity, like bezier handles
+        SplineKind::Hermite => {
+            let tangents = points.iter().map(|point| point.handle_out * 3.0);
+            CubicHermite::new(positions, tangents).to_curve().ok()
+        }
+        SplineKind::Polyline => LinearSpline::new(positions).to_curve().ok(),
+    }
+}
+
+/// Frames along the curve, evenly spaced in curve parameter
+fn build_lut(curve: &CubicCurve<Vec3>) -> Vec<LutEntry> {
+    let num_samples = curve.segments().len() * LUT_SAMPLES_PER_SEGMENT;
+    let max_t = curve.segments().len() as f32;
+
+    let first = {
+        let tangent = tangent_at(curve, 0.0);
+        // +Y unless the curve starts out vertical
+        let reference = match tangent.dot(Vec3::Y).abs() < 0.999 {
+            true => Vec3::Y,
+            false => Vec3::NEG_Z,
+        };
+        SplineFrame {
+            distance: 0.0,
+            position: curve.position(0.0),
+            tangent,
+            up: reference.reject_from_normalized(tangent).normalize(),
+        }
+    };
+    let mut lut = Vec::with_capacity(num_samples + 1);
+    lut.push(LutEntry {
+        t: 0.0,
+        frame: first,
+    });
+
+    for i in 1..=num_samples {
+        let t = max_t * i as f32 / num_samples as f32;
+        let previous = lut[i - 1].frame;
+        let position = curve.position(t);
+        let tangent = tangent_at(curve, t);
+        lut.push(LutEntry {
+            t,
+            frame: SplineFrame {
+                distance: previous.distance + previous.position.distance(position),
+                position,
+                tangent,
+                up: transport_up(&previous, position, tangent),
+            },
+        });
+    }
+    lut
+}
+
+/// Direction of travel, even where the curve stops for a moment, like at bezier points
+/// without handles
+fn tangent_at(curve: &CubicCurve<Vec3>, t: f32) -> Vec3 {
+    const DT: f32 = 1e-3;
+    let max_t = curve.segments().len() as f32;
+    curve.velocity(t).try_normalize().unwrap_or_else(|| {
+        let ahead = curve.position((t + DT).min(max_t));
+        let behind = curve.position((t - DT).max(0.0));
+        (ahead - behind).normalize_or(Vec3::X)
+    })
+}
+
+/// Up vector of `from` carried to the next frame without twisting it: the double
+/// reflection method for rotation minimizing frames (Wang et al. 2008)
+fn transport_up(from: &SplineFrame, position: Vec3, tangent: Vec3) -> Vec3 {
+    let reflect = |v: Vec3, normal: Vec3| {
+        let c = normal.length_squared();
+        match c > 1e-12 {
+            true => v - normal * (2.0 * normal.dot(v) / c),
+            false => v,
+        }
+    };
+    // Across the plane between the two positions, then the one between the tangents
+    let step = position - from.position;
+    let up = reflect(from.up, step);
+    let reflected_tangent = reflect(from.tangent, step);
+    let up = reflect(up, tangent - reflected_tangent);
+    // Keep it perpendicular despite rounding
+    up.reject_from_normalized(tangent)
+        .try_normalize()
+        .unwrap_or(from.up)
+}
+
+/// Where the segments start and end
+fn knots(segments: &[CubicSegment<Vec3>]) -> Vec<Vec3> {
+    let last = segments.last().map(|segment| segment.position(1.0));
+    segments
+        .iter()
+        .map(|segment| segment.position(0.0))
+        .chain(last)
+        .collect()
+}
+
+/// Bezier control points for the segments, which can be any cubic
+fn bezier_points(segments: &[CubicSegment<Vec3>]) -> Vec<ControlPoint> {
+    (0..=segments.len())
+        .map(|i| {
+            let before = i.checked_sub(1).map(|j| &segments[j]);
+            let after = segments.get(i);
+            let position = match (before, after) {
+                (_, Some(after)) => after.position(0.0),
+                (Some(before), None) => before.position(1.0),
+                (None, None) => unreachable!("curves have at least one segment"),
+            };
+            ControlPoint {
+                position,
+                handle_in: before.map_or(Vec3::ZERO, |s| -s.velocity(1.0) / 3.0),
+                handle_out: after.map_or(Vec3::ZERO, |s| s.velocity(0.0) / 3.0),
+            }
+        })
+        .collect()
+}
+
+/// B-spline control points whose curve passes through `knots`, as a natural cubic spline:
+/// no curvature at the ends.
+fn interpolating_b_spline(knots: &[Vec3]) -> Vec<Vec3> {
+    // Each knot is (P[i-1] + 4 P[i] + P[i+1]) / 6 with the control points P. The end
+    // knots are control points, with mirrored ones beyond them, leaving a tridiagonal
+    // system for the interior, solved with the Thomas algorithm.
+    let n = knots.len();
+    let mut points = vec![Vec3::ZERO; n];
+    points[0] = knots[0];
+    points[n - 1] = knots[n - 1];
+    if n > 2 {
+        let interior = n - 2;
+        let mut diagonal = vec![4.0; interior];
+        let mut rhs: Vec<Vec3> = knots[1..n - 1].iter().map(|knot| knot * 6.0).collect();
+        rhs[0] -= points[0];
+        rhs[interior - 1] -= points[n - 1];
+        for i in 1..interior {
+            let factor = 1.0 / diagonal[i - 1];
+            diagonal[i] -= factor;
+            rhs[i] = rhs[i] - rhs[i - 1] * factor;
+        }
+        points[n - 2] = rhs[interior - 1] / diagonal[interior - 1];
+        for i in (0..interior - 1).rev() {
+            points[i + 1] = (rhs[i] - points[i + 2]) / diagonal[i];
+        }
+    }
+
+    let first = points[0] * 2.0 - points[1];
+    let last = points[n - 1] * 2.0 - points[n - 2];
+    std::iter::once(first)
+        .chain(points)
+        .chain(std::iter::once(last))
+        .collect()
 }
 
 pub struct SplinePlugin;
@@ -18,16 +385,182 @@ impl Plugin for SplinePlugin {
 
 fn draw(query: Query<(&Spline, &Transform)>, mut gizmos: Gizmos) {
     for (spline, xform) in &query {
-        gizmos.linestrip(
-            spline.curve.iter_positions(50).map(|val| *xform * val),
-            WHITE,
-        );
+        gizmos.linestrip(spline.draw_points().map(|p| *xform * p), WHITE);
     }
 }
 
-/*
-impl Default for Spline {
-    fn default() -> Self {
-        Self {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wavy() -> Spline {
+        let points = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(4.0, 2.0, 0.0),
+            vec3(8.0, -1.0, 3.0),
+            vec3(12.0, 0.0, 1.0),
+        ];
+        Spline::new(
+            SplineKind::CatmullRom,
+            points.into_iter().map(ControlPoint::new).collect(),
+        )
+        .unwrap()
     }
-}*/
+
+    fn assert_same_curve(a: &Spline, b: &Spline) {
+        let samples = |spline: &Spline| -> Vec<Vec3> {
+            // Compare along the whole curve, not per segment
+            let max_t = spline.curve().segments().len() as f32;
+            (0..=64)
+                .map(|i| spline.curve().position(max_t * i as f32 / 64.0))
+                .collect()
+        };
+        for (p, q) in samples(a).into_iter().zip(samples(b)) {
+            assert!(p.distance(q) < 1e-3, "{p} != {q}");
+        }
+    }
+
+    #[test]
+    fn test_convert_to_bezier_and_hermite_is_exact() {
+        let original = wavy();
+        for kind in [SplineKind::Bezier, SplineKind::Hermite] {
+            let mut converted = wavy();
+            converted.set_kind(kind);
+            assert_eq!(converted.kind(), kind);
+            assert_eq!(converted.points().len(), 4);
+            assert_same_curve(&original, &converted);
+        }
+    }
+
+    #[test]
+    fn test_b_spline_passes_through_knots() {
+        let mut spline = wavy();
+        spline.set_kind(SplineKind::BSpline);
+        assert_eq!(spline.points().len(), 6);
+        for (i, point) in wavy().points().iter().enumerate() {
+            let on_curve = spline.curve().position(i as f32);
+            assert!(on_curve.distance(point.position) < 1e-4, "{i}: {on_curve}");
+        }
+
+        // And back, through the same points
+        spline.set_kind(SplineKind::Polyline);
+        assert_eq!(spline.points().len(), 4);
+        assert!(spline.points()[2].position.distance(vec3(8.0, -1.0, 3.0)) < 1e-4);
+    }
+
+    #[test]
+    fn test_too_few_points() {
+        let points = vec![ControlPoint::new(Vec3::ZERO), ControlPoint::new(Vec3::X)];
+        assert!(Spline::new(SplineKind::BSpline, points.clone()).is_none());
+        assert!(Spline::new(SplineKind::Polyline, points[..1].to_vec()).is_none());
+        let mut spline = Spline::new(SplineKind::Polyline, points).unwrap();
+        spline.set_kind(SplineKind::BSpline);
+        assert_eq!(spline.points().len(), 4);
+    }
+
+    fn polyline(points: &[Vec3]) -> Spline {
+        let points = points.iter().copied().map(ControlPoint::new).collect();
+        Spline::new(SplineKind::Polyline, points).unwrap()
+    }
+
+    /// y = x², x from 0 to 1, in the XY plane
+    fn parabola() -> Spline {
+        let points = vec![
+            ControlPoint {
+                position: Vec3::ZERO,
+                handle_in: Vec3::ZERO,
+                handle_out: vec3(1.0 / 3.0, 0.0, 0.0),
+            },
+            ControlPoint {
+                position: vec3(1.0, 1.0, 0.0),
+                handle_in: vec3(-1.0 / 3.0, -2.0 / 3.0, 0.0),
+                handle_out: Vec3::ZERO,
+            },
+        ];
+        Spline::new(SplineKind::Bezier, points).unwrap()
+    }
+
+    #[test]
+    fn test_length() {
+        let line = polyline(&[Vec3::ZERO, vec3(3.0, 4.0, 0.0), vec3(3.0, 4.0, 12.0)]);
+        assert!((line.length() - 17.0).abs() < 1e-4, "{}", line.length());
+
+        // ∫ sqrt(1 + 4x²) dx
+        let expected = 5f32.sqrt() / 2.0 + 2f32.asinh() / 4.0;
+        let length = parabola().length();
+        assert!((length - expected).abs() < 1e-3, "{length} != {expected}");
+    }
+
+    #[test]
+    fn test_sample_at_distance() {
+        let line = polyline(&[Vec3::ZERO, vec3(10.0, 0.0, 0.0)]);
+        let frame = line.sample_at_distance(2.5);
+        assert!(frame.position.distance(vec3(2.5, 0.0, 0.0)) < 1e-4);
+        assert!(frame.tangent.abs_diff_eq(Vec3::X, 1e-4));
+        assert!(frame.up.abs_diff_eq(Vec3::Y, 1e-4));
+        assert!(frame.tangent.cross(frame.up).abs_diff_eq(Vec3::Z, 1e-4));
+        assert_eq!(line.sample_at_distance(-1.0).position, Vec3::ZERO);
+        assert!(
+            line.sample_at_distance(11.0)
+                .position
+                .abs_diff_eq(10.0 * Vec3::X, 1e-4)
+        );
+
+        // On the parabola, by its own length formula
+        let spline = parabola();
+        let x = 0.5f32;
+        let distance = x * (1.0 + 4.0 * x * x).sqrt() / 2.0 + (2.0 * x).asinh() / 4.0;
+        let frame = spline.sample_at_distance(distance);
+        assert!(
+            frame.position.distance(vec3(x, x * x, 0.0)) < 1e-3,
+            "{frame:?}"
+        );
+        let tangent = vec3(1.0, 2.0 * x, 0.0).normalize();
+        assert!(frame.tangent.abs_diff_eq(tangent, 1e-3), "{frame:?}");
+    }
+
+    #[test]
+    fn test_frames_dont_twist() {
+        // In a plane, rotation minimizing frames keep the plane normal as their side
+        for frame in parabola().iter_equidistant(0.05) {
+            assert!((frame.tangent.length() - 1.0).abs() < 1e-4);
+            assert!(frame.up.dot(frame.tangent).abs() < 1e-4);
+            assert!(
+                frame.tangent.cross(frame.up).abs_diff_eq(Vec3::Z, 1e-3),
+                "{frame:?}"
+            );
+        }
+        // Flat on the ground, up stays up
+        let points = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(4.0, 0.0, 2.0),
+            vec3(8.0, 0.0, -1.0),
+            vec3(12.0, 0.0, 3.0),
+        ];
+        let flat = Spline::new(
+            SplineKind::CatmullRom,
+            points.into_iter().map(ControlPoint::new).collect(),
+        )
+        .unwrap();
+        for frame in flat.iter_equidistant(0.25) {
+            assert!(frame.up.abs_diff_eq(Vec3::Y, 1e-3), "{frame:?}");
+        }
+    }
+
+    #[test]
+    fn test_iter_equidistant() {
+        let line = polyline(&[Vec3::ZERO, vec3(10.0, 0.0, 0.0)]);
+        let distances: Vec<f32> = line.iter_equidistant(3.0).map(|f| f.distance).collect();
+        assert_eq!(distances, [0.0, 3.0, 6.0, 9.0]);
+        assert_eq!(line.iter_equidistant(2.5).count(), 5);
+
+        let spline = parabola();
+        let frames: Vec<_> = spline.iter_equidistant(0.1).collect();
+        assert_eq!(frames.len(), 15);
+        for pair in frames.windows(2) {
+            // Chords are a bit shorter than the arcs between them
+            let chord = pair[0].position.distance(pair[1].position);
+            assert!(chord <= 0.1 + 1e-4 && chord > 0.099, "{chord}");
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use bevy::prelude::*;
+
+use bevy::color::palettes::tailwind::STONE_500;
+
+use crate::spline::Spline;
+
+pub struct SplineMeshPlugin;
+
+impl Plugin for SplineMeshPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_material);
+        app.add_systems(Update, (build_spline_meshes, remove_spline_meshes));
+    }
+}
+
+/// Mesh extruded along the [Spline] on the same entity, rebuilt when either changes.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct SplineMesh {
+    /// Cross-section, left to right with the spline heading away: x to the right, y up.
+    /// Faces point to the left of each profile edge, so up for a road running left to right.
+    pub profile: Vec<Vec2>,
+    /// Distance between cross-sections along the spline
+    pub spacing: f32,
+    /// Distance along the spline for one texture repeat
+    pub uv_length: f32,
+}
+
+impl SplineMesh {
+    /// Flat road `width` wide, with shoulders sloping down into the ground
+    pub fn road(width: f32) -> Self {
+        let half = width / 2.0;
+        Self {
+            profile: vec![
+                vec2(-half - 1.0, -0.5),
+                vec2(-half, 0.0),
+                vec2(half, 0.0),
+                vec2(half + 1.0, -0.5),
+            ],
+            spacing: 1.0,
+            uv_length: width,
+        }
+    }
+
+    /// Extent of the profile across the spline, shoulders included
+    pub fn width(&self) -> f32 {
+        let (min, max) = self
+            .profile
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), p| {
+                (min.min(p.x), max.max(p.x))
+            });
+        (max - min).max(0.0)
+    }
+
+    /// Stretches the profile across the spline to `width`, keeping its shape and the texture's
+    /// aspect. Does nothing to a profile without width.
+    pub fn set_width(&mut self, width: f32) {
+        let current = self.width();
+        if current <= 0.0 {
+            return;
+        }
+        let scale = width / current;
+        for p in &mut self.profile {
+            p.x *= scale;
+        }
+        self.uv_length *= scale;
+    }
+
+    pub fn extrude(&self, spline: &Spline) -> ExtrudedMesh {
+        let mut mesh = ExtrudedMesh::default();
+        if self.profile.len() < 2 {
+            return mesh;
+        }
+
+        let profile_normals: Vec<Vec2> = (0..self.profile.len())
+            .map(|j| {
+                let edge_normal = |a: usize, b: usize| {
+                    let edge = self.profile[b] - self.profile[a];
+                    vec2(-edge.y, edge.x).normalize_or_zero()
+                };
+                let before = j.checked_sub(1).map(|i| edge_normal(i, j));
+                let after = (j + 1 < self.profile.len()).then(|| edge_normal(j, j + 1));
+                (before.unwrap_or_default() + after.unwrap_or_default()).normalize_or(Vec2::Y)
+            })
+            .collect();
+        // Across the texture, by distance along the profile
+        let mut profile_u = vec![0.0];
+        for pair in self.profile.windows(2) {
+            profile_u.push(profile_u.last().unwrap() + pair[0].distance(pair[1]));
+        }
+        let profile_length = profile_u.last().copied().unwrap_or(1.0).max(f32::EPSILON);
+
+        let length = spline.length();
+        let num_steps = (length / self.spacing.max(0.01)).ceil().max(1.0) as usize;
+        let uv_length = self.uv_length.max(0.01);
+        for i in 0..=num_steps {
+            let frame = spline.sample_at_distance(length * i as f32 / num_steps as f32);
+            let right = frame.tangent.cross(frame.up);
+            for (j, p) in self.profile.iter().enumerate() {
+                let normal = profile_normals[j];
+                mesh.vertices
+                    .push(frame.position + right * p.x + frame.up * p.y);
+                mesh.normals.push(right * normal.x + frame.up * normal.y);
+                mesh.uvs.push(vec2(
+                    profile_u[j] / profile_length,
+                    frame.distance / uv_length,
+                ));
+            }
+        }
+
+        let ring = self.profile.len() as u32;
+        for i in 0..num_steps as u32 {
+            for j in 0..ring - 1 {
+                let a = i * ring + j;
+                let (b, c, d) = (a + 1, a + ring, a + ring + 1);
+                mesh.indices.extend([a, b, c, b, d, c]);
+            }
+        }
+        mesh
+    }
+}
+
+/// Output of [SplineMesh::extrude], in spline space.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtrudedMesh {
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<u32>,
+}
+
+impl ExtrudedMesh {
+    pub fn bevy_mesh(&self) -> Mesh {
+        use bevy::asset::RenderAssetUsages;
+        use bevy::mesh::Indices;
+        use bevy::mesh::PrimitiveTopology;
+
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs.clone());
+        mesh.insert_indices(Indices::U32(self.indices.clone()));
+        mesh
+    }
+}
+
+/// For spline meshes without a material of their own
+#[derive(Resource)]
+struct SplineMeshMaterial(Handle<StandardMaterial>);
+
+fn setup_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    let material = materials.add(StandardMaterial {
+        base_color: STONE_500.into(),
+        perceptual_roughness: 0.9,
+        ..default()
+    });
+    commands.insert_resource(SplineMeshMaterial(material));
+}
+
+type QSplineMesh<'a> = (
+    Entity,
+    &'a Spline,
+    &'a SplineMesh,
+    Option<&'a Mesh3d>,
+    Has<MeshMaterial3d<StandardMaterial>>,
+);
+
+type SplineOrMeshChanged = Or<(Changed<Spline>, Changed<SplineMesh>)>;
+
+fn build_spline_meshes(
+    mut commands: Commands,
+    material: Res<SplineMeshMaterial>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    q_splines: Query<QSplineMesh, SplineOrMeshChanged>,
+) {
+    for (entity, spline, spline_mesh, mesh3d, has_material) in q_splines.iter() {
+        let mesh = spline_mesh.extrude(spline).bevy_mesh();
+        let mut entity = commands.entity(entity);
+        // Reuse the asset, so dragging a point doesn't pile up meshes
+        match mesh3d {
+            Some(mesh3d) => {
+                if let Err(e) = meshes.insert(mesh3d.id(), mesh) {
+                    error!("Failed to update spline mesh: {e}");
+                }
+            }
+            None => {
+                entity.insert(Mesh3d(meshes.add(mesh)));
+            }
+        }
+        if !has_material {
+            entity.insert(MeshMaterial3d(material.0.clone()));
+        }
+    }
+}
+
+fn remove_spline_meshes(mut commands: Commands, mut removed: RemovedComponents<SplineMesh>) {
+    for entity in removed.read() {
+        if let Ok(mut entity) = commands.get_entity(entity) {
+            entity.remove::<(Mesh3d, MeshMaterial3d<StandardMaterial>)>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spline::ControlPoint;
+    use crate::spline::SplineKind;
+
+    fn straight(length: f32) -> Spline {
+        let points = vec![
+            ControlPoint::new(Vec3::ZERO),
+            ControlPoint::new(vec3(length, 0.0, 0.0)),
+        ];
+        Spline::new(SplineKind::Polyline, points).unwrap()
+    }
+
+    #[test]
+    fn test_extrude_ribbon() {
+        let ribbon = SplineMesh {
+            profile: vec![vec2(-2.0, 0.0), vec2(2.0, 0.0)],
+            spacing: 1.0,
+            uv_length: 4.0,
+        };
+        let mesh = ribbon.extrude(&straight(10.0));
+
+        assert_eq!(mesh.vertices.len(), 11 * 2);
+        assert_eq!(mesh.indices.len(), 10 * 6);
+        // Heading along +X, right is +Z
+        assert!(mesh.vertices[0].abs_diff_eq(vec3(0.0, 0.0, -2.0), 1e-4));
+        assert!(mesh.vertices[21].abs_diff_eq(vec3(10.0, 0.0, 2.0), 1e-4));
+        for normal in &mesh.normals {
+            assert!(normal.abs_diff_eq(Vec3::Y, 1e-4), "{normal}");
+        }
+        // Tiles by distance along the spline, stretches across it
+        assert_eq!(mesh.uvs[1], vec2(1.0, 0.0));
+        assert!(mesh.uvs[21].abs_diff_eq(vec2(1.0, 2.5), 1e-4));
+    }
+
+    #[test]
+    fn test_set_width_keeps_shape() {
+        let mut road = SplineMesh::road(6.0);
+        road.spacing = 0.5;
+        assert_eq!(road.width(), 8.0);
+
+        road.set_width(16.0);
+        assert_eq!(road.width(), 16.0);
+        assert_eq!(road.profile[1], vec2(-6.0, 0.0));
+        assert_eq!(road.profile[3], vec2(8.0, -0.5));
+        assert_eq!(road.spacing, 0.5);
+        assert_eq!(road.uv_length, 12.0);
+    }
+
+    #[test]
+    fn test_faces_match_normals() {
+        // Gentle enough for the inside of the bends not to fold over
+        let points = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(30.0, 4.0, 15.0),
+            vec3(60.0, 0.0, -15.0),
+        ];
+        let spline = Spline::new(
+            SplineKind::CatmullRom,
+            points.into_iter().map(ControlPoint::new).collect(),
+        )
+        .unwrap();
+        let mesh = SplineMesh::road(6.0).extrude(&spline);
+
+        for tri in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| mesh.vertices[tri[k] as usize]);
+            let face_normal = (b - a).cross(c - a);
+            let normal = mesh.normals[tri[0] as usize];
+            assert!(face_normal.dot(normal) > 0.0, "{tri:?}");
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use bevy::math::prelude::*;
+use derive_more::Display;
+use image::Luma;
+
+use crate::terrain_processing::HeightmapBundle;
+use crate::terrain_processing::heightmap::GrayF32Image;
+
+/// Masks derived from terrain height, one value per heightmap texel.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnalysisMap {
+    /// Radians, 0 is flat
+    Slope,
+    /// Laplacian of the height, positive in hollows and negative on ridges
+    Curvature,
+    /// Texels draining through each texel, itself included
+    #[display("Flow accumulation")]
+    FlowAccumulation,
+    /// 0 fully occluded to 1 open sky
+    #[display("Ambient occlusion")]
+    AmbientOcclusion,
+}
+
+impl AnalysisMap {
+    pub const ALL: [Self; 4] = [
+        Self::Slope,
+        Self::Curvature,
+        Self::FlowAccumulation,
+        Self::AmbientOcclusion,
+    ];
+
+    /// Heightmap file in the cache directory, next to `base_heightmap.hmp`
+    pub const fn file_name(&self) -> &'static str {
+        match self {
+            Self::Slope => "analysis_slope.hmp",
+            Self::Curvature => "analysis_curvature.hmp",
+            Self::FlowAccumulation => "analysis_flow.hmp",
+            Self::AmbientOcclusion => "analysis_ao.hmp",
+        }
+    }
+
+    pub fn compute(&self, h_bundle: &HeightmapBundle) -> GrayF32Image {
+        match self {
+            Self::Slope => slope_map(h_bundle),
+            Self::Curvature => curvature_map(h_bundle),
+            Self::FlowAccumulation => flow_accumulation_map(h_bundle),
+            Self::AmbientOcclusion => ambient_occlusion_map(h_bundle, &HorizonAo::default()),
+        }
+    }
+}
+
+/// Horizon search for [ambient_occlusion_map].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HorizonAo {
+    pub directions: u32,
+    /// How far to look for occluders, in world units
+    pub distance: f32,
+}
+
+impl Default for HorizonAo {
+    fn default() -> Self {
+        Self {
+            directions: 8,
+            distance: 64.0,
+        }
+    }
+}
+
+/// World heights of every texel, row by row
+struct Heights {
+    size: IVec2,
+    texel_size: Vec2,
+    values: Vec<f32>,
+}
+
+impl Heights {
+    fn new(h_bundle: &HeightmapBundle) -> Self {
+        let size = h_bundle.size();
+        let values = (0..size.y)
+            .flat_map(|y| (0..size.x).map(move |x| uvec2(x, y)))
+            .map(|texel| h_bundle.height(texel))
+            .collect();
+        Self {
+            size: size.as_ivec2(),
+            texel_size: h_bundle.texel_size(),
+            values,
+        }
+    }
+
+    fn contains(&self, texel: IVec2) -> bool {
+        texel.cmpge(IVec2::ZERO).all() && texel.cmplt(self.size).all()
+    }
+
+    fn index(&self, texel: IVec2) -> usize {
+        (texel.y * self.size.x + texel.x) as usize
+    }
+
+    /// Clamped to the map edges
+    fn get(&self, texel: IVec2) -> f32 {
+        self.values[self.index(texel.clamp(IVec2::ZERO, self.size - 1))]
+    }
+
+    fn image(&self, f: impl Fn(IVec2) -> f32) -> GrayF32Image {
+        GrayF32Image::from_fn(self.size.x as u32, self.size.y as u32, |x, y| {
+            Luma([f(uvec2(x, y).as_ivec2())])
+        })
+    }
+}
+
+pub fn slope_map(h_bundle: &HeightmapBundle) -> GrayF32Image {
+    let heights = Heights::new(h_bundle);
+    heights.image(|p| {
+        let dx =
+            (heights.get(p + IVec2::X) - heights.get(p - IVec2::X)) / (2.0 * heights.texel_size.x);
+        let dz =
+            (heights.get(p + IVec2::Y) - heights.get(p - IVec2::Y)) / (2.0 * heights.texel_size.y);
+        vec2(dx, dz).length().atan()
+    })
+}
+
+pub fn curvature_map(h_bundle: &HeightmapBundle) -> GrayF32Image {
+    let heights = Heights::new(h_bundle);
+    let texel_area = heights.texel_size * heights.texel_size;
+    heights.image(|p| {
+        let h = heights.get(p);
+        let dxx = (heights.get(p + IVec2::X) - 2.0 * h + heights.get(p - IVec2::X)) / texel_area.x;
+        let dzz = (heights.get(p + IVec2::Y) - 2.0 * h + heights.get(p - IVec2::Y)) / texel_area.y;
+        dxx + dzz
+    })
+}
+
+/// D8 flow: every texel drains into its steepest lower neighbour.
+pub fn flow_accumulation_map(h_bundle: &HeightmapBundle) -> GrayF32Image {
+    const NEIGHBOURS: [IVec2; 8] = [
+        ivec2(-1, -1),
+        ivec2(0, -1),
+        ivec2(1, -1),
+        ivec2(-1, 0),
+        ivec2(1, 0),
+        ivec2(-1, 1),
+        ivec2(0, 1),
+        ivec2(1, 1),
+    ];
+
+    let heights = Heights::new(h_bundle);
+    let mut order: Vec<usize> = (0..heights.values.len()).collect();
+    // Highest first, so a texel has all its inflow before passing it on
+    order.sort_by(|a, b| heights.values[*b].total_cmp(&heights.values[*a]));
+
+    let mut flow = vec![1.0f32; heights.values.len()];
+    for i in order {
+        let p = ivec2(i as i32 % heights.size.x, i as i32 / heights.size.x);
+        let h = heights.values[i];
+        let steepest = NEIGHBOURS
+            .iter()
+            .map(|offset| p + *offset)
+            .filter(|n| heights.contains(*n))
+            .map(|n| {
+                let distance = ((n - p).as_vec2() * heights.texel_size).length();
+                (n, (h - heights.get(n)) / distance)
+            })
+            .filter(|(_, drop)| *drop > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((n, _)) = steepest {
+            flow[heights.index(n)] += flow[i];
+        }
+    }
+
+    heights.image(|p| flow[heights.index(p)])
+}
+
+/// How much sky each texel sees, from the highest horizon in a few directions.
+pub fn ambient_occlusion_map(h_bundle: &HeightmapBundle, settings: &HorizonAo) -> GrayF32Image {
+    let heights = Heights::new(h_bundle);
+    let directions: Vec<Vec2> = (0..settings.directions)
+        .map(|i| Vec2::from_angle(i as f32 / settings.directions as f32 * std::f32::consts::TAU))
+        .collect();
+    // Doubling steps: nearby occluders matter most
+    let texel_size = heights.texel_size.min_element();
+    let steps: Vec<f32> = std::iter::successors(Some(texel_size), |d| Some(d * 2.0))
+        .take_while(|d| *d <= settings.distance)
+        .collect();
+
+    heights.image(|p| {
+        let h = heights.get(p);
+        let occlusion: f32 = directions
+            .iter()
+            .map(|dir| {
+                steps
+                    .iter()
+                    .map(|distance| {
+                        let offset = (*dir * *distance / heights.texel_size).round().as_ivec2();
+                        let rise = heights.get(p + offset) - h;
+                        // Sine of the elevation angle
+                        (rise / (rise * rise + distance * distance).sqrt()).max(0.0)
+                    })
+                    .fold(0.0, f32::max)
+            })
+            .sum();
+        1.0 - occlusion / directions.len().max(1) as f32
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain_processing::WorldSettings;
+
+    /// 33x33 texels over a 32 unit world with 100 units of height range
+    fn test_bundle(f: impl Fn(u32, u32) -> f32) -> HeightmapBundle {
+        let map = GrayF32Image::from_fn(33, 33, |x, y| Luma([f(x, y)]));
+        let world = WorldSettings {
+            world_size: 32,
+            cell_size: 16,
+            world_height: 100.0,
+            world_height_offset: 0.0,
+        };
+        HeightmapBundle::new(map, world)
+    }
+
+    #[test]
+    fn test_slope_of_ramp() {
+        // Rises 1 unit per texel: 45 degrees
+        let h_bundle = test_bundle(|x, _| x as f32 / 100.0);
+        let slope = slope_map(&h_bundle);
+        let angle = slope.get_pixel(16, 16)[0];
+        assert!((angle - std::f32::consts::FRAC_PI_4).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_curvature_sign() {
+        let bowl = test_bundle(|x, y| {
+            let d = uvec2(x, y).as_vec2().distance(Vec2::splat(16.0));
+            d * d / 10000.0
+        });
+        assert!(curvature_map(&bowl).get_pixel(16, 16)[0] > 0.0);
+
+        let dome = test_bundle(|x, y| {
+            let d = uvec2(x, y).as_vec2().distance(Vec2::splat(16.0));
+            0.5 - d * d / 10000.0
+        });
+        assert!(curvature_map(&dome).get_pixel(16, 16)[0] < 0.0);
+    }
+
+    #[test]
+    fn test_flow_gathers_in_valley() {
+        // V-shaped valley along x = 16, sloping down towards y = 32
+        let h_bundle =
+            test_bundle(|x, y| (x as f32 - 16.0).abs() / 100.0 + (32 - y) as f32 / 1000.0);
+        let flow = flow_accumulation_map(&h_bundle);
+        assert_eq!(flow.get_pixel(0, 0)[0], 1.0);
+        let outlet = flow.get_pixel(16, 32)[0];
+        assert!(outlet > 33.0 * 30.0, "{outlet}");
+        assert!(outlet <= 33.0 * 33.0);
+    }
+
+    #[test]
+    fn test_ao_pit_is_darker() {
+        let h_bundle = test_bundle(|x, y| {
+            let d = uvec2(x, y).as_vec2().distance(Vec2::splat(16.0));
+            (d / 16.0).min(1.0) * 0.2
+        });
+        let ao = ambient_occlusion_map(&h_bundle, &HorizonAo::default());
+        assert!(ao.get_pixel(16, 16)[0] < 0.5);
+        assert!(ao.get_pixel(0, 16)[0] > 0.9);
+        assert!(ao.iter().all(|v| (0.0..=1.0).contains(v)));
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use crate::terrain_processing::WorldSettings;
+
+/// Describes what the terrain cache was built from, so stale cells can be found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheManifest {
+    /// Hash of the source heightmap file
+    pub source_hash: u64,
+    /// Hash of the layer files applied over it
+    pub layers_hash: u64,
+    pub world: WorldSettings,
+    /// One entry per cell, indexed like the cell files
+    pub cells: Vec<CacheCellEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheCellEntry {
+    /// TerrainMesh::FILE_VER the cell was written with
+    pub file_ver: u32,
+    /// Hash of the heights the cell was built from
+    pub content_hash: u64,
+}
+
+impl CacheManifest {
+    pub const FILE_NAME: &str = "manifest.wcache";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-CacheMan  ";
+    pub const FILE_VER: u32 = 1;
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        file.write_all(&self.source_hash.to_le_bytes())?;
+        file.write_all(&self.layers_hash.to_le_bytes())?;
+        self.world.write_header(&mut file)?;
+        file.write_all(&(self.cells.len() as u32).to_le_bytes())?;
+        for cell in &self.cells {
+            file.write_all(&cell.file_ver.to_le_bytes())?;
+            file.write_all(&cell.content_hash.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut buf = [0u8; 4];
+        let mut buf64 = [0u8; 8];
+        let mut sig_buf = [0u8; 16];
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid FILE_SIG",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
+            ));
+        }
+
+        file.read_exact(&mut buf64)?;
+        let source_hash = u64::from_le_bytes(buf64);
+        file.read_exact(&mut buf64)?;
+        let layers_hash = u64::from_le_bytes(buf64);
+        let world = WorldSettings::read_header(&mut file)?;
+
+        file.read_exact(&mut buf)?;
+        let num_cells = u32::from_le_bytes(buf);
+        if num_cells != world.num_cells() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "cell count mismatch: exp '{}', got '{num_cells}'",
+                    world.num_cells()
+                ),
+            ));
+        }
+
+        let mut cells = Vec::with_capacity(num_cells as usize);
+        for _ in 0..num_cells {
+            file.read_exact(&mut buf)?;
+            let file_ver = u32::from_le_bytes(buf);
+            file.read_exact(&mut buf64)?;
+            let content_hash = u64::from_le_bytes(buf64);
+            cells.push(CacheCellEntry {
+                file_ver,
+                content_hash,
+            });
+        }
+
+        Ok(Self {
+            source_hash,
+            layers_hash,
+            world,
+            cells,
+        })
+    }
+}
+
+/// FNV-1a. Unlike `DefaultHasher`, output is stable across platforms and rust versions.
+#[derive(Debug, Clone, Copy)]
+pub struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for ContentHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Hashes a byte slice with [ContentHasher].
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = ContentHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hash_known_values() {
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let world = WorldSettings {
+            world_size: 1024,
+            cell_size: 512,
+            ..Default::default()
+        };
+        let manifest = CacheManifest {
+            source_hash: 1234,
+            layers_hash: 5678,
+            world,
+            cells: (0..world.num_cells())
+                .map(|i| CacheCellEntry {
+                    file_ver: 2,
+                    content_hash: i as u64 * 31,
+                })
+                .collect(),
+        };
+        let path = std::env::temp_dir().join("worldedit_test_manifest.wcache");
+        manifest.save(&path).unwrap();
+        assert_eq!(CacheManifest::load(&path).unwrap(), manifest);
+    }
+}
@@ -0,0 +1,673 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use bevy::math::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use rand_chacha::rand_core::RngCore;
+use rand_chacha::rand_core::SeedableRng;
+
+use crate::terrain_processing::heightmap::GrayF32Image;
+
+/// Where the crunch caches [ErosionMaps::sediment], as a heightmap file.
+pub const SEDIMENT_FILE_NAME: &str = "erosion_sediment.hmp";
+/// Where the crunch caches [ErosionMaps::flow], as a heightmap file.
+pub const FLOW_FILE_NAME: &str = "erosion_flow.hmp";
+
+/// Water droplets running downhill, picking up sediment on steep ground and dropping
+/// it where they slow down.
+///
+/// Heights and capacities are in heightmap units (usually 0..1), distances in texels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HydraulicErosion {
+    /// Droplets simulated per iteration
+    pub droplets: u32,
+    /// Steps before a droplet is dropped
+    pub lifetime: u32,
+    /// 0 follows the slope exactly, 1 ignores it
+    pub inertia: f32,
+    /// Sediment a droplet can carry, per unit of height drop, speed and water
+    pub capacity: f32,
+    /// Keeps droplets on flat ground eroding a little
+    pub min_capacity: f32,
+    /// Fraction of excess sediment dropped per step
+    pub deposition: f32,
+    /// Fraction of free capacity eroded per step
+    pub erosion: f32,
+    /// Fraction of water lost per step
+    pub evaporation: f32,
+    pub gravity: f32,
+    /// Erosion spreads over this many texels around the droplet
+    pub radius: u32,
+}
+
+impl Default for HydraulicErosion {
+    fn default() -> Self {
+        Self {
+            droplets: 1024,
+            lifetime: 48,
+            inertia: 0.05,
+            capacity: 4.0,
+            min_capacity: 0.0001,
+            deposition: 0.3,
+            erosion: 0.3,
+            evaporation: 0.02,
+            gravity: 4.0,
+            radius: 3,
+        }
+    }
+}
+
+/// Material sliding off slopes steeper than the talus angle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalErosion {
+    /// Steepest stable height difference between neighbouring texels
+    pub talus: f32,
+    /// Fraction of the excess moved per iteration, 0..=1
+    pub rate: f32,
+}
+
+impl Default for ThermalErosion {
+    fn default() -> Self {
+        Self {
+            talus: 0.002,
+            rate: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErosionSettings {
+    pub seed: u64,
+    pub iterations: u32,
+    pub hydraulic: Option<HydraulicErosion>,
+    pub thermal: Option<ThermalErosion>,
+    /// Keep track of [ErosionMaps::sediment]
+    pub sediment_map: bool,
+    /// Keep track of [ErosionMaps::flow]
+    pub flow_map: bool,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            iterations: 64,
+            hydraulic: Some(HydraulicErosion::default()),
+            thermal: Some(ThermalErosion::default()),
+            sediment_map: false,
+            flow_map: false,
+        }
+    }
+}
+
+impl ErosionSettings {
+    pub const FILE_NAME: &str = "erosion.wero";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-Erosion   ";
+    pub const FILE_VER: u32 = 0;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(hydraulic) = &self.hydraulic {
+            if hydraulic.radius == 0 {
+                return Err("erosion radius must be non-zero".to_string());
+            }
+            if !(0.0..=1.0).contains(&hydraulic.inertia)
+                || !(0.0..=1.0).contains(&hydraulic.evaporation)
+            {
+                return Err("inertia and evaporation must be within 0..=1".to_string());
+            }
+        }
+        if let Some(thermal) = &self.thermal
+            && !(0.0..=1.0).contains(&thermal.rate)
+        {
+            return Err("thermal erosion rate must be within 0..=1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        self.write(&mut BufWriter::new(File::create(path)?))
+    }
+
+    /// File contents, as written by [Self::save]
+    pub fn write(&self, file: &mut impl Write) -> std::io::Result<()> {
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        file.write_all(&self.seed.to_le_bytes())?;
+        file.write_all(&self.iterations.to_le_bytes())?;
+
+        // Optional parts: a u32 flag, then the values
+        file.write_all(&(self.hydraulic.is_some() as u32).to_le_bytes())?;
+        if let Some(hydraulic) = &self.hydraulic {
+            let values = [
+                hydraulic.droplets,
+                hydraulic.lifetime,
+                hydraulic.inertia.to_bits(),
+                hydraulic.capacity.to_bits(),
+                hydraulic.min_capacity.to_bits(),
+                hydraulic.deposition.to_bits(),
+                hydraulic.erosion.to_bits(),
+                hydraulic.evaporation.to_bits(),
+                hydraulic.gravity.to_bits(),
+                hydraulic.radius,
+            ];
+            for value in values {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+        file.write_all(&(self.thermal.is_some() as u32).to_le_bytes())?;
+        if let Some(thermal) = &self.thermal {
+            file.write_all(&thermal.talus.to_le_bytes())?;
+            file.write_all(&thermal.rate.to_le_bytes())?;
+        }
+
+        file.write_all(&(self.sediment_map as u32).to_le_bytes())?;
+        file.write_all(&(self.flow_map as u32).to_le_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut buf = [0u8; 4];
+        let mut buf64 = [0u8; 8];
+        let mut sig_buf = [0u8; 16];
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid FILE_SIG",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
+            ));
+        }
+
+        let mut read_u32 = |file: &mut BufReader<File>| -> std::io::Result<u32> {
+            file.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+
+        file.read_exact(&mut buf64)?;
+        let seed = u64::from_le_bytes(buf64);
+        let iterations = read_u32(&mut file)?;
+
+        let hydraulic = match read_u32(&mut file)? {
+            0 => None,
+            _ => {
+                let mut values = [0u32; 10];
+                for value in &mut values {
+                    *value = read_u32(&mut file)?;
+                }
+                let [
+                    droplets,
+                    lifetime,
+                    inertia,
+                    capacity,
+                    min_capacity,
+                    deposition,
+                    erosion,
+                    evaporation,
+                    gravity,
+                    radius,
+                ] = values;
+                Some(HydraulicErosion {
+                    droplets,
+                    lifetime,
+                    inertia: f32::from_bits(inertia),
+                    capacity: f32::from_bits(capacity),
+                    min_capacity: f32::from_bits(min_capacity),
+                    deposition: f32::from_bits(deposition),
+                    erosion: f32::from_bits(erosion),
+                    evaporation: f32::from_bits(evaporation),
+                    gravity: f32::from_bits(gravity),
+                    radius,
+                })
+            }
+        };
+        let thermal = match read_u32(&mut file)? {
+            0 => None,
+            _ => Some(ThermalErosion {
+                talus: f32::from_bits(read_u32(&mut file)?),
+                rate: f32::from_bits(read_u32(&mut file)?),
+            }),
+        };
+
+        let sediment_map = read_u32(&mut file)? != 0;
+        let flow_map = read_u32(&mut file)? != 0;
+
+        let settings = Self {
+            seed,
+            iterations,
+            hydraulic,
+            thermal,
+            sediment_map,
+            flow_map,
+        };
+        settings
+            .validate()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(settings)
+    }
+}
+
+/// By-products of an erosion run, same size as the heightmap.
+#[derive(Debug, Clone, Default)]
+pub struct ErosionMaps {
+    /// Height of deposited material, in heightmap units
+    pub sediment: Option<GrayF32Image>,
+    /// Water that passed each texel, normalized to 0..1
+    pub flow: Option<GrayF32Image>,
+}
+
+/// Erosion run that can be advanced a few iterations at a time, e.g. from a background
+/// task reporting progress.
+#[derive(Debug)]
+pub struct Erosion {
+    map: GrayF32Image,
+    settings: ErosionSettings,
+    rng: ChaCha8Rng,
+    iteration: u32,
+    /// Brush offsets and weights for spreading hydraulic erosion
+    brush: Vec<(IVec2, f32)>,
+    sediment: Option<GrayF32Image>,
+    flow: Option<GrayF32Image>,
+}
+
+impl Erosion {
+    pub fn new(map: GrayF32Image, settings: &ErosionSettings) -> Self {
+        let (width, height) = map.dimensions();
+        let radius = settings.hydraulic.map_or(1, |hydraulic| hydraulic.radius) as i32;
+        let brush = (-radius..=radius)
+            .flat_map(|y| (-radius..=radius).map(move |x| ivec2(x, y)))
+            .filter_map(|offset| {
+                let weight = radius as f32 - offset.as_vec2().length();
+                (weight > 0.0).then_some((offset, weight))
+            })
+            .collect();
+
+        Self {
+            map,
+            settings: *settings,
+            rng: ChaCha8Rng::seed_from_u64(settings.seed),
+            iteration: 0,
+            brush,
+            sediment: settings
+                .sediment_map
+                .then(|| GrayF32Image::new(width, height)),
+            flow: settings.flow_map.then(|| GrayF32Image::new(width, height)),
+        }
+    }
+
+    pub const fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    pub const fn is_done(&self) -> bool {
+        self.iteration >= self.settings.iterations
+    }
+
+    pub const fn map(&self) -> &GrayF32Image {
+        &self.map
+    }
+
+    /// Runs up to `iterations` more iterations. Returns `true` once all are done.
+    pub fn step(&mut self, iterations: u32) -> bool {
+        let end = self
+            .iteration
+            .saturating_add(iterations)
+            .min(self.settings.iterations);
+        while self.iteration < end {
+            if let Some(hydraulic) = self.settings.hydraulic {
+                for _ in 0..hydraulic.droplets {
+                    self.simulate_droplet(&hydraulic);
+                }
+            }
+            if let Some(thermal) = self.settings.thermal {
+                self.thermal_pass(&thermal);
+            }
+            self.iteration += 1;
+        }
+        self.is_done()
+    }
+
+    /// Eroded heightmap and the requested by-products
+    pub fn finish(self) -> (GrayF32Image, ErosionMaps) {
+        let flow = self.flow.map(|mut flow| {
+            let max = flow.iter().fold(0.0f32, |max, v| max.max(*v));
+            if max > 0.0 {
+                flow.iter_mut().for_each(|v| *v /= max);
+            }
+            flow
+        });
+        let maps = ErosionMaps {
+            sediment: self.sediment,
+            flow,
+        };
+        (self.map, maps)
+    }
+
+    fn simulate_droplet(&mut self, hydraulic: &HydraulicErosion) {
+        let max = uvec2(self.map.width(), self.map.height()).as_vec2() - 1.0;
+        if max.min_element() < 1.0 {
+            return;
+        }
+
+        let mut pos = vec2(self.random_unit(), self.random_unit()) * max;
+        let mut dir = Vec2::ZERO;
+        let mut speed = 1.0f32;
+        let mut water = 1.0f32;
+        let mut sediment = 0.0f32;
+
+        for _ in 0..hydraulic.lifetime {
+            let (h, gradient) = self.height_and_gradient(pos);
+            dir = dir * hydraulic.inertia - gradient * (1.0 - hydraulic.inertia);
+            let Some(step) = dir.try_normalize() else {
+                // Flat ground
+                break;
+            };
+            dir = step;
+
+            let old_pos = pos;
+            pos += dir;
+            if pos.cmplt(Vec2::ZERO).any() || pos.cmpge(max).any() {
+                break;
+            }
+            if let Some(flow) = &mut self.flow {
+                let texel = old_pos.as_uvec2();
+                flow.get_pixel_mut(texel.x, texel.y)[0] += water;
+            }
+
+            let dh = self.height_and_gradient(pos).0 - h;
+            let capacity = (-dh * speed * water * hydraulic.capacity).max(hydraulic.min_capacity);
+
+            if sediment > capacity || dh > 0.0 {
+                // Uphill fills the pit behind, otherwise drop part of the excess
+                let amount = if dh > 0.0 {
+                    dh.min(sediment)
+                } else {
+                    (sediment - capacity) * hydraulic.deposition
+                };
+                sediment -= amount;
+                self.deposit(old_pos, amount);
+            } else {
+                let amount = ((capacity - sediment) * hydraulic.erosion).min(-dh);
+                sediment += self.erode(old_pos, amount);
+            }
+
+            speed = (speed * speed - dh * hydraulic.gravity).max(0.0).sqrt();
+            water *= 1.0 - hydraulic.evaporation;
+        }
+    }
+
+    fn random_unit(&mut self) -> f32 {
+        (self.rng.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Bilinear height and gradient within the texel containing `pos`
+    fn height_and_gradient(&self, pos: Vec2) -> (f32, Vec2) {
+        let texel = pos.as_uvec2();
+        let f = pos - texel.as_vec2();
+        let h = |dx, dy| self.map.get_pixel(texel.x + dx, texel.y + dy)[0];
+        let (h00, h10, h01, h11) = (h(0, 0), h(1, 0), h(0, 1), h(1, 1));
+
+        let gradient = vec2(
+            (h10 - h00) * (1.0 - f.y) + (h11 - h01) * f.y,
+            (h01 - h00) * (1.0 - f.x) + (h11 - h10) * f.x,
+        );
+        let height = h00.lerp(h10, f.x).lerp(h01.lerp(h11, f.x), f.y);
+        (height, gradient)
+    }
+
+    /// Adds `amount` to the four texels around `pos`
+    fn deposit(&mut self, pos: Vec2, amount: f32) {
+        let texel = pos.as_uvec2();
+        let f = pos - texel.as_vec2();
+        let corners = [
+            (uvec2(0, 0), (1.0 - f.x) * (1.0 - f.y)),
+            (uvec2(1, 0), f.x * (1.0 - f.y)),
+            (uvec2(0, 1), (1.0 - f.x) * f.y),
+            (uvec2(1, 1), f.x * f.y),
+        ];
+        for (offset, weight) in corners {
+            let p = texel + offset;
+            self.map.get_pixel_mut(p.x, p.y)[0] += amount * weight;
+            if let Some(sediment) = &mut self.sediment {
+                sediment.get_pixel_mut(p.x, p.y)[0] += amount * weight;
+            }
+        }
+    }
+
+    /// Removes up to `amount` around `pos`, returns how much was removed
+    fn erode(&mut self, pos: Vec2, amount: f32) -> f32 {
+        let size = uvec2(self.map.width(), self.map.height()).as_ivec2();
+        let center = pos.round().as_ivec2();
+        let texels = || {
+            self.brush.iter().filter_map(move |(offset, weight)| {
+                let p = center + *offset;
+                (p.cmpge(IVec2::ZERO).all() && p.cmplt(size).all())
+                    .then_some((p.as_uvec2(), *weight))
+            })
+        };
+        let total_weight: f32 = texels().map(|(_, weight)| weight).sum();
+        if total_weight <= 0.0 {
+            return 0.0;
+        }
+
+        let removals: Vec<_> = texels()
+            .map(|(p, weight)| {
+                let h = self.map.get_pixel(p.x, p.y)[0];
+                // Don't dig below zero
+                (p, (amount * weight / total_weight).min(h.max(0.0)))
+            })
+            .collect();
+
+        let mut removed = 0.0;
+        for (p, delta) in removals {
+            self.map.get_pixel_mut(p.x, p.y)[0] -= delta;
+            if let Some(sediment) = &mut self.sediment {
+                let s = &mut sediment.get_pixel_mut(p.x, p.y)[0];
+                *s = (*s - delta).max(0.0);
+            }
+            removed += delta;
+        }
+        removed
+    }
+
+    fn thermal_pass(&mut self, thermal: &ThermalErosion) {
+        const NEIGHBOURS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+        let (width, height) = self.map.dimensions();
+        let size = uvec2(width, height).as_ivec2();
+        let mut deltas = GrayF32Image::new(width, height);
+
+        for (x, y, h) in self.map.enumerate_pixels() {
+            let p = uvec2(x, y).as_ivec2();
+            let mut total = 0.0;
+            let mut steepest = 0.0f32;
+            let mut lower = [None; 4];
+            for (i, offset) in NEIGHBOURS.iter().enumerate() {
+                let n = p + *offset;
+                if n.cmplt(IVec2::ZERO).any() || n.cmpge(size).any() {
+                    continue;
+                }
+                let n = n.as_uvec2();
+                let d = h[0] - self.map.get_pixel(n.x, n.y)[0];
+                if d > thermal.talus {
+                    lower[i] = Some((n, d));
+                    total += d;
+                    steepest = steepest.max(d);
+                }
+            }
+            if total <= 0.0 {
+                continue;
+            }
+
+            // Half the excess levels the steepest pair
+            let moved = thermal.rate * (steepest - thermal.talus) / 2.0;
+            deltas.get_pixel_mut(x, y)[0] -= moved;
+            for (n, d) in lower.into_iter().flatten() {
+                deltas.get_pixel_mut(n.x, n.y)[0] += moved * d / total;
+            }
+        }
+
+        for (h, delta) in self.map.iter_mut().zip(deltas.iter()) {
+            *h += delta;
+        }
+        if let Some(sediment) = &mut self.sediment {
+            for (s, delta) in sediment.iter_mut().zip(deltas.iter()) {
+                *s = (*s + delta).max(0.0);
+            }
+        }
+    }
+}
+
+/// Runs all iterations at once.
+pub fn erode(map: GrayF32Image, settings: &ErosionSettings) -> (GrayF32Image, ErosionMaps) {
+    let mut erosion = Erosion::new(map, settings);
+    erosion.step(settings.iterations);
+    erosion.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// Cone with its peak at 1.0 in the middle
+    fn cone(size: u32) -> GrayF32Image {
+        let center = Vec2::splat((size - 1) as f32 / 2.0);
+        GrayF32Image::from_fn(size, size, |x, y| {
+            let d = uvec2(x, y).as_vec2().distance(center) / center.x;
+            Luma([(1.0 - d).max(0.0)])
+        })
+    }
+
+    fn volume(map: &GrayF32Image) -> f32 {
+        map.iter().sum()
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let settings = ErosionSettings {
+            seed: 7,
+            iterations: 4,
+            flow_map: true,
+            ..Default::default()
+        };
+        let (a, a_maps) = erode(cone(32), &settings);
+        let (b, b_maps) = erode(cone(32), &settings);
+        assert_eq!(a, b);
+        assert_eq!(a_maps.flow, b_maps.flow);
+
+        let (c, _) = erode(
+            cone(32),
+            &ErosionSettings {
+                seed: 8,
+                ..settings
+            },
+        );
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_stepwise_matches_single_run() {
+        let settings = ErosionSettings {
+            iterations: 6,
+            ..Default::default()
+        };
+        let (all_at_once, _) = erode(cone(32), &settings);
+
+        let mut erosion = Erosion::new(cone(32), &settings);
+        assert!(!erosion.step(4));
+        assert_eq!(erosion.iteration(), 4);
+        assert!(erosion.step(4));
+        assert_eq!(erosion.iteration(), 6);
+        assert_eq!(erosion.finish().0, all_at_once);
+    }
+
+    #[test]
+    fn test_hydraulic_moves_material_downhill() {
+        let settings = ErosionSettings {
+            iterations: 8,
+            thermal: None,
+            sediment_map: true,
+            flow_map: true,
+            ..Default::default()
+        };
+        let original = cone(48);
+        let (eroded, maps) = erode(original.clone(), &settings);
+
+        let peak = |map: &GrayF32Image| map.get_pixel(24, 24)[0];
+        assert!(peak(&eroded) <= peak(&original));
+        assert_ne!(eroded, original);
+
+        let sediment = maps.sediment.unwrap();
+        assert!(sediment.iter().any(|s| *s > 0.0));
+        let flow = maps.flow.unwrap();
+        assert_eq!(flow.iter().fold(0.0f32, |max, v| max.max(*v)), 1.0);
+        // Droplets that run off the map take their sediment with them
+        assert!(volume(&eroded) <= volume(&original) + 1e-2);
+    }
+
+    #[test]
+    fn test_thermal_relaxes_to_talus() {
+        let settings = ErosionSettings {
+            iterations: 200,
+            hydraulic: None,
+            thermal: Some(ThermalErosion {
+                talus: 0.05,
+                rate: 0.5,
+            }),
+            ..Default::default()
+        };
+        let spike = GrayF32Image::from_fn(9, 9, |x, y| {
+            Luma([if (x, y) == (4, 4) { 1.0 } else { 0.0 }])
+        });
+        let (relaxed, _) = erode(spike.clone(), &settings);
+
+        assert!((volume(&relaxed) - volume(&spike)).abs() < 1e-4);
+        for (x, y, h) in relaxed.enumerate_pixels() {
+            if x + 1 < 9 {
+                assert!((h[0] - relaxed.get_pixel(x + 1, y)[0]).abs() < 0.05 + 1e-2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_load() {
+        let settings = ErosionSettings {
+            seed: u64::MAX - 3,
+            iterations: 12,
+            hydraulic: Some(HydraulicErosion {
+                droplets: 100,
+                inertia: 0.25,
+                ..Default::default()
+            }),
+            thermal: None,
+            sediment_map: true,
+            flow_map: false,
+        };
+        let path = std::env::temp_dir().join("worldedit_test_settings.wero");
+        settings.save(&path).unwrap();
+        assert_eq!(ErosionSettings::load(&path).unwrap(), settings);
+
+        let settings = ErosionSettings {
+            hydraulic: None,
+            thermal: Some(ThermalErosion::default()),
+            ..settings
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(ErosionSettings::load(&path).unwrap(), settings);
+    }
+}
@@ -0,0 +1,527 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use bevy::math::prelude::*;
+use derive_more::Display;
+use image::Luma;
+use rand_chacha::ChaCha8Rng;
+use rand_chacha::rand_core::RngCore;
+use rand_chacha::rand_core::SeedableRng;
+
+use crate::terrain_processing::heightmap::GrayF32Image;
+
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseBasis {
+    #[default]
+    Perlin,
+    Simplex,
+}
+
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FractalKind {
+    /// Rolling hills
+    #[default]
+    Fbm,
+    /// Sharp crests, mountain ranges
+    Ridged,
+    /// Rounded lumps, dunes
+    Billow,
+}
+
+/// Displaces sample positions by another noise, for twisted, eroded looking shapes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DomainWarp {
+    /// Displacement in noise units
+    pub amplitude: f32,
+    /// Relative to [NoiseSettings::frequency]
+    pub frequency: f32,
+}
+
+/// Fades the terrain out towards the map edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IslandFalloff {
+    /// Where the coast ends, 1.0 touching the map edges
+    pub radius: f32,
+    /// Width of the slope down to the sea, same units as `radius`
+    pub width: f32,
+}
+
+/// Quantizes heights into flat steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Terracing {
+    pub steps: u32,
+    /// 0 keeps the original slopes, 1 gives flat terraces with steep risers
+    pub sharpness: f32,
+}
+
+/// Everything [generate] needs. Same settings give the same terrain on every platform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseSettings {
+    pub seed: u64,
+    pub basis: NoiseBasis,
+    pub fractal: FractalKind,
+    /// Features across the whole map at the first octave
+    pub frequency: f32,
+    pub octaves: u32,
+    /// Frequency multiplier between octaves
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves
+    pub gain: f32,
+    pub warp: Option<DomainWarp>,
+    pub island: Option<IslandFalloff>,
+    pub terraces: Option<Terracing>,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            basis: NoiseBasis::default(),
+            fractal: FractalKind::default(),
+            frequency: 4.0,
+            octaves: 6,
+            lacunarity: 2.0,
+            gain: 0.5,
+            warp: None,
+            island: None,
+            terraces: None,
+        }
+    }
+}
+
+impl NoiseSettings {
+    pub const FILE_NAME: &str = "terrain.wgen";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-NoiseGen  ";
+    pub const FILE_VER: u32 = 0;
+    const MAX_OCTAVES: u32 = 16;
+
+    pub fn validate(&self) -> std::io::Result<()> {
+        let invalid = |msg: String| Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+
+        if !(1..=Self::MAX_OCTAVES).contains(&self.octaves) {
+            return invalid(format!(
+                "octaves {} must be within 1..={}",
+                self.octaves,
+                Self::MAX_OCTAVES
+            ));
+        }
+        if self.terraces.is_some_and(|terraces| terraces.steps == 0) {
+            return invalid("terrace steps must be non-zero".to_string());
+        }
+        let floats = [
+            Some(self.frequency),
+            Some(self.lacunarity),
+            Some(self.gain),
+            self.warp.map(|warp| warp.amplitude),
+            self.warp.map(|warp| warp.frequency),
+            self.island.map(|island| island.radius),
+            self.island.map(|island| island.width),
+            self.terraces.map(|terraces| terraces.sharpness),
+        ];
+        if !floats.into_iter().flatten().all(f32::is_finite) {
+            return invalid("noise settings must be finite".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        self.write(&mut BufWriter::new(File::create(path)?))
+    }
+
+    /// File contents, as written by [Self::save]
+    pub fn write(&self, file: &mut impl Write) -> std::io::Result<()> {
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        file.write_all(&self.seed.to_le_bytes())?;
+        file.write_all(&(self.basis as u32).to_le_bytes())?;
+        file.write_all(&(self.fractal as u32).to_le_bytes())?;
+        file.write_all(&self.frequency.to_le_bytes())?;
+        file.write_all(&self.octaves.to_le_bytes())?;
+        file.write_all(&self.lacunarity.to_le_bytes())?;
+        file.write_all(&self.gain.to_le_bytes())?;
+
+        // Optional parts: a u32 flag, then the values
+        let optional = [
+            self.warp
+                .map(|warp| [warp.amplitude.to_bits(), warp.frequency.to_bits()]),
+            self.island
+                .map(|island| [island.radius.to_bits(), island.width.to_bits()]),
+            self.terraces
+                .map(|terraces| [terraces.steps, terraces.sharpness.to_bits()]),
+        ];
+        for values in optional {
+            file.write_all(&(values.is_some() as u32).to_le_bytes())?;
+            for value in values.into_iter().flatten() {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut buf = [0u8; 4];
+        let mut buf64 = [0u8; 8];
+        let mut sig_buf = [0u8; 16];
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid FILE_SIG",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
+            ));
+        }
+
+        let mut read_u32 = |file: &mut BufReader<File>| -> std::io::Result<u32> {
+            file.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+        let invalid_enum = |name: &str, value: u32| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid {name}: '{value}'"),
+            )
+        };
+
+        file.read_exact(&mut buf64)?;
+        let seed = u64::from_le_bytes(buf64);
+        let basis = match read_u32(&mut file)? {
+            0 => NoiseBasis::Perlin,
+            1 => NoiseBasis::Simplex,
+            v => return Err(invalid_enum("noise basis", v)),
+        };
+        let fractal = match read_u32(&mut file)? {
+            0 => FractalKind::Fbm,
+            1 => FractalKind::Ridged,
+            2 => FractalKind::Billow,
+            v => return Err(invalid_enum("fractal kind", v)),
+        };
+        let frequency = f32::from_bits(read_u32(&mut file)?);
+        let octaves = read_u32(&mut file)?;
+        let lacunarity = f32::from_bits(read_u32(&mut file)?);
+        let gain = f32::from_bits(read_u32(&mut file)?);
+
+        let mut read_optional = |file: &mut BufReader<File>| -> std::io::Result<Option<[u32; 2]>> {
+            match read_u32(file)? {
+                0 => Ok(None),
+                _ => Ok(Some([read_u32(file)?, read_u32(file)?])),
+            }
+        };
+        let warp = read_optional(&mut file)?.map(|[amplitude, frequency]| DomainWarp {
+            amplitude: f32::from_bits(amplitude),
+            frequency: f32::from_bits(frequency),
+        });
+        let island = read_optional(&mut file)?.map(|[radius, width]| IslandFalloff {
+            radius: f32::from_bits(radius),
+            width: f32::from_bits(width),
+        });
+        let terraces = read_optional(&mut file)?.map(|[steps, sharpness]| Terracing {
+            steps,
+            sharpness: f32::from_bits(sharpness),
+        });
+
+        let settings = Self {
+            seed,
+            basis,
+            fractal,
+            frequency,
+            octaves,
+            lacunarity,
+            gain,
+            warp,
+            island,
+            terraces,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+/// Heightmap of `size` texels with values in 0..1.
+pub fn generate(size: UVec2, settings: &NoiseSettings) -> GrayF32Image {
+    let noise = FractalNoise::new(settings);
+    let scale = settings.frequency / size.max_element().max(2) as f32;
+    let half_size = size.as_vec2() / 2.0;
+
+    GrayF32Image::from_fn(size.x, size.y, |x, y| {
+        let texel = uvec2(x, y).as_vec2();
+        let mut h = noise.sample(texel * scale);
+
+        if let Some(island) = settings.island {
+            let d = ((texel - half_size) / half_size).length();
+            h *= 1.0 - smoothstep(island.radius - island.width, island.radius, d);
+        }
+        if let Some(terraces) = settings.terraces {
+            h = terrace(h, terraces);
+        }
+        Luma([h.clamp(0.0, 1.0)])
+    })
+}
+
+/// Octaves of gradient noise, with all the random state drawn up front
+struct FractalNoise {
+    settings: NoiseSettings,
+    perm: [u8; 512],
+    /// Per octave, so octaves don't line up at the origin
+    offsets: Vec<Vec2>,
+    warp_offsets: [Vec2; 2],
+}
+
+impl FractalNoise {
+    fn new(settings: &NoiseSettings) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(settings.seed);
+
+        let mut table: [u8; 256] = std::array::from_fn(|i| i as u8);
+        for i in (1..table.len()).rev() {
+            let j = (rng.next_u32() % (i as u32 + 1)) as usize;
+            table.swap(i, j);
+        }
+        let perm = std::array::from_fn(|i| table[i & 255]);
+
+        let mut random_offset = || {
+            let x = (rng.next_u32() >> 8) as f32 / (1 << 16) as f32;
+            let y = (rng.next_u32() >> 8) as f32 / (1 << 16) as f32;
+            vec2(x, y)
+        };
+        let offsets = (0..settings.octaves).map(|_| random_offset()).collect();
+        let warp_offsets = [random_offset(), random_offset()];
+
+        Self {
+            settings: *settings,
+            perm,
+            offsets,
+            warp_offsets,
+        }
+    }
+
+    /// 0..1
+    fn sample(&self, mut p: Vec2) -> f32 {
+        if let Some(warp) = self.settings.warp {
+            let q = p * warp.frequency;
+            p += vec2(
+                self.basis(q + self.warp_offsets[0]),
+                self.basis(q + self.warp_offsets[1]),
+            ) * warp.amplitude;
+        }
+
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        for offset in &self.offsets {
+            let n = self.basis(p * frequency + *offset);
+            let v = match self.settings.fractal {
+                FractalKind::Fbm => n * 0.5 + 0.5,
+                FractalKind::Ridged => {
+                    let r = 1.0 - n.abs();
+                    r * r
+                }
+                FractalKind::Billow => n.abs(),
+            };
+            sum += v * amplitude;
+            norm += amplitude;
+            amplitude *= self.settings.gain;
+            frequency *= self.settings.lacunarity;
+        }
+        sum / norm
+    }
+
+    /// -1..1
+    fn basis(&self, p: Vec2) -> f32 {
+        let n = match self.settings.basis {
+            NoiseBasis::Perlin => self.perlin(p),
+            NoiseBasis::Simplex => self.simplex(p),
+        };
+        n.clamp(-1.0, 1.0)
+    }
+
+    fn hash(&self, cell: IVec2) -> u8 {
+        let x = (cell.x & 255) as usize;
+        let y = (cell.y & 255) as usize;
+        self.perm[self.perm[x] as usize + y]
+    }
+
+    fn perlin(&self, p: Vec2) -> f32 {
+        let cell = p.floor();
+        let f = p - cell;
+        let cell = cell.as_ivec2();
+        let corner = |dx: i32, dy: i32| {
+            let hash = self.hash(cell + ivec2(dx, dy));
+            gradient(hash, f - vec2(dx as f32, dy as f32))
+        };
+
+        let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+        let top = corner(0, 0).lerp(corner(1, 0), u.x);
+        let bottom = corner(0, 1).lerp(corner(1, 1), u.x);
+        top.lerp(bottom, u.y)
+    }
+
+    fn simplex(&self, p: Vec2) -> f32 {
+        // (sqrt(3) - 1) / 2 and (3 - sqrt(3)) / 6
+        const F2: f32 = 0.366_025_4;
+        const G2: f32 = 0.211_324_87;
+
+        let skewed = (p + (p.x + p.y) * F2).floor();
+        let unskewed = skewed - (skewed.x + skewed.y) * G2;
+        let cell = skewed.as_ivec2();
+        let p0 = p - unskewed;
+        let step = if p0.x > p0.y { IVec2::X } else { IVec2::Y };
+        let p1 = p0 - step.as_vec2() + G2;
+        let p2 = p0 - 1.0 + 2.0 * G2;
+
+        let contribution = |offset: IVec2, d: Vec2| {
+            let t = 0.5 - d.length_squared();
+            if t < 0.0 {
+                return 0.0;
+            }
+            let t2 = t * t;
+            t2 * t2 * gradient(self.hash(cell + offset), d)
+        };
+
+        70.0 * (contribution(IVec2::ZERO, p0)
+            + contribution(step, p1)
+            + contribution(IVec2::ONE, p2))
+    }
+}
+
+fn gradient(hash: u8, d: Vec2) -> f32 {
+    match hash & 7 {
+        0 => d.x + d.y,
+        1 => -d.x + d.y,
+        2 => d.x - d.y,
+        3 => -d.x - d.y,
+        4 => d.x,
+        5 => -d.x,
+        6 => d.y,
+        _ => -d.y,
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn terrace(h: f32, terraces: Terracing) -> f32 {
+    let steps = terraces.steps as f32;
+    let t = h * steps;
+    let step = t.floor();
+    let f = t - step;
+    // Twice smoothed, for flat treads and steep risers
+    let s = smoothstep(0.0, 1.0, smoothstep(0.0, 1.0, f));
+    (step + f.lerp(s, terraces.sharpness)) / steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic() {
+        let settings = NoiseSettings {
+            seed: 42,
+            warp: Some(DomainWarp {
+                amplitude: 0.5,
+                frequency: 1.0,
+            }),
+            ..Default::default()
+        };
+        let a = generate(uvec2(32, 32), &settings);
+        let b = generate(uvec2(32, 32), &settings);
+        assert_eq!(a, b);
+
+        let other_seed = generate(
+            uvec2(32, 32),
+            &NoiseSettings {
+                seed: 43,
+                ..settings
+            },
+        );
+        assert_ne!(a, other_seed);
+    }
+
+    #[test]
+    fn test_range_and_variation() {
+        for basis in [NoiseBasis::Perlin, NoiseBasis::Simplex] {
+            for fractal in [FractalKind::Fbm, FractalKind::Ridged, FractalKind::Billow] {
+                let settings = NoiseSettings {
+                    basis,
+                    fractal,
+                    ..Default::default()
+                };
+                let map = generate(uvec2(64, 64), &settings);
+                let (min, max) = map
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+                        (min.min(*h), max.max(*h))
+                    });
+                assert!(min >= 0.0 && max <= 1.0, "{basis} {fractal}");
+                assert!(max - min > 0.1, "{basis} {fractal} is flat");
+            }
+        }
+    }
+
+    #[test]
+    fn test_island_edges_are_sea() {
+        let settings = NoiseSettings {
+            island: Some(IslandFalloff {
+                radius: 0.8,
+                width: 0.3,
+            }),
+            ..Default::default()
+        };
+        let map = generate(uvec2(64, 64), &settings);
+        assert_eq!(map.get_pixel(0, 0)[0], 0.0);
+        assert_eq!(map.get_pixel(0, 32)[0], 0.0);
+        assert!(map.get_pixel(32, 32)[0] > 0.0);
+    }
+
+    #[test]
+    fn test_sharp_terraces_are_flat() {
+        let terraces = Terracing {
+            steps: 4,
+            sharpness: 1.0,
+        };
+        assert_eq!(terrace(0.25, terraces), 0.25);
+        assert!((terrace(0.26, terraces) - terrace(0.27, terraces)).abs() < 1e-3);
+        assert!(terrace(0.49, terraces) < 0.5);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let settings = NoiseSettings {
+            seed: u64::MAX - 7,
+            basis: NoiseBasis::Simplex,
+            fractal: FractalKind::Ridged,
+            warp: Some(DomainWarp {
+                amplitude: 0.25,
+                frequency: 2.0,
+            }),
+            terraces: Some(Terracing {
+                steps: 8,
+                sharpness: 0.5,
+            }),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join("worldedit_test_settings.wgen");
+        settings.save(&path).unwrap();
+        assert_eq!(NoiseSettings::load(&path).unwrap(), settings);
+    }
+}
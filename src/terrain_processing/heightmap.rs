@@ -5,45 +5,26 @@ use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 
-use image::DynamicImage;
-use image::GenericImageView;
 use image::GrayImage;
-use image::ImageBuffer;
 use image::Luma;
 
+use crate::terrain_processing::WorldSettings;
+
 /// Single channel f32 image for heightmaps
 pub type GrayF32Image = image::ImageBuffer<image::Luma<f32>, Vec<f32>>;
 
 pub const FILE_EXT: &str = "hmp";
 pub const FILE_SIG: &[u8; 16] = b"WEdit-hmp_f32   ";
-pub const FILE_VER: u32 = 0;
-
-pub fn from_dynamic_image(img: DynamicImage) -> GrayF32Image {
-    let (w, h) = img.dimensions();
-    match img {
-        DynamicImage::ImageRgb32F(rgb) => {
-            ImageBuffer::from_fn(w, h, |x, y| Luma([rgb.get_pixel(x, y)[0]]))
-        }
-        DynamicImage::ImageRgba32F(rgba) => {
-            ImageBuffer::from_fn(w, h, |x, y| Luma([rgba.get_pixel(x, y)[0]]))
-        }
-        _ => {
-            println!(
-                "heightmap::from_dynamic_image() WARNING: Image isn't 32bit scalar. Quality will suffer."
-            );
-            let trash = img.to_luma32f();
-            ImageBuffer::from_fn(w, h, |x, y| Luma([trash.get_pixel(x, y)[0]]))
-        }
-    }
-}
+pub const FILE_VER: u32 = 1;
 
-pub fn save(path: &Path, map: &GrayF32Image) -> std::io::Result<()> {
+pub fn save(path: &Path, map: &GrayF32Image, world: &WorldSettings) -> std::io::Result<()> {
     let mut file = BufWriter::new(File::create(path)?);
 
     let (w, h) = map.dimensions();
 
     file.write_all(FILE_SIG)?;
     file.write_all(&FILE_VER.to_le_bytes())?;
+    world.write_header(&mut file)?;
     file.write_all(&w.to_le_bytes())?;
     file.write_all(&h.to_le_bytes())?;
 
@@ -66,7 +47,7 @@ pub fn save_png(path: &Path, map: &GrayF32Image) -> Result<(), image::ImageError
     img.save(path)
 }
 
-pub fn load(path: &Path) -> std::io::Result<GrayF32Image> {
+pub fn load(path: &Path) -> std::io::Result<(GrayF32Image, WorldSettings)> {
     let mut file = BufReader::new(File::open(path)?);
 
     let mut buf = [0u8; 4];
@@ -88,6 +69,8 @@ pub fn load(path: &Path) -> std::io::Result<GrayF32Image> {
         ));
     }
 
+    let world = WorldSettings::read_header(&mut file)?;
+
     file.read_exact(&mut buf)?;
     let width = u32::from_le_bytes(buf);
     file.read_exact(&mut buf)?;
@@ -98,10 +81,12 @@ pub fn load(path: &Path) -> std::io::Result<GrayF32Image> {
     let bytes = bytemuck::cast_slice_mut(&mut pixels);
     file.read_exact(bytes)?;
 
-    GrayF32Image::from_raw(width, height, pixels).ok_or_else(|| {
+    let map = GrayF32Image::from_raw(width, height, pixels).ok_or_else(|| {
         std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             "failed to construct GrayF32Image",
         )
-    })
+    })?;
+
+    Ok((map, world))
 }
@@ -1,21 +1,228 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
 use bevy::math::prelude::*;
+use image::Luma;
 
+use crate::terrain_processing::WorldSettings;
 use crate::terrain_processing::heightmap::GrayF32Image;
 
-use crate::terrain_processing::WORLD_HEIGHT;
-use crate::terrain_processing::WORLD_HEIGHT_OFFSET;
+/// Filtering used by [HeightmapBundle::sample].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeightFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Catmull-Rom. Smooth slopes, but may overshoot around sharp edges.
+    Bicubic,
+}
+
+/// How a [HeightLayer] combines with the layers below it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LayerBlend {
+    #[default]
+    Add,
+    Subtract,
+    Max,
+    Min,
+    Replace,
+}
+
+impl LayerBlend {
+    pub const ALL: [Self; 5] = [
+        Self::Add,
+        Self::Subtract,
+        Self::Max,
+        Self::Min,
+        Self::Replace,
+    ];
+}
+
+/// A non-destructive edit on top of the base map. Values are in base map units, so 1.0 is
+/// the full world height.
+#[derive(Debug, Clone)]
+pub struct HeightLayer {
+    pub name: String,
+    pub blend: LayerBlend,
+    pub enabled: bool,
+    /// Scales the layer's effect, together with [Self::mask]
+    pub opacity: f32,
+    pub map: GrayF32Image,
+    /// Per texel opacity, 0..1. `None` applies the layer everywhere.
+    pub mask: Option<GrayF32Image>,
+}
+
+impl HeightLayer {
+    /// Empty layer for a base map of `size`
+    pub fn new(name: impl Into<String>, blend: LayerBlend, size: UVec2) -> Self {
+        Self {
+            name: name.into(),
+            blend,
+            enabled: true,
+            opacity: 1.0,
+            map: GrayF32Image::new(size.x, size.y),
+            mask: None,
+        }
+    }
+
+    /// Texels per side of [Self::map]
+    pub fn size(&self) -> UVec2 {
+        uvec2(self.map.width(), self.map.height())
+    }
+
+    /// Enabled, fully opaque [LayerBlend::Add] without a mask, so a change to its map
+    /// changes the height by the same amount
+    pub fn is_plain_add(&self) -> bool {
+        self.blend == LayerBlend::Add && self.enabled && self.opacity == 1.0 && self.mask.is_none()
+    }
+
+    /// Blends this layer's texel over `below`
+    pub fn apply(&self, below: f32, position: UVec2) -> f32 {
+        let mut weight = self.opacity;
+        if let Some(mask) = &self.mask {
+            weight *= mask.get_pixel(position.x, position.y)[0];
+        }
+        let value = self.map.get_pixel(position.x, position.y)[0];
+
+        match self.blend {
+            LayerBlend::Add => below + value * weight,
+            LayerBlend::Subtract => below - value * weight,
+            LayerBlend::Max => below.lerp(below.max(value), weight),
+            LayerBlend::Min => below.lerp(below.min(value), weight),
+            LayerBlend::Replace => below.lerp(value, weight),
+        }
+    }
+}
+
+impl HeightLayer {
+    pub const FILE_EXT: &str = "wlayer";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-Layer     ";
+    pub const FILE_VER: u32 = 0;
+    /// Longest name [Self::read] accepts, in bytes
+    const MAX_NAME_LEN: u32 = 1024;
+    /// Widest and tallest map [Self::read] accepts
+    const MAX_SIZE: u32 = 16384;
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+
+        file.write_all(&(self.name.len() as u32).to_le_bytes())?;
+        file.write_all(self.name.as_bytes())?;
+        file.write_all(&(self.blend as u32).to_le_bytes())?;
+        file.write_all(&(self.enabled as u32).to_le_bytes())?;
+        file.write_all(&self.opacity.to_le_bytes())?;
+
+        let size = self.size();
+        file.write_all(&size.x.to_le_bytes())?;
+        file.write_all(&size.y.to_le_bytes())?;
+        file.write_all(bytemuck::cast_slice(self.map.as_raw()))?;
+        match &self.mask {
+            None => file.write_all(&0u32.to_le_bytes())?,
+            Some(mask) => {
+                file.write_all(&1u32.to_le_bytes())?;
+                file.write_all(bytemuck::cast_slice(mask.as_raw()))?;
+            }
+        }
+
+        file.flush()
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Like [Self::load], from anything holding the file contents
+    pub fn read(file: &mut impl Read) -> std::io::Result<Self> {
+        let mut buf = [0u8; 4];
+        let mut sig_buf = [0u8; 16];
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(invalid("invalid FILE_SIG".to_string()));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(invalid(format!(
+                "invalid version: exp '{}', got '{ver}'",
+                Self::FILE_VER
+            )));
+        }
+
+        let mut read_u32 = |file: &mut dyn Read| -> std::io::Result<u32> {
+            file.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
 
-#[derive(Debug)]
+        // Checked before allocating, so a broken file can't ask for too much memory
+        let name_len = read_u32(file)?;
+        if name_len > Self::MAX_NAME_LEN {
+            return Err(invalid(format!("name too long: '{name_len}'")));
+        }
+        let mut name_buf = vec![0u8; name_len as usize];
+        file.read_exact(&mut name_buf)?;
+        let name = String::from_utf8(name_buf).map_err(|e| invalid(e.to_string()))?;
+        let blend = match read_u32(file)? {
+            v if (v as usize) < LayerBlend::ALL.len() => LayerBlend::ALL[v as usize],
+            v => return Err(invalid(format!("invalid blend: '{v}'"))),
+        };
+        let enabled = read_u32(file)? != 0;
+        let opacity = f32::from_bits(read_u32(file)?);
+
+        let (w, h) = (read_u32(file)?, read_u32(file)?);
+        if w == 0 || h == 0 || w > Self::MAX_SIZE || h > Self::MAX_SIZE {
+            return Err(invalid(format!("invalid size: {w} x {h}")));
+        }
+        let read_map = |file: &mut dyn Read| -> std::io::Result<GrayF32Image> {
+            let mut map = GrayF32Image::new(w, h);
+            file.read_exact(bytemuck::cast_slice_mut(&mut map))?;
+            Ok(map)
+        };
+        let map = read_map(file)?;
+        let mask = match read_u32(file)? {
+            0 => None,
+            _ => Some(read_map(file)?),
+        };
+
+        Ok(Self {
+            name,
+            blend,
+            enabled,
+            opacity,
+            map,
+            mask,
+        })
+    }
+}
+
+/// Heightmap stretched over the whole world. Texel `(0, 0)` sits at the world origin and
+/// the last texel at `world_size`.
+#[derive(Debug, Clone)]
 pub struct HeightmapBundle {
     size: UVec2,
     base_map: GrayF32Image,
+    world: WorldSettings,
+    filter: HeightFilter,
+    /// Applied bottom to top over the base map
+    layers: Vec<HeightLayer>,
 }
 
 impl HeightmapBundle {
-    pub fn new(base_map: GrayF32Image) -> Self {
+    pub fn new(base_map: GrayF32Image, world: WorldSettings) -> Self {
         Self {
             size: uvec2(base_map.width(), base_map.height()),
             base_map,
+            world,
+            filter: HeightFilter::default(),
+            layers: Vec::new(),
         }
     }
 
@@ -23,21 +230,522 @@ impl HeightmapBundle {
         self.size
     }
 
+    pub const fn world(&self) -> &WorldSettings {
+        &self.world
+    }
+
     pub const fn base_map(&self) -> &GrayF32Image {
         &self.base_map
     }
 
-    pub fn set_base_map(&mut self, base_map: GrayF32Image) {
+    /// Layers no longer matching the map size are taken out and returned, bottom to top.
+    #[must_use = "dropped layers are lost unless resized and pushed again"]
+    pub fn set_base_map(&mut self, base_map: GrayF32Image) -> Vec<HeightLayer> {
         self.size = uvec2(base_map.width(), base_map.height());
         self.base_map = base_map;
+        let size = self.size;
+        let (kept, dropped) = std::mem::take(&mut self.layers)
+            .into_iter()
+            .partition(|layer| layer_fits(layer, size));
+        self.layers = kept;
+        dropped
     }
 
-    /// Returns height for a given position. Use like a fragment shader.
+    pub fn layers(&self) -> &[HeightLayer] {
+        &self.layers
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> Option<&mut HeightLayer> {
+        self.layers.get_mut(index)
+    }
+
+    /// Adds a layer on top and returns its index. Layer and mask must match [Self::size].
+    pub fn push_layer(&mut self, layer: HeightLayer) -> Result<usize, HeightLayer> {
+        if !layer_fits(&layer, self.size) {
+            return Err(layer);
+        }
+        self.layers.push(layer);
+        Ok(self.layers.len() - 1)
+    }
+
+    /// `None` if there's no layer at `index`.
+    pub fn remove_layer(&mut self, index: usize) -> Option<HeightLayer> {
+        (index < self.layers.len()).then(|| self.layers.remove(index))
+    }
+
+    /// Turns the layer at `index` into a plain additive one (see [HeightLayer::is_plain_add])
+    /// holding the same change in height, so the terrain looks the same. `None` if there's no
+    /// layer at `index`.
+    pub fn bake_layer(&mut self, index: usize) -> Option<()> {
+        let (below, rest) = self.layers.split_at_mut(index);
+        let layer = rest.first_mut()?;
+        let map = GrayF32Image::from_fn(self.size.x, self.size.y, |x, y| {
+            let position = uvec2(x, y);
+            let mut h = self.base_map.get_pixel(x, y)[0];
+            for below in below.iter().filter(|layer| layer.enabled) {
+                h = below.apply(h, position);
+            }
+            let blended = if layer.enabled {
+                layer.apply(h, position)
+            } else {
+                h
+            };
+            Luma([blended - h])
+        });
+        layer.map = map;
+        layer.blend = LayerBlend::Add;
+        layer.enabled = true;
+        layer.opacity = 1.0;
+        layer.mask = None;
+        Some(())
+    }
+
+    /// Moves a layer to `to`, shifting the ones in between. `None`, leaving the layers as
+    /// they were, if either index is out of range.
+    pub fn move_layer(&mut self, from: usize, to: usize) -> Option<()> {
+        if from >= self.layers.len() || to >= self.layers.len() {
+            return None;
+        }
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+        Some(())
+    }
+
+    pub const fn filter(&self) -> HeightFilter {
+        self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: HeightFilter) {
+        self.filter = filter;
+    }
+
+    /// World units between two texels
+    pub fn texel_size(&self) -> Vec2 {
+        let intervals = self.size.max(UVec2::splat(2)) - UVec2::ONE;
+        self.world.world_size as f32 / intervals.as_vec2()
+    }
+
+    /// Returns height for a given texel, with all enabled layers applied. Use like a
+    /// fragment shader.
     pub fn height(&self, mut position: UVec2) -> f32 {
         position = position.min(self.size - UVec2::ONE);
         let mut h = self.base_map.get_pixel(position.x, position.y)[0];
-        h *= WORLD_HEIGHT;
-        h += WORLD_HEIGHT_OFFSET;
+        for layer in self.layers.iter().filter(|layer| layer.enabled) {
+            h = layer.apply(h, position);
+        }
+        h *= self.world.world_height;
+        h += self.world.world_height_offset;
         h
     }
+
+    /// Height at a world position, filtered with [Self::filter]
+    pub fn sample(&self, position: Vec2) -> f32 {
+        self.sample_filtered(position, self.filter)
+    }
+
+    pub fn sample_filtered(&self, position: Vec2, filter: HeightFilter) -> f32 {
+        let p = position / self.texel_size();
+        let p0 = p.floor();
+        let t = p - p0;
+        let p0 = p0.as_ivec2();
+        let texel = |x: i32, y: i32| self.height(ivec2(x, y).max(IVec2::ZERO).as_uvec2());
+
+        match filter {
+            HeightFilter::Nearest => {
+                let p = p.round().as_ivec2();
+                texel(p.x, p.y)
+            }
+            HeightFilter::Bilinear => {
+                let row = |y| texel(p0.x, y).lerp(texel(p0.x + 1, y), t.x);
+                row(p0.y).lerp(row(p0.y + 1), t.y)
+            }
+            HeightFilter::Bicubic => {
+                let row = |y| catmull_rom(t.x, [-1, 0, 1, 2].map(|dx| texel(p0.x + dx, y)));
+                catmull_rom(t.y, [-1, 0, 1, 2].map(|dy| row(p0.y + dy)))
+            }
+        }
+    }
+
+    /// Surface normal at a world position, from central differences one texel apart
+    pub fn normal_at(&self, position: Vec2) -> Vec3 {
+        let step = self.texel_size();
+        let dx =
+            self.sample(position + vec2(step.x, 0.0)) - self.sample(position - vec2(step.x, 0.0));
+        let dz =
+            self.sample(position + vec2(0.0, step.y)) - self.sample(position - vec2(0.0, step.y));
+        vec3(-dx / (2.0 * step.x), 1.0, -dz / (2.0 * step.y)).normalize()
+    }
+
+    /// Steepness at a world position in radians, 0 being flat
+    pub fn slope_at(&self, position: Vec2) -> f32 {
+        self.normal_at(position).y.clamp(-1.0, 1.0).acos()
+    }
+
+    /// First point where `ray` (in world space, origin at texel `(0, 0)`) goes below the
+    /// surface within the world bounds. Only visits the texel quads under the ray, and
+    /// intersects the bilinear surface through their corners exactly.
+    pub fn raycast(&self, ray: Ray3d, max_distance: f32) -> Option<Vec3> {
+        // Clip the ray to the world square
+        let world_size = self.world.world_size as f32;
+        let (mut t_min, mut t_max) = (0.0f32, max_distance);
+        for axis in [0, 2] {
+            let (origin, dir) = (ray.origin[axis], ray.direction[axis]);
+            if dir.abs() < f32::EPSILON {
+                if !(0.0..=world_size).contains(&origin) {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (0.0 - origin) / dir;
+            let t1 = (world_size - origin) / dir;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+        if t_min > t_max {
+            return None;
+        }
+
+        // Starts underground
+        let start = ray.get_point(t_min);
+        if start.y < self.sample_filtered(start.xz(), HeightFilter::Bilinear) {
+            return None;
+        }
+
+        // Walk the texel quads the ray crosses, in texel units
+        let texel_size = self.texel_size();
+        let p = ray.origin.xz() / texel_size;
+        let d = ray.direction.xz() / texel_size;
+        let last_quad = self.size.as_ivec2() - 2;
+        let mut quad = (p + d * t_min)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, last_quad.max(IVec2::ZERO));
+        let step = ivec2(d.x.signum() as i32, d.y.signum() as i32);
+
+        let mut t_enter = t_min;
+        while quad.cmpge(IVec2::ZERO).all() && quad.cmple(last_quad).all() {
+            let exit = |axis: usize| match d[axis] {
+                d if d > 0.0 => (quad[axis] as f32 + 1.0 - p[axis]) / d,
+                d if d < 0.0 => (quad[axis] as f32 - p[axis]) / d,
+                _ => f32::INFINITY,
+            };
+            let (t_x, t_y) = (exit(0), exit(1));
+            let t_exit = t_x.min(t_y).min(t_max);
+
+            if let Some(t) = self.intersect_quad(ray, p, d, quad.as_uvec2(), t_enter, t_exit) {
+                return Some(ray.get_point(t));
+            }
+            if t_exit >= t_max {
+                break;
+            }
+
+            t_enter = t_exit;
+            if t_x < t_y {
+                quad.x += step.x;
+            } else {
+                quad.y += step.y;
+            }
+        }
+        None
+    }
+
+    /// First `t` in `t0..=t1` where `ray` meets the bilinear patch over the quad starting
+    /// at texel `quad`. `p` and `d` are the ray's origin and direction in texels.
+    fn intersect_quad(
+        &self,
+        ray: Ray3d,
+        p: Vec2,
+        d: Vec2,
+        quad: UVec2,
+        t0: f32,
+        t1: f32,
+    ) -> Option<f32> {
+        let h00 = self.height(quad);
+        let h10 = self.height(quad + UVec2::X);
+        let h01 = self.height(quad + UVec2::Y);
+        let h11 = self.height(quad + UVec2::ONE);
+
+        // Passes above the highest corner
+        let lowest = ray.get_point(t0).y.min(ray.get_point(t1).y);
+        if lowest > h00.max(h10).max(h01).max(h11) {
+            return None;
+        }
+
+        // Patch height along the ray is a quadratic in t
+        let (u, v) = (p - quad.as_vec2(), d);
+        let (b, c, e) = (h10 - h00, h01 - h00, h00 - h10 - h01 + h11);
+        let h0 = h00 + b * u.x + c * u.y + e * u.x * u.y;
+        let h1 = b * v.x + c * v.y + e * (u.x * v.y + u.y * v.x);
+        let h2 = e * v.x * v.y;
+
+        // Ray minus surface, descending through zero at the hit
+        let (f0, f1, f2) = (ray.origin.y - h0, ray.direction.y - h1, -h2);
+        let f = |t: f32| f0 + f1 * t + f2 * t * t;
+        let in_range = |t: &f32| (t0 - 1e-4..=t1 + 1e-4).contains(t);
+
+        // The stable form, as f2 gets tiny for rays along an axis
+        let mut roots = [f32::NAN; 2];
+        let discriminant = f1 * f1 - 4.0 * f2 * f0;
+        if discriminant >= 0.0 {
+            let q = -0.5 * (f1 + f1.signum() * discriminant.sqrt());
+            roots = [q / f2, f0 / q];
+        }
+        let first = roots
+            .into_iter()
+            .filter(in_range)
+            .min_by(f32::total_cmp)
+            .map(|t| t.clamp(t0, t1));
+        // Grazing rays can miss the roots by rounding, but not end up below the patch
+        first.or_else(|| (f(t1) < 0.0).then_some(t1))
+    }
+}
+
+fn layer_fits(layer: &HeightLayer, size: UVec2) -> bool {
+    let fits = |map: &GrayF32Image| map.dimensions() == (size.x, size.y);
+    fits(&layer.map) && layer.mask.as_ref().is_none_or(fits)
+}
+
+/// Interpolates between `p[1]` and `p[2]`
+fn catmull_rom(t: f32, p: [f32; 4]) -> f32 {
+    let [p0, p1, p2, p3] = p;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+        + (3.0 * (p1 - p2) + p3 - p0) * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// 5x5 texels over a 4 unit world, one texel per unit
+    fn test_bundle(f: impl Fn(u32, u32) -> f32) -> HeightmapBundle {
+        let map = GrayF32Image::from_fn(5, 5, |x, y| Luma([f(x, y)]));
+        let world = WorldSettings {
+            world_size: 4,
+            cell_size: 4,
+            world_height: 1.0,
+            world_height_offset: 0.0,
+        };
+        HeightmapBundle::new(map, world)
+    }
+
+    #[test]
+    fn test_sample_hits_texels() {
+        let h_bundle = test_bundle(|x, y| ((x * 7 + y * 3) % 5) as f32);
+        for filter in [
+            HeightFilter::Nearest,
+            HeightFilter::Bilinear,
+            HeightFilter::Bicubic,
+        ] {
+            for y in 0..5 {
+                for x in 0..5 {
+                    let p = uvec2(x, y);
+                    let h = h_bundle.sample_filtered(p.as_vec2(), filter);
+                    assert!((h - h_bundle.height(p)).abs() < 1e-5, "{filter:?} at {p}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sample_between_texels() {
+        let h_bundle = test_bundle(|x, _| x as f32);
+        let p = vec2(1.25, 2.5);
+        assert_eq!(h_bundle.sample_filtered(p, HeightFilter::Bilinear), 1.25);
+        // Catmull-Rom reproduces linear ramps
+        assert!((h_bundle.sample_filtered(p, HeightFilter::Bicubic) - 1.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_layer_blending() {
+        let mut h_bundle = test_bundle(|_, _| 0.5);
+        let size = h_bundle.size();
+        let p = uvec2(1, 1);
+
+        let mut layer = HeightLayer::new("bump", LayerBlend::Add, size);
+        layer.map.fill(0.25);
+        let bump = h_bundle.push_layer(layer).unwrap();
+        assert_eq!(h_bundle.height(p), 0.75);
+
+        for (blend, expected) in [
+            (LayerBlend::Add, 0.75),
+            (LayerBlend::Subtract, 0.25),
+            (LayerBlend::Max, 0.5),
+            (LayerBlend::Min, 0.25),
+            (LayerBlend::Replace, 0.25),
+        ] {
+            h_bundle.layer_mut(bump).unwrap().blend = blend;
+            assert_eq!(h_bundle.height(p), expected, "{blend:?}");
+        }
+
+        h_bundle.layer_mut(bump).unwrap().enabled = false;
+        assert_eq!(h_bundle.height(p), 0.5);
+    }
+
+    #[test]
+    fn test_layer_mask_and_order() {
+        let mut h_bundle = test_bundle(|_, _| 0.0);
+        let size = h_bundle.size();
+
+        let mut add = HeightLayer::new("add", LayerBlend::Add, size);
+        add.map.fill(1.0);
+        add.opacity = 0.5;
+        add.mask = Some(GrayF32Image::from_fn(5, 5, |x, _| Luma([x as f32 / 4.0])));
+        h_bundle.push_layer(add).unwrap();
+        assert_eq!(h_bundle.height(uvec2(0, 2)), 0.0);
+        assert_eq!(h_bundle.height(uvec2(4, 2)), 0.5);
+
+        let mut floor = HeightLayer::new("floor", LayerBlend::Max, size);
+        floor.map.fill(0.25);
+        h_bundle.push_layer(floor).unwrap();
+        assert_eq!(h_bundle.height(uvec2(0, 2)), 0.25);
+        assert_eq!(h_bundle.height(uvec2(4, 2)), 0.5);
+
+        // Raising the floor first, then adding on top
+        h_bundle.move_layer(1, 0).unwrap();
+        assert_eq!(h_bundle.layers()[0].name, "floor");
+        assert_eq!(h_bundle.height(uvec2(4, 2)), 0.75);
+
+        assert_eq!(h_bundle.move_layer(0, 2), None);
+        assert!(h_bundle.remove_layer(2).is_none());
+        assert_eq!(h_bundle.remove_layer(0).unwrap().name, "floor");
+        assert_eq!(h_bundle.layers()[0].name, "add");
+    }
+
+    #[test]
+    fn test_bake_layer_keeps_heights() {
+        let mut h_bundle = test_bundle(|x, _| x as f32 * 0.1);
+        let size = h_bundle.size();
+        let mut layer = HeightLayer::new("Max", LayerBlend::Max, size);
+        layer.opacity = 0.5;
+        layer.map = GrayF32Image::from_fn(size.x, size.y, |_, y| Luma([y as f32 * 0.2]));
+        layer.mask = Some(GrayF32Image::from_fn(size.x, size.y, |x, _| {
+            Luma([if x % 2 == 0 { 1.0 } else { 0.25 }])
+        }));
+        h_bundle.push_layer(layer).unwrap();
+        let heights = |h_bundle: &HeightmapBundle| -> Vec<f32> {
+            (0..size.y)
+                .flat_map(|y| (0..size.x).map(move |x| uvec2(x, y)))
+                .map(|texel| h_bundle.height(texel))
+                .collect()
+        };
+        let before = heights(&h_bundle);
+
+        h_bundle.bake_layer(0).unwrap();
+        assert!(h_bundle.layers()[0].is_plain_add());
+        for (a, b) in before.iter().zip(heights(&h_bundle)) {
+            assert!((a - b).abs() < 1e-4, "{a} {b}");
+        }
+        assert_eq!(h_bundle.bake_layer(1), None);
+    }
+
+    #[test]
+    fn test_layer_size_mismatch() {
+        let mut h_bundle = test_bundle(|_, _| 0.0);
+        let layer = HeightLayer::new("small", LayerBlend::Add, uvec2(2, 2));
+        assert!(h_bundle.push_layer(layer).is_err());
+
+        let layer = HeightLayer::new("full", LayerBlend::Add, h_bundle.size());
+        h_bundle.push_layer(layer).unwrap();
+        let dropped = h_bundle.set_base_map(GrayF32Image::new(3, 3));
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].name, "full");
+        assert!(h_bundle.layers().is_empty());
+    }
+
+    #[test]
+    fn test_layer_save_load() {
+        let mut layer = HeightLayer::new("Sculpt", LayerBlend::Max, uvec2(5, 3));
+        layer.enabled = false;
+        layer.opacity = 0.75;
+        layer.map = GrayF32Image::from_fn(5, 3, |x, y| Luma([x as f32 - y as f32 * 0.5]));
+        layer.mask = Some(GrayF32Image::from_fn(5, 3, |x, _| Luma([x as f32 / 4.0])));
+
+        let path = std::env::temp_dir().join("worldedit_test_layer.wlayer");
+        layer.save(&path).unwrap();
+        let loaded = HeightLayer::load(&path).unwrap();
+        assert_eq!(loaded.name, layer.name);
+        assert_eq!(loaded.blend, layer.blend);
+        assert_eq!(loaded.enabled, layer.enabled);
+        assert_eq!(loaded.opacity, layer.opacity);
+        assert_eq!(loaded.map, layer.map);
+        assert_eq!(loaded.mask, layer.mask);
+    }
+
+    #[test]
+    fn test_layer_read_rejects_huge_sizes() {
+        let header = |values: &[u32]| -> Vec<u8> {
+            let mut bytes = HeightLayer::FILE_SIG.to_vec();
+            bytes.extend(HeightLayer::FILE_VER.to_le_bytes());
+            bytes.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+            bytes
+        };
+        let huge_name = header(&[u32::MAX]);
+        let err = HeightLayer::read(&mut huge_name.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // Empty name, Add, enabled, opacity 1, then a size whose texel count overflows u32
+        let huge_map = header(&[0, 0, 1, 1.0f32.to_bits(), 70000, 70000]);
+        let err = HeightLayer::read(&mut huge_map.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_raycast() {
+        let h_bundle = test_bundle(|x, _| x as f32 * 0.25);
+        let down = Ray3d::new(vec3(2.0, 10.0, 1.0), Dir3::NEG_Y);
+        let hit = h_bundle.raycast(down, 100.0).unwrap();
+        assert!(hit.abs_diff_eq(vec3(2.0, 0.5, 1.0), 1e-4));
+
+        let outside = Ray3d::new(vec3(-1.0, 10.0, 1.0), Dir3::NEG_Y);
+        assert_eq!(h_bundle.raycast(outside, 100.0), None);
+
+        let up = Ray3d::new(vec3(2.0, 10.0, 1.0), Dir3::Y);
+        assert_eq!(h_bundle.raycast(up, 100.0), None);
+
+        let underground = Ray3d::new(vec3(2.0, 0.1, 1.0), Dir3::X);
+        assert_eq!(h_bundle.raycast(underground, 100.0), None);
+    }
+
+    #[test]
+    fn test_raycast_grazing_spike() {
+        // One texel spike, clipped by a nearly flat ray just below its tip
+        let h_bundle = test_bundle(|x, y| if (x, y) == (2, 2) { 1.0 } else { 0.0 });
+        let direction = Dir3::new(vec3(1.0, -0.01, 0.0)).unwrap();
+        let ray = Ray3d::new(vec3(0.0, 0.99, 2.0), direction);
+        let hit = h_bundle.raycast(ray, 100.0).unwrap();
+
+        assert!((hit.y - h_bundle.sample(hit.xz())).abs() < 1e-4);
+        assert!(hit.x > 1.0 && hit.x < 2.0, "{hit}");
+    }
+
+    #[test]
+    fn test_raycast_matches_surface() {
+        let h_bundle = test_bundle(|x, y| ((x * 7 + y * 3) % 5) as f32 * 0.2);
+        for i in 0..32 {
+            let angle = i as f32 / 32.0 * std::f32::consts::TAU;
+            let direction = Dir3::new(Vec2::from_angle(angle).extend(-0.4).xzy()).unwrap();
+            // Through the middle, low enough to land before the far edge
+            let ray = Ray3d::new(vec3(2.0, 0.5, 2.0) - direction * 4.0, direction);
+            let hit = h_bundle.raycast(ray, 100.0).unwrap();
+            assert!((hit.y - h_bundle.sample(hit.xz())).abs() < 1e-3, "{hit}");
+        }
+    }
+
+    #[test]
+    fn test_normal_and_slope() {
+        let h_bundle = test_bundle(|x, _| x as f32);
+        let p = vec2(2.0, 2.0);
+        assert!(
+            h_bundle
+                .normal_at(p)
+                .abs_diff_eq(vec3(-1.0, 1.0, 0.0).normalize(), 1e-5)
+        );
+        assert!((h_bundle.slope_at(p) - std::f32::consts::FRAC_PI_4).abs() < 1e-5);
+
+        let flat = test_bundle(|_, _| 0.5);
+        assert_eq!(flat.slope_at(p), 0.0);
+    }
 }
@@ -0,0 +1,288 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::math::prelude::*;
+use derive_more::Display;
+use image::ImageBuffer;
+use image::ImageFormat;
+use image::Luma;
+
+use crate::terrain_processing::HeightmapBundle;
+use crate::terrain_processing::heightmap::GrayF32Image;
+
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// 16-bit grayscale, stretched over the terrain's height range
+    #[default]
+    #[display("PNG 16-bit")]
+    Png16,
+    /// Headerless little-endian u16, stretched over the terrain's height range. Unity's
+    /// terrain import format.
+    #[display("Raw 16-bit")]
+    R16,
+    /// Single channel 32-bit float world heights
+    #[display("TIFF 32-bit float")]
+    TiffF32,
+    /// 32-bit float world heights in a single `Y` channel
+    #[display("EXR 32-bit float")]
+    ExrF32,
+}
+
+impl ExportFormat {
+    pub const ALL: [Self; 4] = [Self::Png16, Self::R16, Self::TiffF32, Self::ExrF32];
+
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Self::Png16 => "png",
+            Self::R16 => "r16",
+            Self::TiffF32 => "tif",
+            Self::ExrF32 => "exr",
+        }
+    }
+
+    /// Sample type, as written to the sidecar
+    pub const fn sample(&self) -> &'static str {
+        match self {
+            Self::Png16 | Self::R16 => "u16",
+            Self::TiffF32 | Self::ExrF32 => "f32",
+        }
+    }
+}
+
+/// Written next to exported heightmaps, see [sidecar_path].
+///
+/// World height of a sample is `sample * height_scale + height_offset`, with 16-bit
+/// samples normalized to 0..1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportMetadata {
+    /// Heightmap file name, next to the sidecar
+    pub file: String,
+    pub format: ExportFormat,
+    pub width: u32,
+    pub height: u32,
+    pub world_size: u32,
+    pub height_scale: f32,
+    pub height_offset: f32,
+}
+
+#[derive(Debug, Display)]
+pub enum ExportError {
+    #[display("failed to write '{}': {_1}", _0.display())]
+    Io(PathBuf, std::io::Error),
+    #[display("failed to write '{}': {_1}", _0.display())]
+    Image(PathBuf, image::ImageError),
+    #[display("failed to write '{}': {_1}", _0.display())]
+    Tiff(PathBuf, tiff::TiffError),
+    #[display("failed to write '{}': {_1}", _0.display())]
+    Exr(PathBuf, exr::error::Error),
+}
+
+impl std::error::Error for ExportError {}
+
+impl ExportMetadata {
+    pub fn to_json(&self) -> String {
+        let json = serde_json::json!({
+            "file": self.file,
+            "sample": self.format.sample(),
+            "channels": 1,
+            "width": self.width,
+            "height": self.height,
+            "world_size": self.world_size,
+            "height_scale": self.height_scale,
+            "height_offset": self.height_offset,
+        });
+        // Can't fail, the keys are strings
+        serde_json::to_string_pretty(&json).unwrap() + "\n"
+    }
+}
+
+/// `heightmap.png` -> `heightmap.png.json`
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+/// Writes the bundle's world heights to `path`, plus an [ExportMetadata] sidecar.
+pub fn export(
+    path: &Path,
+    h_bundle: &HeightmapBundle,
+    format: ExportFormat,
+) -> Result<ExportMetadata, ExportError> {
+    let size = h_bundle.size();
+    let heights: Vec<f32> = (0..size.y)
+        .flat_map(|y| (0..size.x).map(move |x| uvec2(x, y)))
+        .map(|position| h_bundle.height(position))
+        .collect();
+
+    let (min, max) = heights
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+            (min.min(*h), max.max(*h))
+        });
+    let (height_scale, height_offset) = match format {
+        ExportFormat::Png16 | ExportFormat::R16 => ((max - min).max(f32::EPSILON), min),
+        ExportFormat::TiffF32 | ExportFormat::ExrF32 => (1.0, 0.0),
+    };
+    let quantize = |h: &f32| ((h - height_offset) / height_scale * u16::MAX as f32).round() as u16;
+
+    let image_err = |e| ExportError::Image(path.to_owned(), e);
+    match format {
+        ExportFormat::Png16 => {
+            let pixels = heights.iter().map(quantize).collect();
+            ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(size.x, size.y, pixels)
+                .unwrap()
+                .save_with_format(path, ImageFormat::Png)
+                .map_err(image_err)?;
+        }
+        ExportFormat::R16 => {
+            let bytes: Vec<u8> = heights
+                .iter()
+                .flat_map(|h| quantize(h).to_le_bytes())
+                .collect();
+            std::fs::write(path, bytes).map_err(io_err(path))?;
+        }
+        ExportFormat::TiffF32 => write_tiff(path, size, &heights)?,
+        ExportFormat::ExrF32 => write_exr(path, size, heights)?,
+    }
+
+    let metadata = ExportMetadata {
+        file: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        format,
+        width: size.x,
+        height: size.y,
+        world_size: h_bundle.world().world_size,
+        height_scale,
+        height_offset,
+    };
+    let json_path = sidecar_path(path);
+    let mut file = BufWriter::new(File::create(&json_path).map_err(io_err(&json_path))?);
+    file.write_all(metadata.to_json().as_bytes())
+        .map_err(io_err(&json_path))?;
+
+    Ok(metadata)
+}
+
+/// Writes heightmap values as they are to a 32-bit float EXR, e.g. to import a map put
+/// together with [heightmap_ops](crate::terrain_processing::heightmap_ops).
+pub fn save_exr(path: &Path, map: &GrayF32Image) -> Result<(), ExportError> {
+    let size = UVec2::from(map.dimensions());
+    write_exr(path, size, map.as_raw().clone())
+}
+
+/// `image` only writes RGB(A) float images, so these go through the format crates
+fn write_tiff(path: &Path, size: UVec2, heights: &[f32]) -> Result<(), ExportError> {
+    let tiff_err = |e| ExportError::Tiff(path.to_owned(), e);
+    let file = BufWriter::new(File::create(path).map_err(io_err(path))?);
+    tiff::encoder::TiffEncoder::new(file)
+        .map_err(tiff_err)?
+        .write_image::<tiff::encoder::colortype::Gray32Float>(size.x, size.y, heights)
+        .map_err(tiff_err)
+}
+
+fn write_exr(path: &Path, size: UVec2, heights: Vec<f32>) -> Result<(), ExportError> {
+    use exr::prelude::*;
+
+    let channel = AnyChannel::new("Y", FlatSamples::F32(heights));
+    let image = Image::from_channels(
+        (size.x as usize, size.y as usize),
+        AnyChannels::sort(vec![channel].into()),
+    );
+    image
+        .write()
+        .to_file(path)
+        .map_err(|e| ExportError::Exr(path.to_owned(), e))
+}
+
+fn io_err(path: &Path) -> impl FnOnce(std::io::Error) -> ExportError {
+    let path = path.to_owned();
+    move |e| ExportError::Io(path, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain_processing::WorldSettings;
+    use crate::terrain_processing::heightmap::GrayF32Image;
+    use crate::terrain_processing::heightmap_import;
+    use crate::terrain_processing::heightmap_import::ImportSettings;
+
+    fn test_bundle() -> HeightmapBundle {
+        let map = GrayF32Image::from_fn(8, 4, |x, y| Luma([(x + y * 8) as f32 / 31.0]));
+        HeightmapBundle::new(map, WorldSettings::default())
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let h_bundle = test_bundle();
+        let world = h_bundle.world();
+        let dir = std::env::temp_dir();
+
+        for format in ExportFormat::ALL {
+            let path = dir
+                .join("worldedit_test_export")
+                .with_extension(format.extension());
+            let metadata = export(&path, &h_bundle, format).unwrap();
+            assert_eq!((metadata.width, metadata.height), (8, 4));
+            assert_eq!(metadata.file, path.file_name().unwrap().to_string_lossy());
+            assert!(sidecar_path(&path).exists());
+
+            let import = match format {
+                ExportFormat::R16 => ImportSettings {
+                    format: heightmap_import::ImportFormat::Raw(heightmap_import::RawFormat {
+                        width: 8,
+                        height: 4,
+                        sample: heightmap_import::RawSample::U16,
+                        endian: heightmap_import::Endian::Little,
+                    }),
+                    ..Default::default()
+                },
+                _ => ImportSettings::default(),
+            };
+            let map = heightmap_import::import(&path, &import).unwrap();
+
+            // 16 bits over the world height
+            let tolerance = world.world_height / u16::MAX as f32;
+            for (x, y, sample) in map.enumerate_pixels() {
+                let h = sample[0] * metadata.height_scale + metadata.height_offset;
+                let expected = h_bundle.height(uvec2(x, y));
+                assert!(
+                    (h - expected).abs() <= tolerance,
+                    "{format:?}: {h} != {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sidecar_json() {
+        let metadata = ExportMetadata {
+            file: "my \"terrain\"\\v2.tif".to_string(),
+            format: ExportFormat::TiffF32,
+            width: 8,
+            height: 4,
+            world_size: 1024,
+            height_scale: 1.0,
+            height_offset: 0.0,
+        };
+        let json: serde_json::Value = serde_json::from_str(&metadata.to_json()).unwrap();
+        assert_eq!(json["file"], "my \"terrain\"\\v2.tif");
+        assert_eq!(json["sample"], "f32");
+        assert_eq!(json["width"], 8);
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(
+            sidecar_path(Path::new("out/terrain.r16")),
+            Path::new("out/terrain.r16.json")
+        );
+    }
+}
@@ -0,0 +1,753 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::log::warn;
+use bevy::math::prelude::*;
+use derive_more::Display;
+use image::DynamicImage;
+use image::ImageFormat;
+use image::ImageReader;
+use image::Luma;
+use tiff::decoder::DecodingResult;
+
+use crate::terrain_processing::heightmap::GrayF32Image;
+use crate::terrain_processing::heightmap_ops;
+use crate::terrain_processing::heightmap_ops::HeightmapOpError;
+use crate::terrain_processing::heightmap_ops::ResampleFilter;
+use crate::terrain_processing::heightmap_ops::Rotation;
+use crate::terrain_processing::terrain_cruncher::DEFAULT_SOURCE;
+
+/// How to read a source heightmap, see [import].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImportSettings {
+    pub format: ImportFormat,
+    pub range: HeightRange,
+    pub layout: ImportLayout,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// PNG, EXR, TIFF, ... guessed from the file contents. 16-bit and float images are
+    /// read without quantization, including single channel EXRs and TIFFs.
+    #[default]
+    Image,
+    /// Headerless samples, row by row, as exported by World Machine, Gaea, etc.
+    Raw(RawFormat),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFormat {
+    pub width: u32,
+    pub height: u32,
+    pub sample: RawSample,
+    pub endian: Endian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawSample {
+    /// `.r16`, normalized to 0..1
+    U16,
+    /// `.r32`, float
+    F32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Maps source values to heightmap values (0..1 covers the world height).
+///
+/// Integer samples are normalized to 0..1 before mapping, float samples are used as is.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum HeightRange {
+    /// Keep source values
+    #[default]
+    Source,
+    /// Stretch the lowest and highest source value to 0..1
+    Normalize,
+    /// Source `min` maps to 0 and `max` to 1
+    Custom { min: f32, max: f32 },
+}
+
+/// Which source texels make up the heightmap. Applied after [HeightRange], in field order.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImportLayout {
+    /// Source texels to keep, the whole image if `None`
+    pub crop: Option<URect>,
+    pub rotation: Rotation,
+    /// Texels, wrapping around the edges
+    pub offset: IVec2,
+    /// Texels per side of the result, the cropped size if `None`
+    pub resample: Option<(UVec2, ResampleFilter)>,
+}
+
+impl ImportLayout {
+    /// Size of the heightmap made from a `source_size` source
+    pub fn output_size(&self, source_size: UVec2) -> UVec2 {
+        if let Some((size, _)) = self.resample {
+            return size;
+        }
+        let size = self.crop.map(|crop| crop.size()).unwrap_or(source_size);
+        match self.rotation {
+            Rotation::Cw90 | Rotation::Cw270 => size.yx(),
+            Rotation::None | Rotation::Cw180 => size,
+        }
+    }
+
+    pub fn apply(&self, map: GrayF32Image) -> Result<GrayF32Image, HeightmapOpError> {
+        let mut map = match self.crop {
+            Some(crop) => heightmap_ops::crop(&map, crop.min, crop.size())?,
+            None => map,
+        };
+        if self.rotation != Rotation::None {
+            map = heightmap_ops::rotate(&map, self.rotation);
+        }
+        if self.offset != IVec2::ZERO {
+            map = heightmap_ops::offset(&map, self.offset);
+        }
+        if let Some((size, filter)) = self.resample
+            && UVec2::from(map.dimensions()) != size
+        {
+            map = heightmap_ops::resample(&map, size, filter);
+        }
+        Ok(map)
+    }
+}
+
+/// The project's source heightmap file and how to read it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightmapSource {
+    pub path: PathBuf,
+    pub import: ImportSettings,
+}
+
+impl Default for HeightmapSource {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from(DEFAULT_SOURCE),
+            import: ImportSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum ImportError {
+    #[display("failed to read '{}': {_1}", _0.display())]
+    Io(PathBuf, std::io::Error),
+    #[display("failed to decode '{}': {_1}", _0.display())]
+    Image(PathBuf, image::ImageError),
+    #[display("failed to decode TIFF '{}': {_1}", _0.display())]
+    Tiff(PathBuf, tiff::TiffError),
+    #[display("failed to decode EXR '{}': {_1}", _0.display())]
+    Exr(PathBuf, exr::error::Error),
+    #[display("'{}' has {_1} bytes, expected {_2} for the given dimensions", _0.display())]
+    RawSize(PathBuf, usize, usize),
+    #[display("raw dimensions for '{}' must be non-zero", _0.display())]
+    RawEmpty(PathBuf),
+    #[display("invalid height range: min {_0}, max {_1}")]
+    InvalidRange(f32, f32),
+    #[display("failed to lay out '{}': {_1}", _0.display())]
+    Layout(PathBuf, HeightmapOpError),
+}
+
+impl std::error::Error for ImportError {}
+
+impl ImportSettings {
+    /// Binary encoding, as stored in [HeightmapSource::FILE_NAME]
+    pub fn write(&self, file: &mut impl Write) -> std::io::Result<()> {
+        // Enums: a u32 tag, then their values
+        let mut values: Vec<u32> = Vec::new();
+        match self.format {
+            ImportFormat::Image => values.push(0),
+            ImportFormat::Raw(raw) => values.extend([
+                1,
+                raw.width,
+                raw.height,
+                raw.sample as u32,
+                raw.endian as u32,
+            ]),
+        }
+        match self.range {
+            HeightRange::Source => values.push(0),
+            HeightRange::Normalize => values.push(1),
+            HeightRange::Custom { min, max } => values.extend([2, min.to_bits(), max.to_bits()]),
+        }
+        let layout = &self.layout;
+        match layout.crop {
+            None => values.push(0),
+            Some(crop) => values.extend([1, crop.min.x, crop.min.y, crop.max.x, crop.max.y]),
+        }
+        values.push(layout.rotation as u32);
+        values.extend([layout.offset.x as u32, layout.offset.y as u32]);
+        match layout.resample {
+            None => values.push(0),
+            Some((size, filter)) => values.extend([1, size.x, size.y, filter as u32]),
+        }
+        for value in values {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl RawFormat {
+    pub const fn num_bytes(&self) -> usize {
+        let sample_size = match self.sample {
+            RawSample::U16 => 2,
+            RawSample::F32 => 4,
+        };
+        self.width as usize * self.height as usize * sample_size
+    }
+}
+
+/// Reads a heightmap file.
+pub fn import(path: &Path, settings: &ImportSettings) -> Result<GrayF32Image, ImportError> {
+    let bytes = std::fs::read(path).map_err(|e| ImportError::Io(path.to_owned(), e))?;
+    import_bytes(path, &bytes, settings)
+}
+
+/// Like [import], for file contents that were already read. `path` is only used for errors.
+pub fn import_bytes(
+    path: &Path,
+    bytes: &[u8],
+    settings: &ImportSettings,
+) -> Result<GrayF32Image, ImportError> {
+    let mut map = match settings.format {
+        ImportFormat::Image => {
+            let image_err = |e| ImportError::Image(path.to_owned(), e);
+            let reader = ImageReader::new(Cursor::new(bytes))
+                .with_guessed_format()
+                .map_err(|e| image_err(e.into()))?;
+            // `image` only decodes RGB(A) float images, heightmaps tend to have one channel
+            let single_channel = match reader.format() {
+                Some(ImageFormat::Tiff) => {
+                    gray_tiff_heights(bytes).map_err(|e| ImportError::Tiff(path.to_owned(), e))?
+                }
+                Some(ImageFormat::OpenExr) => {
+                    Some(exr_heights(bytes).map_err(|e| ImportError::Exr(path.to_owned(), e))?)
+                }
+                _ => None,
+            };
+            match single_channel {
+                Some(map) => map,
+                None => image_heights(reader.decode().map_err(image_err)?),
+            }
+        }
+        ImportFormat::Raw(raw) => raw_heights(path, bytes, &raw)?,
+    };
+    map_range(&mut map, settings.range)?;
+    settings
+        .layout
+        .apply(map)
+        .map_err(|e| ImportError::Layout(path.to_owned(), e))
+}
+
+/// First channel of `img`, integer formats normalized to 0..1.
+pub fn image_heights(img: DynamicImage) -> GrayF32Image {
+    let (w, h) = (img.width(), img.height());
+    match img {
+        DynamicImage::ImageLuma16(luma) => GrayF32Image::from_fn(w, h, |x, y| {
+            Luma([luma.get_pixel(x, y)[0] as f32 / u16::MAX as f32])
+        }),
+        DynamicImage::ImageLumaA16(luma) => GrayF32Image::from_fn(w, h, |x, y| {
+            Luma([luma.get_pixel(x, y)[0] as f32 / u16::MAX as f32])
+        }),
+        DynamicImage::ImageRgb16(rgb) => GrayF32Image::from_fn(w, h, |x, y| {
+            Luma([rgb.get_pixel(x, y)[0] as f32 / u16::MAX as f32])
+        }),
+        DynamicImage::ImageRgba16(rgba) => GrayF32Image::from_fn(w, h, |x, y| {
+            Luma([rgba.get_pixel(x, y)[0] as f32 / u16::MAX as f32])
+        }),
+        DynamicImage::ImageRgb32F(rgb) => {
+            GrayF32Image::from_fn(w, h, |x, y| Luma([rgb.get_pixel(x, y)[0]]))
+        }
+        DynamicImage::ImageRgba32F(rgba) => {
+            GrayF32Image::from_fn(w, h, |x, y| Luma([rgba.get_pixel(x, y)[0]]))
+        }
+        _ => {
+            warn!("Heightmap image is 8-bit, quality will suffer");
+            let rgb = img.to_rgb8();
+            GrayF32Image::from_fn(w, h, |x, y| {
+                Luma([rgb.get_pixel(x, y)[0] as f32 / u8::MAX as f32])
+            })
+        }
+    }
+}
+
+/// Single channel TIFFs, integer formats normalized to 0..1. `None` for other color types.
+fn gray_tiff_heights(bytes: &[u8]) -> tiff::TiffResult<Option<GrayF32Image>> {
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes))?;
+    let color_type = decoder.colortype()?;
+    if !matches!(color_type, tiff::ColorType::Gray(_)) {
+        return Ok(None);
+    }
+    let (w, h) = decoder.dimensions()?;
+
+    let pixels = match decoder.read_image()? {
+        DecodingResult::U8(samples) => {
+            warn!("Heightmap image is 8-bit, quality will suffer");
+            samples.iter().map(|s| *s as f32 / u8::MAX as f32).collect()
+        }
+        DecodingResult::U16(samples) => samples
+            .iter()
+            .map(|s| *s as f32 / u16::MAX as f32)
+            .collect(),
+        DecodingResult::F16(samples) => samples.iter().map(|s| s.to_f32()).collect(),
+        DecodingResult::F32(samples) => samples,
+        DecodingResult::F64(samples) => samples.iter().map(|s| *s as f32).collect(),
+        _ => {
+            return Err(tiff::TiffError::UnsupportedError(
+                tiff::TiffUnsupportedError::UnsupportedColorType(color_type),
+            ));
+        }
+    };
+    GrayF32Image::from_raw(w, h, pixels)
+        .map(Some)
+        .ok_or(tiff::TiffError::FormatError(
+            tiff::TiffFormatError::InconsistentSizesEncountered,
+        ))
+}
+
+/// The Y, R or else first channel of the first layer of an EXR
+fn exr_heights(bytes: &[u8]) -> exr::error::Result<GrayF32Image> {
+    use exr::prelude::*;
+
+    let image = exr::image::read::read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(Cursor::new(bytes))?;
+
+    let layer = image.layer_data;
+    let channels = &layer.channel_data.list;
+    let channel = ["Y", "R"]
+        .iter()
+        .find_map(|name| channels.iter().find(|c| c.name == **name))
+        .or(channels.first())
+        .ok_or(Error::Invalid("no channels".into()))?;
+    let pixels = channel.sample_data.values_as_f32().collect();
+    let (w, h) = (layer.size.width() as u32, layer.size.height() as u32);
+    GrayF32Image::from_raw(w, h, pixels).ok_or(Error::Invalid("channel size".into()))
+}
+
+fn raw_heights(path: &Path, bytes: &[u8], raw: &RawFormat) -> Result<GrayF32Image, ImportError> {
+    if raw.width == 0 || raw.height == 0 {
+        return Err(ImportError::RawEmpty(path.to_owned()));
+    }
+    if bytes.len() != raw.num_bytes() {
+        return Err(ImportError::RawSize(
+            path.to_owned(),
+            bytes.len(),
+            raw.num_bytes(),
+        ));
+    }
+
+    let pixels = match raw.sample {
+        RawSample::U16 => bytes
+            .chunks_exact(2)
+            .map(|b| {
+                let b = [b[0], b[1]];
+                let v = match raw.endian {
+                    Endian::Little => u16::from_le_bytes(b),
+                    Endian::Big => u16::from_be_bytes(b),
+                };
+                v as f32 / u16::MAX as f32
+            })
+            .collect(),
+        RawSample::F32 => bytes
+            .chunks_exact(4)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                match raw.endian {
+                    Endian::Little => f32::from_le_bytes(b),
+                    Endian::Big => f32::from_be_bytes(b),
+                }
+            })
+            .collect(),
+    };
+
+    // Size was checked above
+    Ok(GrayF32Image::from_raw(raw.width, raw.height, pixels).unwrap())
+}
+
+fn map_range(map: &mut GrayF32Image, range: HeightRange) -> Result<(), ImportError> {
+    let (min, max) = match range {
+        HeightRange::Source => return Ok(()),
+        HeightRange::Normalize => map
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+                (min.min(*h), max.max(*h))
+            }),
+        HeightRange::Custom { min, max } => (min, max),
+    };
+
+    // A flat map has nothing to stretch
+    if range == HeightRange::Normalize && min == max {
+        map.fill(0.0);
+        return Ok(());
+    }
+    if !(min.is_finite() && max.is_finite() && min != max) {
+        return Err(ImportError::InvalidRange(min, max));
+    }
+
+    let scale = 1.0 / (max - min);
+    for h in map.iter_mut() {
+        *h = (*h - min) * scale;
+    }
+    Ok(())
+}
+
+impl HeightmapSource {
+    pub const FILE_NAME: &str = "source.wsrc";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-Source    ";
+    pub const FILE_VER: u32 = 0;
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+
+        let source_path = self.path.to_string_lossy();
+        file.write_all(&(source_path.len() as u32).to_le_bytes())?;
+        file.write_all(source_path.as_bytes())?;
+
+        self.import.write(&mut file)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut buf = [0u8; 4];
+        let mut sig_buf = [0u8; 16];
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid FILE_SIG",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
+            ));
+        }
+
+        let mut read_u32 = |file: &mut BufReader<File>| -> std::io::Result<u32> {
+            file.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+        let invalid_enum = |name: &str, value: u32| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid {name}: '{value}'"),
+            )
+        };
+
+        let path_len = read_u32(&mut file)?;
+        let mut path_buf = vec![0u8; path_len as usize];
+        file.read_exact(&mut path_buf)?;
+        let source_path = String::from_utf8(path_buf)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        let format = match read_u32(&mut file)? {
+            0 => ImportFormat::Image,
+            1 => {
+                let width = read_u32(&mut file)?;
+                let height = read_u32(&mut file)?;
+                let sample = match read_u32(&mut file)? {
+                    0 => RawSample::U16,
+                    1 => RawSample::F32,
+                    v => return Err(invalid_enum("raw sample", v)),
+                };
+                let endian = match read_u32(&mut file)? {
+                    0 => Endian::Little,
+                    1 => Endian::Big,
+                    v => return Err(invalid_enum("endian", v)),
+                };
+                if width == 0 || height == 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid raw dimensions: {width} x {height}"),
+                    ));
+                }
+                ImportFormat::Raw(RawFormat {
+                    width,
+                    height,
+                    sample,
+                    endian,
+                })
+            }
+            v => return Err(invalid_enum("import format", v)),
+        };
+        let range = match read_u32(&mut file)? {
+            0 => HeightRange::Source,
+            1 => HeightRange::Normalize,
+            2 => HeightRange::Custom {
+                min: f32::from_bits(read_u32(&mut file)?),
+                max: f32::from_bits(read_u32(&mut file)?),
+            },
+            v => return Err(invalid_enum("height range", v)),
+        };
+        let crop = match read_u32(&mut file)? {
+            0 => None,
+            1 => {
+                let min = uvec2(read_u32(&mut file)?, read_u32(&mut file)?);
+                let max = uvec2(read_u32(&mut file)?, read_u32(&mut file)?);
+                Some(URect::from_corners(min, max))
+            }
+            v => return Err(invalid_enum("crop", v)),
+        };
+        let rotation = match read_u32(&mut file)? {
+            v if (v as usize) < Rotation::ALL.len() => Rotation::ALL[v as usize],
+            v => return Err(invalid_enum("rotation", v)),
+        };
+        let offset = ivec2(read_u32(&mut file)? as i32, read_u32(&mut file)? as i32);
+        let resample = match read_u32(&mut file)? {
+            0 => None,
+            1 => {
+                let size = uvec2(read_u32(&mut file)?, read_u32(&mut file)?);
+                let filter = match read_u32(&mut file)? {
+                    v if (v as usize) < ResampleFilter::ALL.len() => {
+                        ResampleFilter::ALL[v as usize]
+                    }
+                    v => return Err(invalid_enum("resample filter", v)),
+                };
+                Some((size, filter))
+            }
+            v => return Err(invalid_enum("resample", v)),
+        };
+
+        Ok(Self {
+            path: PathBuf::from(source_path),
+            import: ImportSettings {
+                format,
+                range,
+                layout: ImportLayout {
+                    crop,
+                    rotation,
+                    offset,
+                    resample,
+                },
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path() -> &'static Path {
+        Path::new("test")
+    }
+
+    #[test]
+    fn test_png16_is_not_quantized() {
+        let img = image::ImageBuffer::<Luma<u16>, _>::from_fn(4, 4, |x, y| {
+            Luma([(y * 4 + x) as u16 * 4369])
+        });
+        let mut bytes = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+
+        let map = import_bytes(test_path(), &bytes, &ImportSettings::default()).unwrap();
+        assert_eq!(map.dimensions(), (4, 4));
+        assert_eq!(map.get_pixel(1, 0)[0], 4369.0 / 65535.0);
+        assert_eq!(map.get_pixel(3, 3)[0], 1.0);
+    }
+
+    #[test]
+    fn test_float_tiff() {
+        let heights = [-12.5f32, 0.0, 0.25, 1.0, 300.75, 1e-3];
+        let mut bytes = Cursor::new(Vec::new());
+        tiff::encoder::TiffEncoder::new(&mut bytes)
+            .unwrap()
+            .write_image::<tiff::encoder::colortype::Gray32Float>(3, 2, &heights)
+            .unwrap();
+
+        let map = import_bytes(test_path(), bytes.get_ref(), &ImportSettings::default()).unwrap();
+        assert_eq!(map.dimensions(), (3, 2));
+        assert_eq!(map.as_raw(), &heights);
+    }
+
+    #[test]
+    fn test_float_exr() {
+        use exr::prelude::WritableImage;
+
+        let heights = vec![-12.5f32, 0.0, 0.25, 1.0, 300.75, 1e-3];
+        let channel =
+            exr::image::AnyChannel::new("Y", exr::image::FlatSamples::F32(heights.clone()));
+        let image = exr::image::Image::from_channels(
+            (3, 2),
+            exr::image::AnyChannels::sort(vec![channel].into()),
+        );
+        let mut bytes = Cursor::new(Vec::new());
+        image.write().to_buffered(&mut bytes).unwrap();
+
+        let map = import_bytes(test_path(), bytes.get_ref(), &ImportSettings::default()).unwrap();
+        assert_eq!(map.dimensions(), (3, 2));
+        assert_eq!(map.as_raw(), &heights);
+    }
+
+    #[test]
+    fn test_raw_endianness() {
+        let settings = |endian| ImportSettings {
+            format: ImportFormat::Raw(RawFormat {
+                width: 2,
+                height: 1,
+                sample: RawSample::U16,
+                endian,
+            }),
+            ..Default::default()
+        };
+        let bytes = [0xff, 0xff, 0x00, 0xff];
+
+        let le = import_bytes(test_path(), &bytes, &settings(Endian::Little)).unwrap();
+        assert_eq!(le.as_raw(), &[1.0, 0xff00 as f32 / 65535.0]);
+        let be = import_bytes(test_path(), &bytes, &settings(Endian::Big)).unwrap();
+        assert_eq!(be.as_raw(), &[1.0, 0x00ff as f32 / 65535.0]);
+    }
+
+    #[test]
+    fn test_raw_size_mismatch() {
+        let settings = ImportSettings {
+            format: ImportFormat::Raw(RawFormat {
+                width: 4,
+                height: 4,
+                sample: RawSample::F32,
+                endian: Endian::Little,
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            import_bytes(test_path(), &[0; 60], &settings),
+            Err(ImportError::RawSize(_, 60, 64))
+        ));
+
+        let empty = ImportSettings {
+            format: ImportFormat::Raw(RawFormat {
+                width: 0,
+                height: 4,
+                sample: RawSample::F32,
+                endian: Endian::Little,
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            import_bytes(test_path(), &[], &empty),
+            Err(ImportError::RawEmpty(_))
+        ));
+    }
+
+    #[test]
+    fn test_height_range() {
+        let bytes: Vec<u8> = [100.0f32, 150.0, 300.0]
+            .iter()
+            .flat_map(|h| h.to_be_bytes())
+            .collect();
+        let format = ImportFormat::Raw(RawFormat {
+            width: 3,
+            height: 1,
+            sample: RawSample::F32,
+            endian: Endian::Big,
+        });
+
+        let custom = ImportSettings {
+            format,
+            range: HeightRange::Custom {
+                min: 100.0,
+                max: 200.0,
+            },
+            ..Default::default()
+        };
+        let map = import_bytes(test_path(), &bytes, &custom).unwrap();
+        assert_eq!(map.as_raw(), &[0.0, 0.5, 2.0]);
+
+        let normalize = ImportSettings {
+            format,
+            range: HeightRange::Normalize,
+            ..Default::default()
+        };
+        let map = import_bytes(test_path(), &bytes, &normalize).unwrap();
+        assert_eq!(map.as_raw(), &[0.0, 0.25, 1.0]);
+    }
+
+    #[test]
+    fn test_layout() {
+        let bytes: Vec<u8> = (0..16u32).flat_map(|v| (v as f32).to_le_bytes()).collect();
+        let settings = ImportSettings {
+            format: ImportFormat::Raw(RawFormat {
+                width: 4,
+                height: 4,
+                sample: RawSample::F32,
+                endian: Endian::Little,
+            }),
+            layout: ImportLayout {
+                crop: Some(URect::new(0, 0, 2, 3)),
+                rotation: Rotation::Cw90,
+                resample: Some((uvec2(5, 3), ResampleFilter::Bilinear)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let source_size = uvec2(4, 4);
+        assert_eq!(settings.layout.output_size(source_size), uvec2(5, 3));
+
+        let map = import_bytes(test_path(), &bytes, &settings).unwrap();
+        assert_eq!(map.dimensions(), (5, 3));
+        // Bottom left of the crop ends up top left
+        assert_eq!(map.get_pixel(0, 0)[0], 8.0);
+
+        let no_resample = ImportLayout {
+            resample: None,
+            ..settings.layout
+        };
+        assert_eq!(no_resample.output_size(source_size), uvec2(3, 2));
+    }
+
+    #[test]
+    fn test_source_save_load() {
+        let source = HeightmapSource {
+            path: PathBuf::from("assets/tiles/combined.exr"),
+            import: ImportSettings {
+                format: ImportFormat::Raw(RawFormat {
+                    width: 513,
+                    height: 257,
+                    sample: RawSample::U16,
+                    endian: Endian::Big,
+                }),
+                range: HeightRange::Custom {
+                    min: -10.0,
+                    max: 400.0,
+                },
+                layout: ImportLayout {
+                    crop: Some(URect::new(1, 2, 300, 200)),
+                    rotation: Rotation::Cw270,
+                    offset: ivec2(-5, 12),
+                    resample: Some((uvec2(129, 129), ResampleFilter::Lanczos3)),
+                },
+            },
+        };
+        let path = std::env::temp_dir().join("worldedit_test_source.wsrc");
+        source.save(&path).unwrap();
+        assert_eq!(HeightmapSource::load(&path).unwrap(), source);
+    }
+}
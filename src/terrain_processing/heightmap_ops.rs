@@ -0,0 +1,320 @@
+use bevy::math::prelude::*;
+use derive_more::Display;
+
+use crate::terrain_processing::heightmap::GrayF32Image;
+
+/// Reconstruction filter for [resample].
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleFilter {
+    Nearest,
+    Bilinear,
+    /// Catmull-Rom
+    #[default]
+    Bicubic,
+    /// Sharpest, but may ring around cliffs
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    pub const ALL: [Self; 4] = [Self::Nearest, Self::Bilinear, Self::Bicubic, Self::Lanczos3];
+
+    /// Kernel radius in source texels
+    const fn support(self) -> f32 {
+        match self {
+            Self::Nearest => 0.5,
+            Self::Bilinear => 1.0,
+            Self::Bicubic => 2.0,
+            Self::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Self::Nearest => (x < 0.5) as u32 as f32,
+            Self::Bilinear => (1.0 - x).max(0.0),
+            Self::Bicubic if x < 1.0 => 1.5 * x * x * x - 2.5 * x * x + 1.0,
+            Self::Bicubic if x < 2.0 => -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0,
+            Self::Bicubic => 0.0,
+            Self::Lanczos3 if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            Self::Lanczos3 => 0.0,
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        return 1.0;
+    }
+    let x = x * std::f32::consts::PI;
+    x.sin() / x
+}
+
+/// Quarter turns for [rotate]. Lossless, unlike arbitrary angles.
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    #[display("0°")]
+    None,
+    #[display("90°")]
+    Cw90,
+    #[display("180°")]
+    Cw180,
+    #[display("270°")]
+    Cw270,
+}
+
+impl Rotation {
+    pub const ALL: [Self; 4] = [Self::None, Self::Cw90, Self::Cw180, Self::Cw270];
+}
+
+#[derive(Debug, Display, Clone, PartialEq)]
+pub enum HeightmapOpError {
+    #[display("crop of {size} at {min} doesn't fit in the {map_size} heightmap")]
+    Crop {
+        min: UVec2,
+        size: UVec2,
+        map_size: UVec2,
+    },
+    #[display("no tiles to combine")]
+    NoTiles,
+    #[display("{_0} tiles don't fill rows of {_1}")]
+    TileCount(usize, u32),
+    #[display("tile {index} is {size}, expected {expected}")]
+    TileSize {
+        index: usize,
+        size: UVec2,
+        expected: UVec2,
+    },
+    #[display("tile overlap {_0} must be less than the tile size {_1}")]
+    TileOverlap(u32, UVec2),
+}
+
+impl std::error::Error for HeightmapOpError {}
+
+fn map_size(map: &GrayF32Image) -> UVec2 {
+    UVec2::from(map.dimensions())
+}
+
+/// Scales `map` to `size` texels. Corner texels stay in the corners, matching how
+/// heightmaps span the world.
+pub fn resample(map: &GrayF32Image, size: UVec2, filter: ResampleFilter) -> GrayF32Image {
+    let size = size.max(UVec2::ONE);
+    let src_size = map_size(map);
+    let rows = resample_axis(map.as_raw(), src_size, size.x, filter);
+    // Columns are rows of the transpose
+    let transposed = transpose(&rows, uvec2(size.x, src_size.y));
+    let columns = resample_axis(&transposed, uvec2(src_size.y, size.x), size.y, filter);
+    let pixels = transpose(&columns, uvec2(size.y, size.x));
+    GrayF32Image::from_raw(size.x, size.y, pixels).expect("pixel count matches the size")
+}
+
+/// Resamples each row of `pixels` from `size.x` to `width` samples
+fn resample_axis(pixels: &[f32], size: UVec2, width: u32, filter: ResampleFilter) -> Vec<f32> {
+    let src_width = size.x as usize;
+    let step = match width {
+        1 => 0.0,
+        _ => (size.x - 1) as f32 / (width - 1) as f32,
+    };
+    // Shrinking widens the kernel, so every source texel contributes
+    let scale = match filter {
+        ResampleFilter::Nearest => 1.0,
+        _ => step.max(1.0),
+    };
+    let support = filter.support() * scale;
+
+    // Weights are the same for every row: first source texel and its weights
+    let taps: Vec<(isize, Vec<f32>)> = (0..width)
+        .map(|i| {
+            let center = match width {
+                1 => (size.x - 1) as f32 / 2.0,
+                _ => i as f32 * step,
+            };
+            if filter == ResampleFilter::Nearest {
+                return (center.round() as isize, vec![1.0]);
+            }
+            let first = (center - support).ceil() as isize;
+            let last = (center + support).floor() as isize;
+            let mut weights: Vec<f32> = (first..=last)
+                .map(|j| filter.weight((j as f32 - center) / scale))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            weights.iter_mut().for_each(|w| *w /= sum);
+            (first, weights)
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(width as usize * size.y as usize);
+    for row in pixels.chunks_exact(src_width) {
+        for (first, weights) in &taps {
+            let value = weights
+                .iter()
+                .enumerate()
+                .map(|(k, w)| {
+                    let j = (first + k as isize).clamp(0, src_width as isize - 1);
+                    row[j as usize] * w
+                })
+                .sum();
+            out.push(value);
+        }
+    }
+    out
+}
+
+fn transpose(pixels: &[f32], size: UVec2) -> Vec<f32> {
+    let (w, h) = (size.x as usize, size.y as usize);
+    (0..w)
+        .flat_map(|x| (0..h).map(move |y| pixels[y * w + x]))
+        .collect()
+}
+
+/// `size` texels of `map` starting at `min`
+pub fn crop(map: &GrayF32Image, min: UVec2, size: UVec2) -> Result<GrayF32Image, HeightmapOpError> {
+    let fits = size.cmpgt(UVec2::ZERO).all() && (min + size).cmple(map_size(map)).all();
+    if !fits {
+        return Err(HeightmapOpError::Crop {
+            min,
+            size,
+            map_size: map_size(map),
+        });
+    }
+    Ok(image::imageops::crop_imm(map, min.x, min.y, size.x, size.y).to_image())
+}
+
+/// Moves the texels by `shift`, wrapping around the edges
+pub fn offset(map: &GrayF32Image, shift: IVec2) -> GrayF32Image {
+    let size = map_size(map).as_ivec2();
+    GrayF32Image::from_fn(map.width(), map.height(), |x, y| {
+        let source = (ivec2(x as i32, y as i32) - shift).rem_euclid(size);
+        *map.get_pixel(source.x as u32, source.y as u32)
+    })
+}
+
+/// Clockwise, seen from above with +Y rows running south
+pub fn rotate(map: &GrayF32Image, rotation: Rotation) -> GrayF32Image {
+    match rotation {
+        Rotation::None => map.clone(),
+        Rotation::Cw90 => image::imageops::rotate90(map),
+        Rotation::Cw180 => image::imageops::rotate180(map),
+        Rotation::Cw270 => image::imageops::rotate270(map),
+    }
+}
+
+/// Stitches equally sized tiles, row by row, `columns` per row. Neighbouring tiles
+/// share `overlap` texels along their edges, which are averaged.
+pub fn combine_tiles(
+    tiles: &[GrayF32Image],
+    columns: u32,
+    overlap: u32,
+) -> Result<GrayF32Image, HeightmapOpError> {
+    let Some(first) = tiles.first() else {
+        return Err(HeightmapOpError::NoTiles);
+    };
+    if columns == 0 || !tiles.len().is_multiple_of(columns as usize) {
+        return Err(HeightmapOpError::TileCount(tiles.len(), columns));
+    }
+    let tile_size = map_size(first);
+    if let Some((index, tile)) = tiles
+        .iter()
+        .enumerate()
+        .find(|(_, tile)| map_size(tile) != tile_size)
+    {
+        return Err(HeightmapOpError::TileSize {
+            index,
+            size: map_size(tile),
+            expected: tile_size,
+        });
+    }
+    if UVec2::splat(overlap).cmpge(tile_size).any() {
+        return Err(HeightmapOpError::TileOverlap(overlap, tile_size));
+    }
+
+    let grid = uvec2(columns, tiles.len() as u32 / columns);
+    let stride = tile_size - overlap;
+    let size = grid * stride + overlap;
+    let mut sum = GrayF32Image::new(size.x, size.y);
+    let mut count = vec![0u32; (size.x * size.y) as usize];
+    for (i, tile) in tiles.iter().enumerate() {
+        let origin = uvec2(i as u32 % columns, i as u32 / columns) * stride;
+        for (x, y, value) in tile.enumerate_pixels() {
+            let p = origin + uvec2(x, y);
+            sum.get_pixel_mut(p.x, p.y)[0] += value[0];
+            count[(p.y * size.x + p.x) as usize] += 1;
+        }
+    }
+    for (value, count) in sum.iter_mut().zip(count) {
+        *value /= count as f32;
+    }
+    Ok(sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    fn from_fn(size: u32, f: impl Fn(u32, u32) -> f32) -> GrayF32Image {
+        GrayF32Image::from_fn(size, size, |x, y| Luma([f(x, y)]))
+    }
+
+    #[test]
+    fn test_resample_keeps_ramps_and_corners() {
+        let map = from_fn(9, |x, y| (x + 2 * y) as f32);
+        for filter in ResampleFilter::ALL {
+            let up = resample(&map, uvec2(17, 17), filter);
+            assert_eq!(up.dimensions(), (17, 17));
+            assert_eq!(up.get_pixel(16, 16)[0], 24.0, "{filter}");
+            if filter == ResampleFilter::Nearest {
+                continue;
+            }
+            // Halfway between texels (4, 4) and (5, 4). Lanczos only nearly keeps ramps.
+            assert!((up.get_pixel(9, 8)[0] - 12.5).abs() < 0.02, "{filter}");
+        }
+    }
+
+    #[test]
+    fn test_downsample_averages_detail() {
+        // Alternating spikes vanish instead of aliasing into stripes
+        let map = from_fn(65, |x, _| (x % 2) as f32);
+        let down = resample(&map, uvec2(9, 9), ResampleFilter::Lanczos3);
+        for value in down.iter().skip(1).take(7) {
+            assert!((value - 0.5).abs() < 0.1, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_crop_offset_rotate() {
+        let map = from_fn(4, |x, y| (y * 4 + x) as f32);
+
+        let cropped = crop(&map, uvec2(1, 2), uvec2(2, 2)).unwrap();
+        assert_eq!(cropped.as_raw(), &[9.0, 10.0, 13.0, 14.0]);
+        assert!(crop(&map, uvec2(3, 0), uvec2(2, 2)).is_err());
+
+        let shifted = offset(&map, ivec2(1, 0));
+        assert_eq!(shifted.get_pixel(0, 0)[0], 3.0);
+        assert_eq!(shifted.get_pixel(1, 0)[0], 0.0);
+
+        let turned = rotate(&map, Rotation::Cw90);
+        // The left column becomes the top row
+        assert_eq!(&turned.as_raw()[..4], &[12.0, 8.0, 4.0, 0.0]);
+    }
+
+    #[test]
+    fn test_combine_tiles() {
+        let tile = |v: f32| from_fn(3, |_, _| v);
+        let tiles = [tile(0.0), tile(2.0), tile(4.0), tile(6.0)];
+
+        let combined = combine_tiles(&tiles, 2, 1).unwrap();
+        assert_eq!(combined.dimensions(), (5, 5));
+        assert_eq!(combined.get_pixel(0, 0)[0], 0.0);
+        assert_eq!(combined.get_pixel(4, 4)[0], 6.0);
+        // Shared by two and four tiles
+        assert_eq!(combined.get_pixel(2, 0)[0], 1.0);
+        assert_eq!(combined.get_pixel(2, 2)[0], 3.0);
+
+        assert_eq!(
+            combine_tiles(&tiles[..3], 2, 0),
+            Err(HeightmapOpError::TileCount(3, 2))
+        );
+    }
+}
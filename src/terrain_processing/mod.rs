@@ -1,27 +1,27 @@
+pub mod analysis;
+mod cache_manifest;
+pub mod erosion;
+pub mod generator;
 pub mod heightmap;
 mod heightmap_bundle;
+pub mod heightmap_export;
+pub mod heightmap_import;
+pub mod heightmap_ops;
+pub mod sculpt;
+pub mod splat;
 pub mod terrain_cruncher;
 mod terrain_mesh;
+pub mod water;
+mod world_settings;
 
+pub use cache_manifest::{CacheCellEntry, CacheManifest, ContentHasher, content_hash};
+pub use heightmap_bundle::HeightFilter;
+pub use heightmap_bundle::HeightLayer;
 pub use heightmap_bundle::HeightmapBundle;
+pub use heightmap_bundle::LayerBlend;
+pub use terrain_mesh::HeightEncoding;
 pub use terrain_mesh::TerrainMesh;
+pub use world_settings::WorldSettings;
 
-// All dimensions are in metres
-pub const WORLD_SIZE: usize = 2048;
-pub const WORLD_HEIGHT: f32 = 512.;
-pub const WORLD_HEIGHT_OFFSET: f32 = -WORLD_HEIGHT / 10.;
-pub const CELL_SIZE: usize = 512;
-pub const NUM_CELLS_ROW: usize = WORLD_SIZE / CELL_SIZE;
-pub const NUM_CELLS: usize = NUM_CELLS_ROW * NUM_CELLS_ROW;
-
+pub const PROJECT_DIR: &str = "assets/";
 pub const CACHE_DIR: &str = "assets/cache/";
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_world_size_is_multiple_of_cell_size() {
-        assert_eq!(WORLD_SIZE % CELL_SIZE, 0);
-    }
-}
@@ -0,0 +1,290 @@
+use bevy::math::prelude::*;
+use derive_more::Display;
+
+use crate::terrain_processing::HeightmapBundle;
+
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BrushTool {
+    #[default]
+    Raise,
+    Lower,
+    Smooth,
+    Flatten,
+    Noise,
+    #[display("Set height")]
+    SetHeight,
+}
+
+impl BrushTool {
+    pub const ALL: [Self; 6] = [
+        Self::Raise,
+        Self::Lower,
+        Self::Smooth,
+        Self::Flatten,
+        Self::Noise,
+        Self::SetHeight,
+    ];
+}
+
+/// Brush strength from the centre (`t = 0`) to the rim (`t = 1`).
+#[derive(Debug, Display, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BrushFalloff {
+    Constant,
+    Linear,
+    #[default]
+    Smooth,
+    Sphere,
+}
+
+impl BrushFalloff {
+    pub const ALL: [Self; 4] = [Self::Constant, Self::Linear, Self::Smooth, Self::Sphere];
+
+    pub fn weight(self, t: f32) -> f32 {
+        if !(0.0..1.0).contains(&t) {
+            return 0.0;
+        }
+        match self {
+            Self::Constant => 1.0,
+            Self::Linear => 1.0 - t,
+            Self::Smooth => {
+                let s = 1.0 - t;
+                s * s * (3.0 - 2.0 * s)
+            }
+            Self::Sphere => (1.0 - t * t).sqrt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Brush {
+    pub tool: BrushTool,
+    /// World units
+    pub radius: f32,
+    /// The most a texel moves per second at the brush centre, in world units
+    pub strength: f32,
+    pub falloff: BrushFalloff,
+    /// World height [BrushTool::Flatten] and [BrushTool::SetHeight] pull towards
+    pub target_height: f32,
+    pub seed: u32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            tool: BrushTool::default(),
+            radius: 32.0,
+            strength: 20.0,
+            falloff: BrushFalloff::default(),
+            target_height: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Applies `dt` seconds of `brush` around `center` (world units, origin at texel `(0, 0)`).
+///
+/// Edits go into `layer`, so they stay non-destructive. A layer that isn't plain additive is
+/// baked into one first (see [HeightmapBundle::bake_layer]), so strokes move the terrain by
+/// what the brush asks for. Returns the area that changed, or `None` if nothing did.
+pub fn apply_brush(
+    h_bundle: &mut HeightmapBundle,
+    layer: usize,
+    brush: &Brush,
+    center: Vec2,
+    dt: f32,
+) -> Option<Rect> {
+    let texel_size = h_bundle.texel_size();
+    let last_texel = h_bundle.size() - UVec2::ONE;
+    let min = ((center - brush.radius) / texel_size).floor();
+    let max = ((center + brush.radius) / texel_size).ceil();
+    if max.x < 0.0 || max.y < 0.0 {
+        return None;
+    }
+    let min = min.max(Vec2::ZERO).as_uvec2();
+    let max = max.as_uvec2().min(last_texel);
+    if min.x > max.x || min.y > max.y {
+        return None;
+    }
+
+    let noise_scale = 4.0 / brush.radius;
+    let mut deltas = Vec::new();
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let texel = uvec2(x, y);
+            let position = texel.as_vec2() * texel_size;
+            let weight = brush
+                .falloff
+                .weight(position.distance(center) / brush.radius);
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let step = brush.strength * weight * dt;
+            let h = h_bundle.height(texel);
+            let delta = match brush.tool {
+                BrushTool::Raise => step,
+                BrushTool::Lower => -step,
+                BrushTool::Noise => value_noise(position * noise_scale, brush.seed) * step,
+                BrushTool::Smooth => (neighbour_average(h_bundle, texel) - h).clamp(-step, step),
+                BrushTool::Flatten | BrushTool::SetHeight => {
+                    (brush.target_height - h).clamp(-step, step)
+                }
+            };
+            deltas.push((texel, delta));
+        }
+    }
+
+    if !h_bundle.layers().get(layer)?.is_plain_add() {
+        h_bundle.bake_layer(layer)?;
+    }
+    let world_height = h_bundle.world().world_height;
+    let layer = h_bundle.layer_mut(layer)?;
+    for (texel, delta) in deltas {
+        layer.map.get_pixel_mut(texel.x, texel.y)[0] += delta / world_height;
+    }
+
+    Some(Rect::from_corners(
+        min.as_vec2() * texel_size,
+        max.as_vec2() * texel_size,
+    ))
+}
+
+fn neighbour_average(h_bundle: &HeightmapBundle, texel: UVec2) -> f32 {
+    let mut sum = 0.0;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let neighbour = (texel.as_ivec2() + ivec2(dx, dy)).max(IVec2::ZERO);
+            sum += h_bundle.height(neighbour.as_uvec2());
+        }
+    }
+    sum / 9.0
+}
+
+/// Smooth noise in -1..1 with features about one unit apart
+fn value_noise(p: Vec2, seed: u32) -> f32 {
+    let cell = p.floor();
+    let t = p - cell;
+    let t = t * t * (3.0 - 2.0 * t);
+    let cell = cell.as_ivec2();
+    let corner = |dx, dy| lattice_value(cell + ivec2(dx, dy), seed);
+    let top = corner(0, 0).lerp(corner(1, 0), t.x);
+    let bottom = corner(0, 1).lerp(corner(1, 1), t.x);
+    top.lerp(bottom, t.y)
+}
+
+fn lattice_value(p: IVec2, seed: u32) -> f32 {
+    let mut h = (p.x as u32).wrapping_mul(0x27d4_eb2d)
+        ^ (p.y as u32).wrapping_mul(0x1656_67b1)
+        ^ seed.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain_processing::HeightLayer;
+    use crate::terrain_processing::LayerBlend;
+    use crate::terrain_processing::WorldSettings;
+    use crate::terrain_processing::heightmap::GrayF32Image;
+    use image::Luma;
+
+    /// 65x65 texels over a 64 unit world, flat at height 0
+    fn test_bundle(f: impl Fn(u32, u32) -> f32) -> (HeightmapBundle, usize) {
+        let map = GrayF32Image::from_fn(65, 65, |x, y| Luma([f(x, y)]));
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            world_height: 100.0,
+            world_height_offset: 0.0,
+        };
+        let mut h_bundle = HeightmapBundle::new(map, world);
+        let size = h_bundle.size();
+        let layer = h_bundle
+            .push_layer(HeightLayer::new("Sculpt", LayerBlend::Add, size))
+            .unwrap();
+        (h_bundle, layer)
+    }
+
+    #[test]
+    fn test_raise_with_falloff() {
+        let (mut h_bundle, layer) = test_bundle(|_, _| 0.0);
+        let brush = Brush {
+            radius: 8.0,
+            strength: 10.0,
+            ..Default::default()
+        };
+        let area = apply_brush(&mut h_bundle, layer, &brush, vec2(32.0, 32.0), 0.5).unwrap();
+
+        assert!((h_bundle.height(uvec2(32, 32)) - 5.0).abs() < 1e-4);
+        let halfway = h_bundle.height(uvec2(36, 32));
+        assert!(halfway > 0.0 && halfway < 5.0);
+        assert_eq!(h_bundle.height(uvec2(40, 32)), 0.0);
+        assert_eq!(area, Rect::new(24.0, 24.0, 40.0, 40.0));
+    }
+
+    #[test]
+    fn test_raise_through_masked_layer() {
+        let (mut h_bundle, layer) = test_bundle(|_, _| 0.0);
+        let size = h_bundle.size();
+        let sculpt = h_bundle.layer_mut(layer).unwrap();
+        sculpt.blend = LayerBlend::Max;
+        sculpt.opacity = 0.5;
+        sculpt.mask = Some(GrayF32Image::new(size.x, size.y));
+        let brush = Brush {
+            radius: 8.0,
+            strength: 10.0,
+            falloff: BrushFalloff::Constant,
+            ..Default::default()
+        };
+        apply_brush(&mut h_bundle, layer, &brush, vec2(32.0, 32.0), 0.5).unwrap();
+
+        assert!(h_bundle.layers()[layer].is_plain_add());
+        assert!((h_bundle.height(uvec2(32, 32)) - 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_smooth_flattens_spike() {
+        let (mut h_bundle, layer) = test_bundle(|x, y| if (x, y) == (32, 32) { 0.5 } else { 0.0 });
+        let brush = Brush {
+            tool: BrushTool::Smooth,
+            radius: 4.0,
+            strength: 1000.0,
+            falloff: BrushFalloff::Constant,
+            ..Default::default()
+        };
+        apply_brush(&mut h_bundle, layer, &brush, vec2(32.0, 32.0), 1.0).unwrap();
+        assert!(h_bundle.height(uvec2(32, 32)) < 10.0);
+        assert!(h_bundle.height(uvec2(33, 32)) > 0.0);
+    }
+
+    #[test]
+    fn test_set_height_is_limited_by_strength() {
+        let (mut h_bundle, layer) = test_bundle(|_, _| 0.1);
+        let brush = Brush {
+            tool: BrushTool::SetHeight,
+            radius: 4.0,
+            strength: 4.0,
+            falloff: BrushFalloff::Constant,
+            target_height: 20.0,
+            ..Default::default()
+        };
+        let center = vec2(10.0, 10.0);
+        apply_brush(&mut h_bundle, layer, &brush, center, 1.0).unwrap();
+        assert!((h_bundle.height(uvec2(10, 10)) - 14.0).abs() < 1e-4);
+        apply_brush(&mut h_bundle, layer, &brush, center, 10.0).unwrap();
+        assert!((h_bundle.height(uvec2(10, 10)) - 20.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_brush_outside_map() {
+        let (mut h_bundle, layer) = test_bundle(|_, _| 0.0);
+        let brush = Brush::default();
+        assert_eq!(
+            apply_brush(&mut h_bundle, layer, &brush, vec2(-100.0, 10.0), 1.0),
+            None
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bevy::math::prelude::*;
+
+use crate::terrain_processing::HeightmapBundle;
+use crate::terrain_processing::sculpt::BrushFalloff;
+
+/// Texture layers a splat map can blend, two RGBA textures worth.
+pub const MAX_SPLAT_LAYERS: usize = 8;
+
+/// Largest splat map row [SplatMap::load] accepts
+const MAX_SPLAT_SIZE: u32 = 4096;
+
+/// Where a layer shows up when splat maps are generated. Each range fades in and out
+/// over its blend width, centred on the range ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplatRule {
+    /// World units
+    pub min_height: f32,
+    pub max_height: f32,
+    pub height_blend: f32,
+    /// Radians
+    pub min_slope: f32,
+    pub max_slope: f32,
+    pub slope_blend: f32,
+}
+
+impl SplatRule {
+    /// Everywhere
+    pub const ANY: Self = Self {
+        min_height: f32::NEG_INFINITY,
+        max_height: f32::INFINITY,
+        height_blend: 0.0,
+        min_slope: 0.0,
+        max_slope: f32::INFINITY,
+        slope_blend: 0.0,
+    };
+
+    /// 0..1
+    pub fn weight(&self, height: f32, slope: f32) -> f32 {
+        band(height, self.min_height, self.max_height, self.height_blend)
+            * band(slope, self.min_slope, self.max_slope, self.slope_blend)
+    }
+}
+
+fn band(v: f32, min: f32, max: f32, blend: f32) -> f32 {
+    if blend <= 0.0 {
+        return if (min..=max).contains(&v) { 1.0 } else { 0.0 };
+    }
+    let fade_in = ((v - min) / blend + 0.5).clamp(0.0, 1.0);
+    let fade_out = ((max - v) / blend + 0.5).clamp(0.0, 1.0);
+    fade_in * fade_out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplatLayer {
+    pub name: String,
+    /// Relative to the project directory. Layers without one get a plain color.
+    pub texture: Option<PathBuf>,
+    /// World units per texture repeat
+    pub tile_size: f32,
+    pub rule: SplatRule,
+}
+
+impl SplatLayer {
+    /// Project file holding the layer list
+    pub const FILE_NAME: &str = "splat_layers.wset";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-SplatLay  ";
+    pub const FILE_VER: u32 = 0;
+
+    pub fn new(name: impl Into<String>, rule: SplatRule) -> Self {
+        Self {
+            name: name.into(),
+            texture: None,
+            tile_size: 8.0,
+            rule,
+        }
+    }
+
+    /// Sand by the sea, grass, rock on steep slopes and snow on top
+    pub fn default_layers(world_height: f32, world_height_offset: f32) -> Vec<Self> {
+        let steep = 35f32.to_radians();
+        let shore = world_height_offset + world_height * 0.12;
+        let snow_line = world_height_offset + world_height * 0.7;
+        let blend = world_height * 0.02;
+        vec![
+            Self::new(
+                "Grass",
+                SplatRule {
+                    max_slope: steep,
+                    slope_blend: 0.1,
+                    ..SplatRule::ANY
+                },
+            ),
+            Self::new(
+                "Sand",
+                SplatRule {
+                    max_height: shore,
+                    height_blend: blend,
+                    max_slope: steep,
+                    slope_blend: 0.1,
+                    ..SplatRule::ANY
+                },
+            ),
+            Self::new(
+                "Rock",
+                SplatRule {
+                    min_slope: steep,
+                    slope_blend: 0.1,
+                    ..SplatRule::ANY
+                },
+            ),
+            Self::new(
+                "Snow",
+                SplatRule {
+                    min_height: snow_line,
+                    height_blend: blend,
+                    max_slope: steep,
+                    slope_blend: 0.1,
+                    ..SplatRule::ANY
+                },
+            ),
+        ]
+    }
+}
+
+impl SplatLayer {
+    /// Writes a layer list, bottom layer first
+    pub fn save_all(path: &Path, layers: &[Self]) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        file.write_all(&(layers.len() as u32).to_le_bytes())?;
+        for layer in layers {
+            write_string(&mut file, &layer.name)?;
+            match &layer.texture {
+                None => file.write_all(&0u32.to_le_bytes())?,
+                Some(texture) => {
+                    let texture = texture.to_str().ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("texture path '{}' is not UTF-8", texture.display()),
+                        )
+                    })?;
+                    file.write_all(&1u32.to_le_bytes())?;
+                    write_string(&mut file, texture)?;
+                }
+            }
+            let rule = &layer.rule;
+            let values = [
+                layer.tile_size,
+                rule.min_height,
+                rule.max_height,
+                rule.height_blend,
+                rule.min_slope,
+                rule.max_slope,
+                rule.slope_blend,
+            ];
+            for value in values {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load_all(path: &Path) -> std::io::Result<Vec<Self>> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_header(&mut file, Self::FILE_SIG, Self::FILE_VER)?;
+
+        let num_layers = read_u32(&mut file)?;
+        if num_layers as usize > MAX_SPLAT_LAYERS {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("too many layers: '{num_layers}'"),
+            ));
+        }
+
+        let mut layers = Vec::with_capacity(num_layers as usize);
+        for _ in 0..num_layers {
+            let name = read_string(&mut file)?;
+            let texture = match read_u32(&mut file)? {
+                0 => None,
+                _ => Some(PathBuf::from(read_string(&mut file)?)),
+            };
+            let mut values = [0.0f32; 7];
+            for value in &mut values {
+                *value = f32::from_bits(read_u32(&mut file)?);
+            }
+            let [
+                tile_size,
+                min_height,
+                max_height,
+                height_blend,
+                min_slope,
+                max_slope,
+                slope_blend,
+            ] = values;
+            layers.push(Self {
+                name,
+                texture,
+                tile_size,
+                rule: SplatRule {
+                    min_height,
+                    max_height,
+                    height_blend,
+                    min_slope,
+                    max_slope,
+                    slope_blend,
+                },
+            });
+        }
+
+        Ok(layers)
+    }
+}
+
+/// Layer weights over one terrain cell. Texel centres are spread evenly over the cell,
+/// so the map lines up with the cell's UVs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplatMap {
+    size: u32,
+    weights: Vec<[f32; MAX_SPLAT_LAYERS]>,
+}
+
+impl SplatMap {
+    pub const FILE_EXT: &str = "wsplat";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-SplatMap  ";
+    pub const FILE_VER: u32 = 1;
+
+    /// Fully covered by `layer`
+    pub fn new(size: u32, layer: usize) -> Self {
+        Self {
+            size,
+            weights: vec![one_hot(layer); (size * size) as usize],
+        }
+    }
+
+    /// Weights from the layers' rules, for the cell at `cell_position`
+    pub fn from_rules(
+        h_bundle: &HeightmapBundle,
+        layers: &[SplatLayer],
+        cell_position: UVec2,
+        size: u32,
+    ) -> Self {
+        let cell_size = h_bundle.world().cell_size as f32;
+        let origin = cell_position.as_vec2();
+        let mut splat = Self::new(size, 0);
+
+        for y in 0..size {
+            for x in 0..size {
+                let position = splat.texel_position(uvec2(x, y), origin, cell_size);
+                let height = h_bundle.sample(position);
+                let slope = h_bundle.slope_at(position);
+
+                let mut weights = [0.0; MAX_SPLAT_LAYERS];
+                for (weight, layer) in weights.iter_mut().zip(layers) {
+                    *weight = layer.rule.weight(height, slope);
+                }
+                // Later layers paint over earlier ones
+                let mut remaining = 1.0;
+                for weight in weights.iter_mut().rev() {
+                    *weight *= remaining;
+                    remaining -= *weight;
+                }
+                if weights.iter().sum::<f32>() <= 0.0 {
+                    weights = one_hot(0);
+                }
+                splat.weights[(y * size + x) as usize] = normalized(weights);
+            }
+        }
+        splat
+    }
+
+    pub const fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn weights(&self, texel: UVec2) -> [f32; MAX_SPLAT_LAYERS] {
+        self.weights[(texel.y * self.size + texel.x) as usize]
+    }
+
+    /// Reorders the weights after layers were moved or removed. New layer `i` takes the
+    /// weights of old layer `sources[i]`, or none for a new layer.
+    pub fn remap_layers(&mut self, sources: &[Option<usize>]) {
+        for weights in &mut self.weights {
+            let mut remapped = [0.0; MAX_SPLAT_LAYERS];
+            for (weight, source) in remapped.iter_mut().zip(sources) {
+                *weight = source.map_or(0.0, |source| weights[source]);
+            }
+            if remapped.iter().sum::<f32>() <= 0.0 {
+                remapped = one_hot(0);
+            }
+            *weights = normalized(remapped);
+        }
+    }
+
+    /// World position of a texel centre, for a cell starting at `origin`
+    pub fn texel_position(&self, texel: UVec2, origin: Vec2, cell_size: f32) -> Vec2 {
+        origin + (texel.as_vec2() + 0.5) / self.size as f32 * cell_size
+    }
+
+    /// Layers 0-3 and 4-7 as two RGBA8 images, row by row
+    pub fn to_rgba8(&self) -> [Vec<u8>; 2] {
+        let channels = |first: usize| {
+            self.weights
+                .iter()
+                .flat_map(|weights| weights[first..first + 4].iter())
+                .map(|w| (w * 255.0).round() as u8)
+                .collect()
+        };
+        [channels(0), channels(4)]
+    }
+
+    /// Saves the map of the cell covering `cell`, so it isn't loaded for another one
+    pub fn save(&self, path: &Path, cell: URect) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        for v in [cell.min.x, cell.min.y, cell.max.x, cell.max.y] {
+            file.write_all(&v.to_le_bytes())?;
+        }
+        file.write_all(&(MAX_SPLAT_LAYERS as u32).to_le_bytes())?;
+        file.write_all(&self.size.to_le_bytes())?;
+        file.write_all(bytemuck::cast_slice(&self.weights))?;
+
+        Ok(())
+    }
+
+    /// Map and the cell area it was saved for
+    pub fn load(path: &Path) -> std::io::Result<(Self, URect)> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_header(&mut file, Self::FILE_SIG, Self::FILE_VER)?;
+
+        let min = uvec2(read_u32(&mut file)?, read_u32(&mut file)?);
+        let max = uvec2(read_u32(&mut file)?, read_u32(&mut file)?);
+
+        let num_layers = read_u32(&mut file)?;
+        if num_layers as usize != MAX_SPLAT_LAYERS {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("layer count mismatch: exp '{MAX_SPLAT_LAYERS}', got '{num_layers}'"),
+            ));
+        }
+        let size = read_u32(&mut file)?;
+        if size == 0 || size > MAX_SPLAT_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid size: '{size}'"),
+            ));
+        }
+        let mut weights = vec![[0.0; MAX_SPLAT_LAYERS]; (size * size) as usize];
+        file.read_exact(bytemuck::cast_slice_mut(&mut weights))?;
+
+        Ok((Self { size, weights }, URect { min, max }))
+    }
+}
+
+fn read_header(file: &mut impl Read, sig: &[u8; 16], ver: u32) -> std::io::Result<()> {
+    let mut sig_buf = [0u8; 16];
+    file.read_exact(&mut sig_buf)?;
+    if &sig_buf != sig {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "invalid FILE_SIG",
+        ));
+    }
+    let file_ver = read_u32(file)?;
+    if file_ver != ver {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid version: exp '{ver}', got '{file_ver}'"),
+        ));
+    }
+    Ok(())
+}
+
+fn read_u32(file: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn write_string(file: &mut impl Write, s: &str) -> std::io::Result<()> {
+    file.write_all(&(s.len() as u32).to_le_bytes())?;
+    file.write_all(s.as_bytes())
+}
+
+fn read_string(file: &mut impl Read) -> std::io::Result<String> {
+    let mut bytes = vec![0u8; read_u32(file)? as usize];
+    file.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn one_hot(layer: usize) -> [f32; MAX_SPLAT_LAYERS] {
+    let mut weights = [0.0; MAX_SPLAT_LAYERS];
+    weights[layer.min(MAX_SPLAT_LAYERS - 1)] = 1.0;
+    weights
+}
+
+fn normalized(weights: [f32; MAX_SPLAT_LAYERS]) -> [f32; MAX_SPLAT_LAYERS] {
+    let sum: f32 = weights.iter().sum();
+    weights.map(|w| w / sum)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplatBrush {
+    pub layer: usize,
+    /// World units
+    pub radius: f32,
+    /// How fast the layer takes over at the brush centre, per second
+    pub strength: f32,
+    pub falloff: BrushFalloff,
+}
+
+impl Default for SplatBrush {
+    fn default() -> Self {
+        Self {
+            layer: 0,
+            radius: 16.0,
+            strength: 2.0,
+            falloff: BrushFalloff::default(),
+        }
+    }
+}
+
+/// Blends `dt` seconds of `brush` into the splat map of the cell at `origin`. Returns
+/// whether anything changed.
+pub fn paint_splat(
+    splat: &mut SplatMap,
+    origin: Vec2,
+    cell_size: f32,
+    brush: &SplatBrush,
+    center: Vec2,
+    dt: f32,
+) -> bool {
+    let target = one_hot(brush.layer);
+    let mut changed = false;
+
+    for y in 0..splat.size {
+        for x in 0..splat.size {
+            let position = splat.texel_position(uvec2(x, y), origin, cell_size);
+            let weight = brush
+                .falloff
+                .weight(position.distance(center) / brush.radius);
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let t = (brush.strength * weight * dt).min(1.0);
+            let weights = &mut splat.weights[(y * splat.size + x) as usize];
+            for (w, target) in weights.iter_mut().zip(target) {
+                *w = w.lerp(target, t);
+            }
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain_processing::WorldSettings;
+    use crate::terrain_processing::heightmap::GrayF32Image;
+    use image::Luma;
+
+    /// Flat at height 0 for x < 32, a 60 degree ramp after
+    fn test_bundle() -> HeightmapBundle {
+        let ramp = 60f32.to_radians().tan();
+        let map = GrayF32Image::from_fn(65, 65, |x, _| {
+            Luma([(x as f32 - 32.0).max(0.0) * ramp / 1000.0])
+        });
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 32,
+            world_height: 1000.0,
+            world_height_offset: 0.0,
+        };
+        HeightmapBundle::new(map, world)
+    }
+
+    fn test_layers() -> Vec<SplatLayer> {
+        vec![
+            SplatLayer::new("Grass", SplatRule::ANY),
+            SplatLayer::new(
+                "Rock",
+                SplatRule {
+                    min_slope: 30f32.to_radians(),
+                    ..SplatRule::ANY
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_rules_pick_layers() {
+        let h_bundle = test_bundle();
+        let flat = SplatMap::from_rules(&h_bundle, &test_layers(), uvec2(0, 0), 8);
+        let steep = SplatMap::from_rules(&h_bundle, &test_layers(), uvec2(32, 0), 8);
+
+        assert_eq!(flat.weights(uvec2(4, 4))[..2], [1.0, 0.0]);
+        assert_eq!(steep.weights(uvec2(4, 4))[..2], [0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_weights_are_normalized() {
+        let layers = vec![
+            SplatLayer::new("A", SplatRule::ANY),
+            SplatLayer::new(
+                "B",
+                SplatRule {
+                    max_height: 10.0,
+                    height_blend: 40.0,
+                    ..SplatRule::ANY
+                },
+            ),
+        ];
+        let splat = SplatMap::from_rules(&test_bundle(), &layers, uvec2(32, 0), 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let sum: f32 = splat.weights(uvec2(x, y)).iter().sum();
+                assert!((sum - 1.0).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_paint_blends_towards_layer() {
+        let mut splat = SplatMap::new(16, 0);
+        let brush = SplatBrush {
+            layer: 5,
+            radius: 8.0,
+            strength: 1.0,
+            falloff: BrushFalloff::Constant,
+        };
+        assert!(paint_splat(
+            &mut splat,
+            Vec2::ZERO,
+            32.0,
+            &brush,
+            vec2(16.0, 16.0),
+            0.5
+        ));
+
+        let center = splat.weights(uvec2(8, 8));
+        assert_eq!((center[0], center[5]), (0.5, 0.5));
+        assert_eq!(splat.weights(uvec2(0, 0))[0], 1.0);
+
+        let [low, high] = splat.to_rgba8();
+        let i = (8 * 16 + 8) * 4;
+        assert_eq!((low[i], high[i + 1]), (128, 128));
+    }
+
+    #[test]
+    fn test_layers_save_load() {
+        let mut layers = SplatLayer::default_layers(1000.0, -50.0);
+        layers[2].texture = Some(PathBuf::from("textures/rock.png"));
+        layers[2].tile_size = 12.5;
+
+        let path = std::env::temp_dir().join("worldedit_test_layers.wset");
+        SplatLayer::save_all(&path, &layers).unwrap();
+        assert_eq!(SplatLayer::load_all(&path).unwrap(), layers);
+    }
+
+    #[test]
+    fn test_splat_map_save_load() {
+        let mut splat = SplatMap::new(8, 0);
+        paint_splat(
+            &mut splat,
+            Vec2::ZERO,
+            32.0,
+            &SplatBrush::default(),
+            vec2(8.0, 8.0),
+            0.1,
+        );
+
+        let cell = URect::new(32, 0, 64, 32);
+        let path = std::env::temp_dir().join("worldedit_test_splat.wsplat");
+        splat.save(&path, cell).unwrap();
+        assert_eq!(SplatMap::load(&path).unwrap(), (splat, cell));
+    }
+
+    #[test]
+    fn test_remap_layers() {
+        let mut splat = SplatMap::new(4, 0);
+        splat.weights[0] = normalized([1.0, 2.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        splat.weights[1] = one_hot(1);
+
+        // Layer 1 moved to the front, layer 0 removed, a new layer added
+        splat.remap_layers(&[Some(1), Some(2), None]);
+        assert_eq!(splat.weights(uvec2(0, 0))[..3], [2.0 / 3.0, 1.0 / 3.0, 0.0]);
+        assert_eq!(splat.weights(uvec2(1, 0)), one_hot(0));
+        // Only the removed layer there, so the first one takes over
+        assert_eq!(splat.weights(uvec2(2, 0)), one_hot(0));
+    }
+
+    #[test]
+    fn test_paint_outside_cell() {
+        let mut splat = SplatMap::new(16, 0);
+        let brush = SplatBrush::default();
+        assert!(!paint_splat(
+            &mut splat,
+            Vec2::ZERO,
+            32.0,
+            &brush,
+            vec2(100.0, 0.0),
+            1.0
+        ));
+    }
+}
@@ -1,51 +1,669 @@
+use std::hash::Hasher;
+use std::path::Path;
 use std::path::PathBuf;
 
-use bevy::math::prelude::*;
-use image::ImageReader;
+use bevy::math::UVec2;
+use derive_more::Display;
 
 use crate::terrain_processing::CACHE_DIR;
-use crate::terrain_processing::CELL_SIZE;
+use crate::terrain_processing::CacheCellEntry;
+use crate::terrain_processing::CacheManifest;
+use crate::terrain_processing::ContentHasher;
+use crate::terrain_processing::HeightEncoding;
+use crate::terrain_processing::HeightFilter;
+use crate::terrain_processing::HeightLayer;
 use crate::terrain_processing::HeightmapBundle;
-use crate::terrain_processing::NUM_CELLS;
-use crate::terrain_processing::NUM_CELLS_ROW;
 use crate::terrain_processing::TerrainMesh;
+use crate::terrain_processing::WorldSettings;
+use crate::terrain_processing::analysis::AnalysisMap;
+use crate::terrain_processing::content_hash;
+use crate::terrain_processing::erosion;
+use crate::terrain_processing::erosion::Erosion;
+use crate::terrain_processing::erosion::ErosionSettings;
+use crate::terrain_processing::generator;
+use crate::terrain_processing::generator::NoiseSettings;
 use crate::terrain_processing::heightmap;
+use crate::terrain_processing::heightmap::GrayF32Image;
+use crate::terrain_processing::heightmap_import;
+use crate::terrain_processing::heightmap_import::ImportError;
+use crate::terrain_processing::heightmap_import::ImportSettings;
+use crate::terrain_processing::heightmap_ops;
+use crate::terrain_processing::heightmap_ops::ResampleFilter;
 
-pub fn crunch_terrain() {
-    if std::fs::exists(CACHE_DIR).unwrap() {
-        std::fs::remove_dir_all(CACHE_DIR).unwrap();
-    }
-    std::fs::create_dir_all(CACHE_DIR).unwrap();
-
-    let base_map = heightmap::from_dynamic_image(
-        ImageReader::open("assets/heightmaps/test_island_0.png")
-            .unwrap()
-            .decode()
-            .unwrap(),
-    );
-    heightmap::save(
-        &PathBuf::from(CACHE_DIR).join("base_heightmap.hmp"),
-        &base_map,
-    )
-    .unwrap();
-    heightmap::save_png(
-        &PathBuf::from(CACHE_DIR).join("base_heightmap.png"),
-        &base_map,
-    )
-    .unwrap();
-    let h_bundle = HeightmapBundle::new(base_map);
-
-    for i in 0..NUM_CELLS {
-        let cell_name = format!("cell_{i:03}");
-        let cell_position = uvec2(
-            ((i % NUM_CELLS_ROW) * CELL_SIZE) as u32,
-            ((i / NUM_CELLS_ROW) * CELL_SIZE) as u32,
+pub const DEFAULT_SOURCE: &str = "assets/pd_heightmaps/Hand_made_terrain_heightmap4096.png";
+
+/// Input and output settings for [crunch_terrain].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrunchConfig {
+    /// Source heightmap image.
+    pub source_path: PathBuf,
+    /// How to read [Self::source_path]
+    pub import: ImportSettings,
+    /// Generate the heightmap from noise instead of reading [Self::source_path]
+    pub generator: Option<NoiseSettings>,
+    /// Erode the source heightmap before meshing
+    pub erosion: Option<ErosionSettings>,
+    /// Output directory. Cells already up to date in here are kept.
+    pub cache_dir: PathBuf,
+    pub world: WorldSettings,
+    /// Quads per cell row. `None` gives one vertex per world unit.
+    pub mesh_resolution: Option<u32>,
+    /// Heightmap filtering used when sampling cell vertices
+    pub filter: HeightFilter,
+    /// Generate tangents for normal mapping
+    pub tangents: bool,
+    /// How cell heights are stored in the cache
+    pub height_encoding: HeightEncoding,
+    /// [HeightLayer] files applied over the base map, bottom to top. Missing files are
+    /// skipped.
+    pub layers: Vec<PathBuf>,
+}
+
+impl Default for CrunchConfig {
+    fn default() -> Self {
+        Self {
+            source_path: PathBuf::from(DEFAULT_SOURCE),
+            import: ImportSettings::default(),
+            generator: None,
+            erosion: None,
+            cache_dir: PathBuf::from(CACHE_DIR),
+            world: WorldSettings::default(),
+            mesh_resolution: None,
+            filter: HeightFilter::default(),
+            tangents: false,
+            height_encoding: HeightEncoding::default(),
+            layers: Vec::new(),
+        }
+    }
+}
+
+impl CrunchConfig {
+    pub fn cell_mesh_path(&self, index: u32) -> PathBuf {
+        self.cache_dir
+            .join(format!("cell_{index:03}"))
+            .with_extension(TerrainMesh::FILE_EXT)
+    }
+
+    pub fn mesh_resolution(&self) -> u32 {
+        self.mesh_resolution.unwrap_or(self.world.cell_size)
+    }
+
+    pub fn validate(&self) -> Result<(), CrunchError> {
+        self.world
+            .validate()
+            .map_err(|e| CrunchError::InvalidConfig(e.to_string()))?;
+        if self.mesh_resolution == Some(0) {
+            return Err(CrunchError::InvalidConfig(
+                "mesh resolution must be non-zero".to_string(),
+            ));
+        }
+        if let Some(erosion) = &self.erosion {
+            erosion.validate().map_err(CrunchError::InvalidConfig)?;
+        }
+        if let Some(generator) = &self.generator {
+            generator
+                .validate()
+                .map_err(|e| CrunchError::InvalidConfig(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum CrunchError {
+    #[display("invalid crunch config: {_0}")]
+    InvalidConfig(String),
+    #[display("failed to import source heightmap: {_0}")]
+    Source(ImportError),
+    #[display("failed to write '{}': {_1}", _0.display())]
+    Write(PathBuf, std::io::Error),
+    #[display("failed to write '{}': {_1}", _0.display())]
+    WritePreview(PathBuf, image::ImageError),
+    #[display("failed to read layer '{}': {_1}", _0.display())]
+    Layer(PathBuf, std::io::Error),
+}
+
+impl std::error::Error for CrunchError {}
+
+/// What [crunch_terrain] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrunchReport {
+    pub num_cells: u32,
+    /// Cells that were stale and got rebuilt
+    pub num_regenerated: u32,
+}
+
+/// Updates the terrain cache from the source heightmap. Only stale cells are regenerated.
+///
+/// Runs everything on the calling thread. See [CrunchPlan] for crunching cells in parallel.
+pub fn crunch_terrain(config: &CrunchConfig) -> Result<CrunchReport, CrunchError> {
+    let plan = CrunchPlan::prepare(config.clone())?;
+
+    let mut entries = Vec::with_capacity(plan.num_cells() as usize);
+    let mut num_regenerated = 0;
+    for i in 0..plan.num_cells() {
+        let cell = plan.crunch_cell(i)?;
+        if cell.regenerated {
+            num_regenerated += 1;
+        }
+        entries.push(cell.entry);
+    }
+    plan.finish(entries)?;
+
+    Ok(CrunchReport {
+        num_cells: plan.num_cells(),
+        num_regenerated,
+    })
+}
+
+/// A cell produced by [CrunchPlan::crunch_cell].
+#[derive(Debug)]
+pub struct CrunchedCell {
+    pub index: u32,
+    pub mesh: TerrainMesh,
+    /// Manifest entry for [CrunchPlan::finish]
+    pub entry: CacheCellEntry,
+    /// False if the cell was loaded from cache
+    pub regenerated: bool,
+}
+
+/// A prepared crunch. Cells are independent and can be crunched from any thread.
+#[derive(Debug)]
+pub struct CrunchPlan {
+    config: CrunchConfig,
+    h_bundle: HeightmapBundle,
+    source_hash: u64,
+    layers_hash: u64,
+    old_manifest: Option<CacheManifest>,
+    /// Source and layers are unchanged, so old manifest entries can be trusted without
+    /// rehashing.
+    source_unchanged: bool,
+}
+
+impl CrunchPlan {
+    /// Reads the source heightmap (or its cached copy, if the source is unchanged).
+    pub fn prepare(config: CrunchConfig) -> Result<Self, CrunchError> {
+        Self::prepare_with_progress(config, |_| ())
+    }
+
+    /// Like [Self::prepare]. `on_erosion` is called with the number of finished
+    /// erosion iterations after each one.
+    pub fn prepare_with_progress(
+        config: CrunchConfig,
+        mut on_erosion: impl FnMut(u32),
+    ) -> Result<Self, CrunchError> {
+        config.validate()?;
+
+        let world = &config.world;
+        let cache_dir = &config.cache_dir;
+        let hmp_path = cache_dir.join("base_heightmap.hmp");
+
+        let source_bytes = match &config.generator {
+            Some(settings) => encoded(|bytes| settings.write(bytes)),
+            None => std::fs::read(&config.source_path)
+                .map_err(|e| CrunchError::Source(ImportError::Io(config.source_path.clone(), e)))?,
+        };
+        // Different settings make different cells from the same file
+        let mut hasher = ContentHasher::default();
+        hasher.write(&source_bytes);
+        hasher.write(&encoded(|bytes| config.import.write(bytes)));
+        hasher.write(&(config.erosion.is_some() as u32).to_le_bytes());
+        if let Some(erosion) = &config.erosion {
+            hasher.write(&encoded(|bytes| erosion.write(bytes)));
+        }
+        hasher.write(&(config.filter as u32).to_le_bytes());
+        hasher.write(&config.mesh_resolution().to_le_bytes());
+        let source_hash = hasher.finish();
+        let (layers, layers_hash) = Self::load_layers(&config)?;
+
+        // A missing or outdated manifest just means everything is stale.
+        let old_manifest = CacheManifest::load(&cache_dir.join(CacheManifest::FILE_NAME))
+            .ok()
+            .filter(|manifest| manifest.world == *world);
+
+        let cached_map = old_manifest
+            .as_ref()
+            .filter(|manifest| manifest.source_hash == source_hash)
+            .and_then(|_| heightmap::load(&hmp_path).ok())
+            .filter(|(_, hmp_world)| hmp_world == world);
+
+        if let Some((base_map, _)) = cached_map {
+            let layers_unchanged = old_manifest
+                .as_ref()
+                .is_some_and(|manifest| manifest.layers_hash == layers_hash);
+            if !layers_unchanged {
+                remove_analysis_maps(cache_dir);
+            }
+            return Ok(Self {
+                h_bundle: Self::heightmap_bundle_for(base_map, layers, &config),
+                config,
+                source_hash,
+                layers_hash,
+                old_manifest,
+                source_unchanged: layers_unchanged,
+            });
+        }
+
+        let base_map = match &config.generator {
+            // One texel per world unit
+            Some(settings) => generator::generate(UVec2::splat(world.world_size + 1), settings),
+            None => {
+                heightmap_import::import_bytes(&config.source_path, &source_bytes, &config.import)
+                    .map_err(CrunchError::Source)?
+            }
+        };
+
+        std::fs::create_dir_all(cache_dir).map_err(write_err(cache_dir))?;
+        remove_analysis_maps(cache_dir);
+
+        let base_map = match &config.erosion {
+            Some(settings) => {
+                let mut erosion = Erosion::new(base_map, settings);
+                loop {
+                    let done = erosion.step(1);
+                    on_erosion(erosion.iteration());
+                    if done {
+                        break;
+                    }
+                }
+                let (base_map, maps) = erosion.finish();
+                let by_products = [
+                    (erosion::SEDIMENT_FILE_NAME, maps.sediment),
+                    (erosion::FLOW_FILE_NAME, maps.flow),
+                ];
+                for (name, map) in by_products {
+                    if let Some(map) = map {
+                        let path = cache_dir.join(name);
+                        heightmap::save(&path, &map, world).map_err(write_err(&path))?;
+                    }
+                }
+                base_map
+            }
+            None => base_map,
+        };
+
+        heightmap::save(&hmp_path, &base_map, world).map_err(write_err(&hmp_path))?;
+        let png_path = cache_dir.join("base_heightmap.png");
+        heightmap::save_png(&png_path, &base_map)
+            .map_err(|e| CrunchError::WritePreview(png_path.clone(), e))?;
+
+        Ok(Self {
+            h_bundle: Self::heightmap_bundle_for(base_map, layers, &config),
+            config,
+            source_hash,
+            layers_hash,
+            old_manifest,
+            source_unchanged: false,
+        })
+    }
+
+    /// Reads [CrunchConfig::layers] and hashes their contents.
+    fn load_layers(config: &CrunchConfig) -> Result<(Vec<HeightLayer>, u64), CrunchError> {
+        let mut layers = Vec::with_capacity(config.layers.len());
+        let mut hasher = ContentHasher::default();
+        for path in &config.layers {
+            let bytes = match std::fs::read(path) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(CrunchError::Layer(path.clone(), e)),
+            };
+            hasher.write(&bytes);
+            let layer = HeightLayer::read(&mut bytes.as_slice())
+                .map_err(|e| CrunchError::Layer(path.clone(), e))?;
+            layers.push(layer);
+        }
+        Ok((layers, hasher.finish()))
+    }
+
+    fn heightmap_bundle_for(
+        base_map: GrayF32Image,
+        layers: Vec<HeightLayer>,
+        config: &CrunchConfig,
+    ) -> HeightmapBundle {
+        let mut h_bundle = HeightmapBundle::new(base_map, config.world);
+        h_bundle.set_filter(config.filter);
+        let size = h_bundle.size();
+        for mut layer in layers {
+            // Painted over a differently sized base map, e.g. before a reimport
+            if layer.size() != size {
+                let filter = ResampleFilter::Bilinear;
+                layer.map = heightmap_ops::resample(&layer.map, size, filter);
+                layer.mask = layer
+                    .mask
+                    .map(|mask| heightmap_ops::resample(&mask, size, filter));
+            }
+            // Sizes match now, so this can't fail
+            let _ = h_bundle.push_layer(layer);
+        }
+        h_bundle
+    }
+
+    pub const fn config(&self) -> &CrunchConfig {
+        &self.config
+    }
+
+    pub const fn num_cells(&self) -> u32 {
+        self.config.world.num_cells()
+    }
+
+    /// Analysis map of the base heightmap, computed and cached on first use.
+    pub fn analysis_map(&self, kind: AnalysisMap) -> Result<GrayF32Image, CrunchError> {
+        let world = &self.config.world;
+        let path = self.config.cache_dir.join(kind.file_name());
+        if let Ok((map, map_world)) = heightmap::load(&path)
+            && map_world == *world
+        {
+            return Ok(map);
+        }
+
+        let map = kind.compute(&self.h_bundle);
+        heightmap::save(&path, &map, world).map_err(write_err(&path))?;
+        Ok(map)
+    }
+
+    pub const fn heightmap_bundle(&self) -> &HeightmapBundle {
+        &self.h_bundle
+    }
+
+    /// Loads a cell from cache, or regenerates it if it's stale.
+    pub fn crunch_cell(&self, index: u32) -> Result<CrunchedCell, CrunchError> {
+        let world = &self.config.world;
+        let resolution = self.config.mesh_resolution();
+        let cell_position = world.cell_position(index);
+        let cell_mesh_path = self.config.cell_mesh_path(index);
+        let sample_heights = || {
+            TerrainMesh::sample_heights(cell_position, world, resolution, &|p| {
+                self.h_bundle.sample(p)
+            })
+        };
+
+        let old_entry = self
+            .old_manifest
+            .as_ref()
+            .map(|manifest| manifest.cells[index as usize]);
+        let mut heights = None;
+        let entry = match old_entry {
+            Some(old_entry) if self.source_unchanged => old_entry,
+            _ => {
+                let new_heights = sample_heights();
+                let hash = content_hash(bytemuck::cast_slice(&new_heights));
+                heights = Some(new_heights);
+                CacheCellEntry {
+                    file_ver: TerrainMesh::FILE_VER,
+                    content_hash: hash,
+                }
+            }
+        };
+
+        if entry.file_ver == TerrainMesh::FILE_VER
+            && old_entry == Some(entry)
+            && let Ok(mesh) = TerrainMesh::load(&cell_mesh_path)
+            && mesh.tangents().is_some() == self.config.tangents
+            && mesh.height_encoding() == self.config.height_encoding
+        {
+            return Ok(CrunchedCell {
+                index,
+                mesh,
+                entry,
+                regenerated: false,
+            });
+        }
+
+        let mesh = TerrainMesh::from_heights(
+            cell_position,
+            world,
+            resolution,
+            heights.unwrap_or_else(sample_heights),
+            self.config.tangents,
         );
-        let cell_mesh_path = PathBuf::from(CACHE_DIR)
-            .join(&cell_name)
-            .with_extension(TerrainMesh::FILE_EXT);
+        mesh.save(&cell_mesh_path, self.config.height_encoding)
+            .map_err(write_err(&cell_mesh_path))?;
+
+        Ok(CrunchedCell {
+            index,
+            mesh,
+            entry: CacheCellEntry {
+                file_ver: TerrainMesh::FILE_VER,
+                ..entry
+            },
+            regenerated: true,
+        })
+    }
+
+    /// Writes the cache manifest. Call once every cell has been crunched.
+    pub fn finish(&self, entries: Vec<CacheCellEntry>) -> Result<(), CrunchError> {
+        let manifest_path = self.config.cache_dir.join(CacheManifest::FILE_NAME);
+        let manifest = CacheManifest {
+            source_hash: self.source_hash,
+            layers_hash: self.layers_hash,
+            world: self.config.world,
+            cells: entries,
+        };
+        manifest
+            .save(&manifest_path)
+            .map_err(write_err(&manifest_path))
+    }
+}
+
+/// Analysis maps are derived from the old heights
+fn remove_analysis_maps(cache_dir: &Path) {
+    for kind in AnalysisMap::ALL {
+        let _ = std::fs::remove_file(cache_dir.join(kind.file_name()));
+    }
+}
+
+/// Settings in their file encoding, which unlike `Debug` output stays put across builds
+fn encoded(write: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    // Writing to memory can't fail
+    write(&mut bytes).unwrap();
+    bytes
+}
+
+fn write_err(path: &Path) -> impl FnOnce(std::io::Error) -> CrunchError {
+    let path = path.to_owned();
+    move |e| CrunchError::Write(path, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain_processing::LayerBlend;
+
+    #[test]
+    fn test_missing_source_is_an_error() {
+        let config = CrunchConfig {
+            source_path: PathBuf::from("assets/does_not_exist.png"),
+            cache_dir: std::env::temp_dir().join("worldedit_test_missing_source"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            crunch_terrain(&config),
+            Err(CrunchError::Source(..))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_world_size() {
+        let config = CrunchConfig {
+            world: WorldSettings {
+                world_size: 768,
+                cell_size: 512,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(CrunchError::InvalidConfig(..))
+        ));
+    }
+
+    fn small_test_config(name: &str) -> CrunchConfig {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        CrunchConfig {
+            source_path: dir.join("source.png"),
+            cache_dir: dir.join("cache"),
+            world: WorldSettings {
+                world_size: 64,
+                cell_size: 16,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_unchanged_source_is_not_regenerated() {
+        let config = small_test_config("worldedit_test_crunch_unchanged");
+        image::GrayImage::from_fn(64, 64, |x, y| image::Luma([(x + y) as u8]))
+            .save(&config.source_path)
+            .unwrap();
+
+        let first = crunch_terrain(&config).unwrap();
+        assert_eq!(first.num_regenerated, config.world.num_cells());
+
+        let second = crunch_terrain(&config).unwrap();
+        assert_eq!(second.num_regenerated, 0);
+    }
+
+    #[test]
+    fn test_only_changed_cells_are_regenerated() {
+        let config = small_test_config("worldedit_test_crunch_partial");
+        image::GrayImage::from_fn(64, 64, |x, y| image::Luma([(x + y) as u8]))
+            .save(&config.source_path)
+            .unwrap();
+        crunch_terrain(&config).unwrap();
+
+        // Change a pixel in the middle of cell 0 only
+        image::GrayImage::from_fn(64, 64, |x, y| match (x, y) {
+            (8, 8) => image::Luma([255]),
+            _ => image::Luma([(x + y) as u8]),
+        })
+        .save(&config.source_path)
+        .unwrap();
+
+        let report = crunch_terrain(&config).unwrap();
+        assert_eq!(report.num_regenerated, 1);
+    }
+
+    #[test]
+    fn test_neighbour_edit_regenerates_normals() {
+        let config = small_test_config("worldedit_test_crunch_neighbour");
+        image::GrayImage::from_fn(64, 64, |x, y| image::Luma([(x + y) as u8]))
+            .save(&config.source_path)
+            .unwrap();
+        crunch_terrain(&config).unwrap();
+
+        // Just past the edge of cell 0, in cell 1. Cell 0's edge normals sample it.
+        image::GrayImage::from_fn(64, 64, |x, y| match (x, y) {
+            (17, 8) => image::Luma([255]),
+            _ => image::Luma([(x + y) as u8]),
+        })
+        .save(&config.source_path)
+        .unwrap();
+
+        let report = crunch_terrain(&config).unwrap();
+        assert_eq!(report.num_regenerated, 2);
+    }
+
+    #[test]
+    fn test_height_encoding_change_regenerates() {
+        let mut config = small_test_config("worldedit_test_crunch_encoding");
+        image::GrayImage::from_fn(64, 64, |x, y| image::Luma([(x + y) as u8]))
+            .save(&config.source_path)
+            .unwrap();
+        crunch_terrain(&config).unwrap();
+
+        config.height_encoding = HeightEncoding::U16;
+        let report = crunch_terrain(&config).unwrap();
+        assert_eq!(report.num_regenerated, config.world.num_cells());
+        assert_eq!(crunch_terrain(&config).unwrap().num_regenerated, 0);
+    }
+
+    #[test]
+    fn test_layer_edit_regenerates_touched_cells() {
+        let mut config = small_test_config("worldedit_test_crunch_layers");
+        image::GrayImage::from_fn(64, 64, |x, y| image::Luma([(x + y) as u8]))
+            .save(&config.source_path)
+            .unwrap();
+        let layer_path = config.cache_dir.with_file_name("sculpt.wlayer");
+        config.layers = vec![layer_path.clone()];
+
+        // A missing layer file is just no layer
+        let first = crunch_terrain(&config).unwrap();
+        assert_eq!(first.num_regenerated, config.world.num_cells());
+
+        // In the middle of cell 0, on a base map of a different size
+        let mut layer = HeightLayer::new("Sculpt", LayerBlend::Add, UVec2::splat(33));
+        layer.map.put_pixel(4, 4, image::Luma([0.5]));
+        layer.save(&layer_path).unwrap();
+
+        let plan = CrunchPlan::prepare(config.clone()).unwrap();
+        assert_eq!(plan.heightmap_bundle().layers().len(), 1);
+        let report = crunch_terrain(&config).unwrap();
+        assert_eq!(report.num_regenerated, 1);
+        assert_eq!(crunch_terrain(&config).unwrap().num_regenerated, 0);
+    }
+
+    #[test]
+    fn test_generated_source() {
+        let mut config = CrunchConfig {
+            generator: Some(NoiseSettings::default()),
+            ..small_test_config("worldedit_test_crunch_generated")
+        };
+        let first = crunch_terrain(&config).unwrap();
+        assert_eq!(first.num_regenerated, config.world.num_cells());
+        assert_eq!(crunch_terrain(&config).unwrap().num_regenerated, 0);
+
+        config.generator = Some(NoiseSettings {
+            seed: 1,
+            ..Default::default()
+        });
+        let reseeded = crunch_terrain(&config).unwrap();
+        assert_eq!(reseeded.num_regenerated, config.world.num_cells());
+    }
+
+    #[test]
+    fn test_analysis_maps_are_cached_until_source_changes() {
+        let config = small_test_config("worldedit_test_crunch_analysis");
+        image::GrayImage::from_fn(64, 64, |x, y| image::Luma([(x + y) as u8]))
+            .save(&config.source_path)
+            .unwrap();
+        let plan = CrunchPlan::prepare(config.clone()).unwrap();
+        let slope = plan.analysis_map(AnalysisMap::Slope).unwrap();
+        let slope_path = config.cache_dir.join(AnalysisMap::Slope.file_name());
+        assert!(slope_path.exists());
+        assert_eq!(plan.analysis_map(AnalysisMap::Slope).unwrap(), slope);
+
+        image::GrayImage::from_fn(64, 64, |x, _| image::Luma([x as u8]))
+            .save(&config.source_path)
+            .unwrap();
+        CrunchPlan::prepare(config).unwrap();
+        assert!(!slope_path.exists());
+    }
+
+    #[test]
+    fn test_eroded_source_writes_flow_map() {
+        let config = CrunchConfig {
+            generator: Some(NoiseSettings::default()),
+            erosion: Some(ErosionSettings {
+                iterations: 2,
+                flow_map: true,
+                ..Default::default()
+            }),
+            ..small_test_config("worldedit_test_crunch_eroded")
+        };
+        let mut iterations = Vec::new();
+        CrunchPlan::prepare_with_progress(config.clone(), |i| iterations.push(i)).unwrap();
+        assert_eq!(iterations, [1, 2]);
 
-        let cell = TerrainMesh::new(cell_position, &|c| h_bundle.height(c));
-        cell.save(&cell_mesh_path).unwrap();
+        let (flow, world) =
+            heightmap::load(&config.cache_dir.join(erosion::FLOW_FILE_NAME)).unwrap();
+        assert_eq!(world, config.world);
+        assert_eq!(flow.width(), config.world.world_size + 1);
+        assert!(!config.cache_dir.join(erosion::SEDIMENT_FILE_NAME).exists());
     }
 }
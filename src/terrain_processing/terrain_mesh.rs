@@ -9,25 +9,36 @@ use bevy::math::prelude::*;
 use bevy::mesh::prelude::*;
 use bytemuck::cast_slice_mut;
 
-use crate::terrain_processing::CELL_SIZE;
-
-const CELL_SIZE_F: f32 = CELL_SIZE as f32;
-const V_STRIDE: usize = CELL_SIZE + 1;
-const NUM_V: usize = V_STRIDE * V_STRIDE;
+use crate::terrain_processing::WorldSettings;
 
 #[derive(Debug)]
 pub struct TerrainMesh {
     /// min corner coordinate
     position: UVec2,
+    world: WorldSettings,
+    /// Quads per row, independent of the heightmap resolution
+    resolution: u32,
+    /// Heights row by row, including a one vertex ring of the neighbouring cells' heights
+    /// around the cell. This is all that gets saved.
+    heights: Vec<f32>,
     vertices: Vec<Vec3>,
     uvs: Vec<Vec2>,
     indices: Vec<u32>,
+    normals: Vec<Vec3>,
+    /// Only generated on request, see [Self::generate_tangents]
+    tangents: Option<Vec<Vec4>>,
+    /// How the file this was loaded from stored the heights. Lossless when not loaded.
+    height_encoding: HeightEncoding,
 }
 
 impl TerrainMesh {
     pub const FILE_EXT: &str = "tmesh";
     pub const FILE_SIG: &[u8; 16] = b"WEdit-TMesh     ";
-    pub const FILE_VER: u32 = 1;
+    pub const FILE_VER: u32 = 5;
+    const FLAG_U16: u32 = 1 << 0;
+    const FLAG_TANGENTS: u32 = 1 << 1;
+    /// Upper limit for [Self::num_lods]
+    pub const MAX_LODS: u32 = 6;
 
     pub fn position(&self) -> &UVec2 {
         &self.position
@@ -41,6 +52,19 @@ impl TerrainMesh {
         self.position = position;
     }
 
+    pub fn world(&self) -> &WorldSettings {
+        &self.world
+    }
+
+    pub const fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// World units between two vertices
+    pub fn vertex_spacing(&self) -> f32 {
+        self.world.cell_size as f32 / self.resolution as f32
+    }
+
     pub fn vertices(&self) -> &Vec<Vec3> {
         &self.vertices
     }
@@ -49,6 +73,41 @@ impl TerrainMesh {
         &self.indices
     }
 
+    pub fn normals(&self) -> &Vec<Vec3> {
+        &self.normals
+    }
+
+    pub fn tangents(&self) -> Option<&Vec<Vec4>> {
+        self.tangents.as_ref()
+    }
+
+    pub const fn height_encoding(&self) -> HeightEncoding {
+        self.height_encoding
+    }
+
+    /// Vertices per row
+    const fn v_stride(resolution: u32) -> usize {
+        resolution as usize + 1
+    }
+
+    const fn num_v(resolution: u32) -> usize {
+        Self::v_stride(resolution) * Self::v_stride(resolution)
+    }
+
+    /// Heights per row, see [Self::heights]
+    const fn h_stride(resolution: u32) -> usize {
+        resolution as usize + 3
+    }
+
+    const fn num_h(resolution: u32) -> usize {
+        Self::h_stride(resolution) * Self::h_stride(resolution)
+    }
+
+    const fn num_i(resolution: u32) -> usize {
+        let resolution = resolution as usize;
+        resolution * resolution * 6 // num_quads * indices_in_quad
+    }
+
     pub fn bevy_mesh(&self) -> Mesh {
         use bevy::asset::RenderAssetUsages;
         use bevy::mesh::Indices;
@@ -60,86 +119,302 @@ impl TerrainMesh {
         );
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices.clone());
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals.clone());
+        if let Some(tangents) = &self.tangents {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents.clone());
+        }
         mesh.insert_indices(Indices::U32(self.indices.clone()));
         mesh
     }
 
-    pub fn new(position: UVec2, f: &dyn Fn(UVec2) -> f32) -> Self {
-        let mut vertices = vec![Vec3::ZERO; NUM_V];
-        let mut uvs = vec![Vec2::ZERO; NUM_V];
-
-        for (z, (v_row, uv_row)) in vertices
-            .chunks_exact_mut(V_STRIDE)
-            .zip(uvs.chunks_exact_mut(V_STRIDE))
-            .enumerate()
-        {
-            let pos_z = z as u32 + position.y;
-            let uv_v = z as f32 / CELL_SIZE_F;
+    /// Number of available LOD levels. Level n keeps every 2^n:th vertex.
+    pub fn num_lods(&self) -> u32 {
+        let resolution = self.resolution;
+        let mut n = 1;
+        while n < Self::MAX_LODS && resolution.is_multiple_of(1 << n) && resolution >> n >= 2 {
+            n += 1;
+        }
+        n
+    }
 
-            for (x, (vertex, uv)) in v_row.iter_mut().zip(uv_row.iter_mut()).enumerate() {
-                let pos_x = x as u32 + position.x;
-                let uv_u = x as f32 / CELL_SIZE_F;
-                let h = f(uvec2(pos_x, pos_z));
+    /// Decimated mesh with skirts hanging from the borders to hide cracks between LOD levels.
+    pub fn bevy_mesh_lod(&self, lod: u32) -> Mesh {
+        use bevy::asset::RenderAssetUsages;
+        use bevy::mesh::Indices;
+        use bevy::mesh::PrimitiveTopology;
 
-                *vertex = Vec3::new(pos_x as f32, h, pos_z as f32);
-                *uv = Vec2::new(uv_u, uv_v);
+        let step = 1 << lod.min(self.num_lods() - 1);
+        let v_stride = Self::v_stride(self.resolution);
+        let lod_size = self.resolution as usize / step;
+        let lod_stride = lod_size + 1;
+
+        let mut vertices = Vec::with_capacity(lod_stride * lod_stride + lod_size * 4);
+        let mut uvs = Vec::with_capacity(vertices.capacity());
+        let mut normals = Vec::with_capacity(vertices.capacity());
+        let mut tangents = self
+            .tangents
+            .as_ref()
+            .map(|_| Vec::with_capacity(vertices.capacity()));
+        for z in 0..lod_stride {
+            for x in 0..lod_stride {
+                let i = z * step * v_stride + x * step;
+                vertices.push(self.vertices[i]);
+                uvs.push(self.uvs[i]);
+                normals.push(self.normals[i]);
+                if let (Some(lod_tangents), Some(tangents)) = (&mut tangents, &self.tangents) {
+                    lod_tangents.push(tangents[i]);
+                }
+            }
+        }
+        let mut indices = grid_indices(lod_size);
+
+        // Walk the border so that skirt triangles face outwards
+        let border: Vec<usize> = (0..lod_size)
+            .chain((0..lod_size).map(|z| z * lod_stride + lod_size))
+            .chain((1..=lod_size).rev().map(|x| lod_size * lod_stride + x))
+            .chain((1..=lod_size).rev().map(|z| z * lod_stride))
+            .collect();
+
+        let skirt_offset = Vec3::Y * self.skirt_depth();
+        let skirt_start = vertices.len();
+        for &i in &border {
+            vertices.push(vertices[i] - skirt_offset);
+            uvs.push(uvs[i]);
+            normals.push(normals[i]);
+            if let Some(tangents) = &mut tangents {
+                tangents.push(tangents[i]);
             }
         }
+        for i in 0..border.len() {
+            let next = (i + 1) % border.len();
+            let a = border[i] as u32;
+            let b = border[next] as u32;
+            let sa = (skirt_start + i) as u32;
+            let sb = (skirt_start + next) as u32;
+            indices.extend([a, b, sa, b, sb, sa]);
+        }
 
-        let num_quads = CELL_SIZE * CELL_SIZE;
-        let mut indices = Vec::with_capacity(num_quads * 6);
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        if let Some(tangents) = tangents {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+        }
+        mesh.insert_indices(Indices::U32(indices));
+        mesh
+    }
 
-        for z in 0..CELL_SIZE {
-            let row0 = z * V_STRIDE;
-            let row1 = (z + 1) * V_STRIDE;
+    /// Deep enough to cover the largest gap between this cell's border at any LOD level and
+    /// the full-detail border.
+    fn skirt_depth(&self) -> f32 {
+        const MARGIN: f32 = 1.0;
+
+        let resolution = self.resolution as usize;
+        let v_stride = Self::v_stride(self.resolution);
+        let edges: [&dyn Fn(usize) -> usize; 4] = [
+            &|i| i,
+            &|i| i * v_stride,
+            &|i| resolution * v_stride + i,
+            &|i| i * v_stride + resolution,
+        ];
+
+        let mut depth = 0.0f32;
+        for lod in 1..self.num_lods() {
+            let step = 1 << lod;
+            for edge in edges {
+                let h = |i: usize| self.vertices[edge(i)].y;
+                for i in 0..=resolution {
+                    let i0 = i / step * step;
+                    let i1 = (i0 + step).min(resolution);
+                    let t = (i - i0) as f32 / step as f32;
+                    let lerp = h(i0) + (h(i1) - h(i0)) * t;
+                    depth = depth.max((h(i) - lerp).abs());
+                }
+            }
+        }
+        depth + MARGIN
+    }
 
-            for x in 0..CELL_SIZE {
-                let a = (row0 + x) as u32;
-                let b = (row0 + x + 1) as u32;
-                let c = (row1 + x) as u32;
-                let d = (row1 + x + 1) as u32;
+    /// Min and max corners of the cell's bounding box
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.vertices
+            .iter()
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), v| {
+                (min.min(*v), max.max(*v))
+            })
+    }
 
-                indices.push(a);
-                indices.push(c);
-                indices.push(b);
+    /// `f` gives the height at a world position. `resolution` is the number of quads per
+    /// row, [WorldSettings::cell_size] gives one vertex per world unit.
+    pub fn new(
+        position: UVec2,
+        world: &WorldSettings,
+        resolution: u32,
+        f: &dyn Fn(Vec2) -> f32,
+    ) -> Self {
+        let heights = Self::sample_heights(position, world, resolution, f);
+        Self::from_heights(position, world, resolution, heights, false)
+    }
 
-                indices.push(b);
-                indices.push(c);
-                indices.push(d);
+    /// Builds the grid from [Self::sample_heights]. Only the heights are stored in files,
+    /// everything else is derived from them.
+    pub(crate) fn from_heights(
+        position: UVec2,
+        world: &WorldSettings,
+        resolution: u32,
+        heights: Vec<f32>,
+        tangents: bool,
+    ) -> Self {
+        let resolution_f = resolution as f32;
+        let spacing = world.cell_size as f32 / resolution_f;
+        let v_stride = Self::v_stride(resolution);
+        let h_stride = Self::h_stride(resolution);
+
+        let mut vertices = Vec::with_capacity(Self::num_v(resolution));
+        let mut uvs = Vec::with_capacity(Self::num_v(resolution));
+        let mut normals = Vec::with_capacity(Self::num_v(resolution));
+
+        for z in 0..v_stride {
+            let pos_z = position.y as f32 + z as f32 * spacing;
+            let uv_v = z as f32 / resolution_f;
+
+            for x in 0..v_stride {
+                let pos_x = position.x as f32 + x as f32 * spacing;
+                let uv_u = x as f32 / resolution_f;
+                let i = (z + 1) * h_stride + x + 1;
+
+                vertices.push(Vec3::new(pos_x, heights[i], pos_z));
+                uvs.push(Vec2::new(uv_u, uv_v));
+
+                // Central differences. The ring holds the neighbouring cells' heights, so
+                // both sides of a seam get the same normal.
+                let dx = (heights[i + 1] - heights[i - 1]) / (2.0 * spacing);
+                let dz = (heights[i + h_stride] - heights[i - h_stride]) / (2.0 * spacing);
+                normals.push(vec3(-dx, 1.0, -dz).normalize());
             }
         }
 
-        Self {
+        let indices = grid_indices(resolution as usize);
+
+        let mut mesh = Self {
             position,
+            world: *world,
+            resolution,
+            heights,
             uvs,
             vertices,
             indices,
+            normals,
+            tangents: None,
+            height_encoding: HeightEncoding::F32,
+        };
+        if tangents {
+            mesh.generate_tangents();
         }
+        mesh
     }
 
-    pub fn apply_height(&mut self, f: &dyn Fn(UVec2) -> f32) {
-        for (i, vertex) in self.vertices.iter_mut().enumerate() {
-            let mut position = self.position;
-            position.x += (i % V_STRIDE) as u32;
-            position.y += (i / V_STRIDE) as u32;
-            vertex.y = f(position);
-        }
+    /// Samples `f` over the cell and the ring around it. The ring is extrapolated past the
+    /// world's min edges.
+    pub(crate) fn sample_heights(
+        position: UVec2,
+        world: &WorldSettings,
+        resolution: u32,
+        f: &dyn Fn(Vec2) -> f32,
+    ) -> Vec<f32> {
+        let spacing = world.cell_size as f32 / resolution as f32;
+        let h_stride = Self::h_stride(resolution);
+        // In vertices from the world origin
+        let origin = (position / world.cell_size * resolution).as_ivec2() - IVec2::ONE;
+        (0..Self::num_h(resolution))
+            .map(|i| {
+                let c = origin + ivec2((i % h_stride) as i32, (i / h_stride) as i32);
+                sample_extrapolated(c, IVec2::MAX, &|c| f(c.as_vec2() * spacing))
+            })
+            .collect()
     }
 
-    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+    pub fn apply_height(&mut self, f: &dyn Fn(Vec2) -> f32) {
+        let heights = Self::sample_heights(self.position, &self.world, self.resolution, f);
+        *self = Self::from_heights(
+            self.position,
+            &self.world,
+            self.resolution,
+            heights,
+            self.tangents.is_some(),
+        );
+    }
+
+    /// Tangents along +U, for normal mapping. Kept up to date by [Self::apply_height] once
+    /// generated.
+    pub fn generate_tangents(&mut self) {
+        self.tangents = Some(
+            self.normals
+                .iter()
+                .map(|n| {
+                    // Gram-Schmidt +X against the normal. UV v runs along +Z, which is
+                    // -(N x T), hence the negative handedness.
+                    let t = (Vec3::X - *n * n.x).normalize();
+                    t.extend(-1.0)
+                })
+                .collect(),
+        );
+    }
+
+    pub fn save(&self, path: &Path, encoding: HeightEncoding) -> std::io::Result<()> {
         let mut file = BufWriter::new(File::create(path)?);
 
         file.write_all(Self::FILE_SIG)?;
         file.write_all(&Self::FILE_VER.to_le_bytes())?;
         file.write_all(&self.position.x.to_le_bytes())?;
         file.write_all(&self.position.y.to_le_bytes())?;
-        file.write_all(bytemuck::cast_slice(&self.vertices))?;
-        file.write_all(bytemuck::cast_slice(&self.uvs))?;
-        file.write_all(bytemuck::cast_slice(&self.indices))?;
+        self.world.write_header(&mut file)?;
+        file.write_all(&self.resolution.to_le_bytes())?;
+
+        let mut flags = 0;
+        if encoding == HeightEncoding::U16 {
+            flags |= Self::FLAG_U16;
+        }
+        if self.tangents.is_some() {
+            flags |= Self::FLAG_TANGENTS;
+        }
+        file.write_all(&flags.to_le_bytes())?;
+
+        match encoding {
+            HeightEncoding::F32 => file.write_all(bytemuck::cast_slice(&self.heights))?,
+            HeightEncoding::U16 => {
+                let (min, max) = self
+                    .heights
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+                        (min.min(*h), max.max(*h))
+                    });
+                let scale = if max > min {
+                    u16::MAX as f32 / (max - min)
+                } else {
+                    0.0
+                };
+                let quantized: Vec<u16> = self
+                    .heights
+                    .iter()
+                    .map(|h| ((h - min) * scale).round() as u16)
+                    .collect();
+
+                file.write_all(&min.to_le_bytes())?;
+                file.write_all(&max.to_le_bytes())?;
+                file.write_all(bytemuck::cast_slice(&quantized))?;
+            }
+        }
 
         Ok(())
     }
 
+    /// Reads the current format as well as the older ones. v1 - v3 stored the full vertex
+    /// buffers, v4 had no resolution.
     pub fn load(path: &Path) -> std::io::Result<Self> {
         let mut file = BufReader::new(File::open(path)?);
 
@@ -155,7 +430,7 @@ impl TerrainMesh {
         }
         file.read_exact(&mut buf)?;
         let ver = u32::from_le_bytes(buf);
-        if ver != Self::FILE_VER {
+        if !(1..=Self::FILE_VER).contains(&ver) {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
@@ -166,54 +441,367 @@ impl TerrainMesh {
         let pos_x = u32::from_le_bytes(buf);
         file.read_exact(&mut buf)?;
         let pos_y = u32::from_le_bytes(buf);
+        let position = uvec2(pos_x, pos_y);
 
-        const NUM_I: usize = CELL_SIZE * CELL_SIZE * 6; // num_quads * indices_in_quad
+        if ver < 4 {
+            return Self::load_legacy(&mut file, ver, position);
+        }
 
-        let position = uvec2(pos_x, pos_y);
-        let mut vertices = vec![Vec3::ZERO; NUM_V];
-        let mut uvs = vec![Vec2::ZERO; NUM_V];
-        let mut indices = vec![0; NUM_I];
-
-        let vertices_bytes: &mut [u8] = cast_slice_mut(&mut vertices);
-        let uvs_bytes: &mut [u8] = cast_slice_mut(&mut uvs);
-        let indices_bytes: &mut [u8] = cast_slice_mut(&mut indices);
-        file.read_exact(vertices_bytes)?;
-        file.read_exact(uvs_bytes)?;
-        file.read_exact(indices_bytes)?;
-
-        Ok(Self {
+        let world = WorldSettings::read_header(&mut file)?;
+        let resolution = match ver {
+            4 => world.cell_size,
+            _ => {
+                file.read_exact(&mut buf)?;
+                u32::from_le_bytes(buf)
+            }
+        };
+        if resolution == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "resolution must be non-zero",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let flags = u32::from_le_bytes(buf);
+
+        let mut heights = vec![0.0f32; Self::num_h(resolution)];
+        let height_encoding = match flags & Self::FLAG_U16 {
+            0 => HeightEncoding::F32,
+            _ => HeightEncoding::U16,
+        };
+        if height_encoding == HeightEncoding::U16 {
+            file.read_exact(&mut buf)?;
+            let min = f32::from_le_bytes(buf);
+            file.read_exact(&mut buf)?;
+            let max = f32::from_le_bytes(buf);
+
+            let mut quantized = vec![0u16; heights.len()];
+            file.read_exact(cast_slice_mut(&mut quantized))?;
+            let scale = (max - min) / u16::MAX as f32;
+            for (h, q) in heights.iter_mut().zip(quantized) {
+                *h = min + q as f32 * scale;
+            }
+        } else {
+            file.read_exact(cast_slice_mut(&mut heights))?;
+        }
+
+        let mut mesh = Self::from_heights(
             position,
-            vertices,
-            uvs,
-            indices,
-        })
+            &world,
+            resolution,
+            heights,
+            flags & Self::FLAG_TANGENTS != 0,
+        );
+        mesh.height_encoding = height_encoding;
+        Ok(mesh)
+    }
+
+    /// v1 had no world header and a fixed 512 cell size. v2 added the header, v3 normals
+    /// and tangents. Only heights (and whether there were tangents) are kept, the ring
+    /// around the cell is extrapolated.
+    fn load_legacy(file: &mut impl Read, ver: u32, position: UVec2) -> std::io::Result<Self> {
+        let world = match ver {
+            1 => WorldSettings::default(),
+            _ => WorldSettings::read_header(file)?,
+        };
+        let resolution = world.cell_size;
+        let num_v = Self::num_v(resolution);
+
+        let mut vertices = vec![Vec3::ZERO; num_v];
+        file.read_exact(cast_slice_mut(&mut vertices))?;
+        // uvs and indices
+        skip_bytes(
+            file,
+            (num_v * size_of::<Vec2>() + Self::num_i(resolution) * 4) as u64,
+        )?;
+
+        let mut tangents = false;
+        if ver >= 3 {
+            skip_bytes(file, (num_v * size_of::<Vec3>()) as u64)?;
+            let mut buf = [0u8; 4];
+            file.read_exact(&mut buf)?;
+            tangents = u32::from_le_bytes(buf) != 0;
+        }
+
+        let v_stride = Self::v_stride(resolution);
+        let h_stride = Self::h_stride(resolution);
+        let max = IVec2::splat(resolution as i32);
+        let heights = (0..Self::num_h(resolution))
+            .map(|i| {
+                let c = ivec2((i % h_stride) as i32, (i / h_stride) as i32) - IVec2::ONE;
+                sample_extrapolated(c, max, &|c| {
+                    vertices[c.y as usize * v_stride + c.x as usize].y
+                })
+            })
+            .collect();
+
+        Ok(Self::from_heights(
+            position, &world, resolution, heights, tangents,
+        ))
     }
 }
 
+/// How [TerrainMesh::save] stores heights.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeightEncoding {
+    /// Lossless
+    #[default]
+    F32,
+    /// Quantized between the cell's min and max height. Half the size of [Self::F32].
+    U16,
+}
+
+/// `f` at `c`, extrapolating linearly one step past the `0..=max` range on either axis.
+fn sample_extrapolated(c: IVec2, max: IVec2, f: &dyn Fn(IVec2) -> f32) -> f32 {
+    let s = |c| sample_extrapolated(c, max, f);
+    if c.x < 0 {
+        2.0 * s(c.with_x(0)) - s(c.with_x(1))
+    } else if c.x > max.x {
+        2.0 * s(c.with_x(max.x)) - s(c.with_x(max.x - 1))
+    } else if c.y < 0 {
+        2.0 * s(c.with_y(0)) - s(c.with_y(1))
+    } else if c.y > max.y {
+        2.0 * s(c.with_y(max.y)) - s(c.with_y(max.y - 1))
+    } else {
+        f(c)
+    }
+}
+
+fn skip_bytes(file: &mut impl Read, num: u64) -> std::io::Result<()> {
+    let skipped = std::io::copy(&mut file.take(num), &mut std::io::sink())?;
+    if skipped != num {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Two triangles for each quad in a square grid
+fn grid_indices(quads_per_row: usize) -> Vec<u32> {
+    let v_stride = quads_per_row + 1;
+    let mut indices = Vec::with_capacity(quads_per_row * quads_per_row * 6);
+
+    for z in 0..quads_per_row {
+        let row0 = z * v_stride;
+        let row1 = (z + 1) * v_stride;
+
+        for x in 0..quads_per_row {
+            let a = (row0 + x) as u32;
+            let b = (row0 + x + 1) as u32;
+            let c = (row1 + x) as u32;
+            let d = (row1 + x + 1) as u32;
+
+            indices.push(a);
+            indices.push(c);
+            indices.push(b);
+
+            indices.push(b);
+            indices.push(c);
+            indices.push(d);
+        }
+    }
+
+    indices
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
 
-    const fn dummy_height_fn(_: UVec2) -> f32 {
+    const fn dummy_height_fn(_: Vec2) -> f32 {
         0.0
     }
 
     #[test]
     fn test_vertices() {
-        let cell = TerrainMesh::new(UVec2::default(), &dummy_height_fn);
+        let world = WorldSettings::default();
+        let cell_size = world.cell_size as usize;
+        let cell = TerrainMesh::new(UVec2::default(), &world, world.cell_size, &dummy_height_fn);
         let vertices = cell.vertices();
 
-        for z in 0..=CELL_SIZE {
-            let row_off = z * (CELL_SIZE + 1);
+        for z in 0..=cell_size {
+            let row_off = z * (cell_size + 1);
             let zf = z as f32;
-            for x in 0..=CELL_SIZE {
+            for x in 0..=cell_size {
                 let xf = x as f32;
                 assert_eq!(vertices[row_off + x], vec3(xf, 0., zf),)
             }
         }
     }
 
+    #[test]
+    fn test_save_load_small_cell() {
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            ..Default::default()
+        };
+        let cell = TerrainMesh::new(uvec2(16, 32), &world, 16, &|c| c.x + c.y);
+        let path = std::env::temp_dir().join("worldedit_test_small_cell.tmesh");
+        cell.save(&path, HeightEncoding::F32).unwrap();
+        let loaded = TerrainMesh::load(&path).unwrap();
+
+        assert_eq!(loaded.position(), cell.position());
+        assert_eq!(loaded.world(), &world);
+        assert_eq!(loaded.vertices(), cell.vertices());
+        assert_eq!(loaded.indices(), cell.indices());
+        assert_eq!(loaded.normals(), cell.normals());
+        assert_eq!(loaded.tangents(), None);
+        assert_eq!(loaded.height_encoding(), HeightEncoding::F32);
+    }
+
+    #[test]
+    fn test_save_load_quantized() {
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            ..Default::default()
+        };
+        let mut cell = TerrainMesh::new(uvec2(0, 16), &world, 16, &|c| {
+            (c.x * 0.3).sin() * 40.0 + c.y
+        });
+        cell.generate_tangents();
+        let path = std::env::temp_dir().join("worldedit_test_quantized_cell.tmesh");
+        cell.save(&path, HeightEncoding::U16).unwrap();
+        let loaded = TerrainMesh::load(&path).unwrap();
+
+        // 16 bits over a ~100 unit range
+        let tolerance = 100.0 / u16::MAX as f32;
+        for (a, b) in loaded.vertices().iter().zip(cell.vertices()) {
+            assert_eq!(a.xz(), b.xz());
+            assert!((a.y - b.y).abs() <= tolerance);
+        }
+        assert_eq!(loaded.indices(), cell.indices());
+        assert!(loaded.tangents().is_some());
+        assert_eq!(loaded.height_encoding(), HeightEncoding::U16);
+    }
+
+    #[test]
+    fn test_load_v1() {
+        // v1: no world header, 512 cell size, raw vertices, uvs and indices
+        let world = WorldSettings::default();
+        let cell = TerrainMesh::new(uvec2(512, 0), &world, 512, &|c| c.x % 7.0);
+
+        let path = std::env::temp_dir().join("worldedit_test_v1_cell.tmesh");
+        let mut file = BufWriter::new(File::create(&path).unwrap());
+        file.write_all(TerrainMesh::FILE_SIG).unwrap();
+        file.write_all(&1u32.to_le_bytes()).unwrap();
+        file.write_all(&512u32.to_le_bytes()).unwrap();
+        file.write_all(&0u32.to_le_bytes()).unwrap();
+        file.write_all(bytemuck::cast_slice(&cell.vertices))
+            .unwrap();
+        file.write_all(bytemuck::cast_slice(&cell.uvs)).unwrap();
+        file.write_all(bytemuck::cast_slice(&cell.indices)).unwrap();
+        drop(file);
+
+        let loaded = TerrainMesh::load(&path).unwrap();
+        assert_eq!(loaded.position(), &uvec2(512, 0));
+        assert_eq!(loaded.world(), &world);
+        assert_eq!(loaded.vertices(), cell.vertices());
+        assert_eq!(loaded.uvs, cell.uvs);
+        assert_eq!(loaded.indices(), cell.indices());
+        assert_eq!(loaded.tangents(), None);
+    }
+
+    #[test]
+    fn test_coarse_resolution() {
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            ..Default::default()
+        };
+        let cell = TerrainMesh::new(uvec2(16, 0), &world, 4, &|c| c.x * 0.5);
+        assert_eq!(cell.vertex_spacing(), 4.0);
+        assert_eq!(cell.vertices().len(), 25);
+        assert_eq!(cell.vertices()[6], vec3(20.0, 10.0, 4.0));
+        assert_eq!(*cell.vertices().last().unwrap(), vec3(32.0, 16.0, 16.0));
+
+        let expected_normal = vec3(-0.5, 1.0, 0.0).normalize();
+        for n in cell.normals() {
+            assert!(n.abs_diff_eq(expected_normal, 1e-6));
+        }
+
+        let path = std::env::temp_dir().join("worldedit_test_coarse_cell.tmesh");
+        cell.save(&path, HeightEncoding::F32).unwrap();
+        let loaded = TerrainMesh::load(&path).unwrap();
+        assert_eq!(loaded.resolution(), 4);
+        assert_eq!(loaded.vertices(), cell.vertices());
+    }
+
+    #[test]
+    fn test_normals_on_slope() {
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            ..Default::default()
+        };
+        let mut cell = TerrainMesh::new(uvec2(16, 16), &world, 16, &|c| c.x);
+        cell.generate_tangents();
+
+        let expected_normal = vec3(-1.0, 1.0, 0.0).normalize();
+        let expected_tangent = vec3(1.0, 1.0, 0.0).normalize();
+        for (n, t) in cell.normals().iter().zip(cell.tangents().unwrap()) {
+            assert!(n.abs_diff_eq(expected_normal, 1e-6));
+            assert!(t.truncate().abs_diff_eq(expected_tangent, 1e-6));
+            assert!(n.dot(t.truncate()).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_seam_normals_match() {
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            ..Default::default()
+        };
+        let f = |c: Vec2| ((c.x as u32 * 7 + c.y as u32 * 13) % 5) as f32;
+        let left = TerrainMesh::new(uvec2(0, 0), &world, 16, &f);
+        let right = TerrainMesh::new(uvec2(16, 0), &world, 16, &f);
+
+        for z in 0..=16 {
+            let left_edge = left.normals()[z * 17 + 16];
+            let right_edge = right.normals()[z * 17];
+            assert_eq!(left_edge, right_edge);
+        }
+    }
+
+    #[test]
+    fn test_lod_vertex_counts() {
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            ..Default::default()
+        };
+        let cell = TerrainMesh::new(UVec2::ZERO, &world, 16, &dummy_height_fn);
+        assert_eq!(cell.num_lods(), 4);
+
+        for lod in 0..cell.num_lods() {
+            let lod_size = 16 >> lod;
+            let mesh = cell.bevy_mesh_lod(lod);
+            let grid_v = (lod_size + 1) * (lod_size + 1);
+            let skirt_v = lod_size * 4;
+            assert_eq!(mesh.count_vertices(), grid_v + skirt_v);
+            assert_eq!(
+                mesh.indices().unwrap().len(),
+                lod_size * lod_size * 6 + skirt_v * 6
+            );
+        }
+    }
+
+    #[test]
+    fn test_skirt_covers_lod_gap() {
+        let world = WorldSettings {
+            world_size: 64,
+            cell_size: 16,
+            ..Default::default()
+        };
+        // Spike in the middle of an edge, which coarser LODs skip
+        let cell = TerrainMesh::new(UVec2::ZERO, &world, 16, &|c| {
+            if c == vec2(7.0, 0.0) { 10.0 } else { 0.0 }
+        });
+        assert!(cell.skirt_depth() >= 10.0);
+    }
+
     // #[test]
     // fn test_serialize_flat() {
     //     let cell = TerrainMesh::flat(vec2(69., 420.));
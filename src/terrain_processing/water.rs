@@ -0,0 +1,652 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use bevy::math::prelude::*;
+use bevy::prelude::Mesh;
+
+use crate::terrain_processing::HeightmapBundle;
+
+/// Project wide water settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaterSettings {
+    /// World height of the sea surface
+    pub sea_level: f32,
+    pub show_sea: bool,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            sea_level: 0.0,
+            show_sea: true,
+        }
+    }
+}
+
+impl WaterSettings {
+    pub const FILE_NAME: &str = "water.wset";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-WaterSet  ";
+    pub const FILE_VER: u32 = 0;
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        file.write_all(&self.sea_level.to_le_bytes())?;
+        file.write_all(&(self.show_sea as u32).to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut buf = [0u8; 4];
+        let mut sig_buf = [0u8; 16];
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid FILE_SIG",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
+            ));
+        }
+
+        file.read_exact(&mut buf)?;
+        let sea_level = f32::from_le_bytes(buf);
+        if !sea_level.is_finite() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("sea level {sea_level} must be finite"),
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let show_sea = u32::from_le_bytes(buf) != 0;
+
+        Ok(Self {
+            sea_level,
+            show_sea,
+        })
+    }
+}
+
+/// Heightmap texels under water in a basin, found by [flood_fill_lake].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LakeExtent {
+    /// World height of the surface, where the basin spills over
+    pub level: f32,
+    /// First texel of the bounding box
+    pub min: UVec2,
+    pub size: UVec2,
+    /// Row by row over the bounding box
+    mask: Vec<bool>,
+}
+
+impl LakeExtent {
+    pub fn contains(&self, texel: UVec2) -> bool {
+        let Some(local) = texel.checked_sub(self.min) else {
+            return false;
+        };
+        local.cmplt(self.size).all() && self.mask[(local.y * self.size.x + local.x) as usize]
+    }
+
+    pub fn num_texels(&self) -> usize {
+        self.mask.iter().filter(|wet| **wet).count()
+    }
+}
+
+/// Lake or river surface, in heightmap space.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaterShape {
+    /// Basin filled up to its rim, from [flood_fill_lake]
+    Basin(LakeExtent),
+    /// Flat surface inside an outline
+    Lake {
+        outline: Vec<Vec2>,
+        level: f32,
+    },
+    River {
+        path: Vec<Vec3>,
+        width: f32,
+    },
+}
+
+impl WaterShape {
+    /// Project file holding every lake and river
+    pub const FILE_NAME: &str = "water_bodies.wbody";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-WaterBody ";
+    pub const FILE_VER: u32 = 0;
+
+    pub fn mesh(&self, texel_size: Vec2) -> WaterMesh {
+        match self {
+            Self::Basin(extent) => WaterMesh::lake(extent, texel_size),
+            Self::Lake { outline, level } => {
+                WaterMesh::polygon(outline, *level, texel_size.min_element())
+            }
+            Self::River { path, width } => WaterMesh::river(path, *width),
+        }
+    }
+
+    pub fn save_all(path: &Path, shapes: &[Self]) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        file.write_all(&(shapes.len() as u32).to_le_bytes())?;
+        for shape in shapes {
+            match shape {
+                Self::Basin(extent) => {
+                    file.write_all(&0u32.to_le_bytes())?;
+                    file.write_all(&extent.level.to_le_bytes())?;
+                    for v in [extent.min, extent.size] {
+                        file.write_all(&v.x.to_le_bytes())?;
+                        file.write_all(&v.y.to_le_bytes())?;
+                    }
+                    let mask: Vec<u8> = extent.mask.iter().map(|wet| *wet as u8).collect();
+                    file.write_all(&mask)?;
+                }
+                Self::Lake { outline, level } => {
+                    file.write_all(&1u32.to_le_bytes())?;
+                    file.write_all(&level.to_le_bytes())?;
+                    file.write_all(&(outline.len() as u32).to_le_bytes())?;
+                    file.write_all(bytemuck::cast_slice(outline))?;
+                }
+                Self::River { path, width } => {
+                    file.write_all(&2u32.to_le_bytes())?;
+                    file.write_all(&width.to_le_bytes())?;
+                    file.write_all(&(path.len() as u32).to_le_bytes())?;
+                    file.write_all(bytemuck::cast_slice(path))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load_all(path: &Path) -> std::io::Result<Vec<Self>> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut buf = [0u8; 4];
+        let mut sig_buf = [0u8; 16];
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid FILE_SIG",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
+            ));
+        }
+
+        let mut read_u32 = |file: &mut BufReader<File>| -> std::io::Result<u32> {
+            file.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+
+        let num_shapes = read_u32(&mut file)?;
+        let mut shapes = Vec::new();
+        for _ in 0..num_shapes {
+            let shape = match read_u32(&mut file)? {
+                0 => {
+                    let level = f32::from_bits(read_u32(&mut file)?);
+                    let min = uvec2(read_u32(&mut file)?, read_u32(&mut file)?);
+                    let size = uvec2(read_u32(&mut file)?, read_u32(&mut file)?);
+                    let len = size.x.checked_mul(size.y);
+                    let mut mask = vec![0u8; checked_len(len, "basin size")?];
+                    file.read_exact(&mut mask)?;
+                    Self::Basin(LakeExtent {
+                        level,
+                        min,
+                        size,
+                        mask: mask.into_iter().map(|wet| wet != 0).collect(),
+                    })
+                }
+                1 => {
+                    let level = f32::from_bits(read_u32(&mut file)?);
+                    let len = read_u32(&mut file)?;
+                    let mut outline = vec![Vec2::ZERO; checked_len(Some(len), "outline")?];
+                    file.read_exact(bytemuck::cast_slice_mut(&mut outline))?;
+                    Self::Lake { outline, level }
+                }
+                2 => {
+                    let width = f32::from_bits(read_u32(&mut file)?);
+                    let len = read_u32(&mut file)?;
+                    let mut path = vec![Vec3::ZERO; checked_len(Some(len), "river path")?];
+                    file.read_exact(bytemuck::cast_slice_mut(&mut path))?;
+                    Self::River { path, width }
+                }
+                v => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid water shape: '{v}'"),
+                    ));
+                }
+            };
+            shapes.push(shape);
+        }
+
+        Ok(shapes)
+    }
+}
+
+/// Most basin texels or outline points [WaterShape::load_all] allocates, so a broken file
+/// can't ask for too much memory
+const MAX_LOAD_LEN: u32 = 1 << 24;
+
+/// `len` as a length to allocate, `None` meaning it overflowed
+fn checked_len(len: Option<u32>, what: &str) -> std::io::Result<usize> {
+    match len {
+        Some(len) if len <= MAX_LOAD_LEN => Ok(len as usize),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid {what} length"),
+        )),
+    }
+}
+
+/// Heap entry, lowest height first
+#[derive(PartialEq)]
+struct Lowest(f32, UVec2);
+
+impl Eq for Lowest {}
+
+impl Ord for Lowest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+impl PartialOrd for Lowest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+const NEIGHBOURS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// Fills the basin water at `position` (world units, origin at texel `(0, 0)`) would run
+/// into, up to where it spills over the rim or off the map.
+///
+/// Returns `None` if there is no basin, or it floods more than `max_texels`.
+pub fn flood_fill_lake(
+    h_bundle: &HeightmapBundle,
+    position: Vec2,
+    max_texels: usize,
+) -> Option<LakeExtent> {
+    let size = h_bundle.size();
+    let texel = (position / h_bundle.texel_size()).round();
+    if texel.cmplt(Vec2::ZERO).any() || texel.cmpge(size.as_vec2()).any() {
+        return None;
+    }
+    let neighbours = |texel: UVec2| {
+        NEIGHBOURS.iter().filter_map(move |offset| {
+            let n = texel.as_ivec2() + *offset;
+            (n.cmpge(IVec2::ZERO).all() && n.cmplt(size.as_ivec2()).all()).then_some(n.as_uvec2())
+        })
+    };
+    let index = |texel: UVec2| (texel.y * size.x + texel.x) as usize;
+
+    // Water runs downhill first
+    let mut bottom = texel.as_uvec2();
+    while let Some(lower) = neighbours(bottom)
+        .filter(|n| h_bundle.height(*n) < h_bundle.height(bottom))
+        .min_by(|a, b| h_bundle.height(*a).total_cmp(&h_bundle.height(*b)))
+    {
+        bottom = lower;
+    }
+
+    // Raise the water until the next lowest shore texel is below it: the rim
+    let mut level = h_bundle.height(bottom);
+    let mut visited = vec![false; (size.x * size.y) as usize];
+    let mut shore = BinaryHeap::from([Lowest(level, bottom)]);
+    visited[index(bottom)] = true;
+    let mut num_flooded = 0;
+    while let Some(Lowest(h, texel)) = shore.pop() {
+        if h < level {
+            break;
+        }
+        level = h;
+        let on_edge =
+            texel.x == 0 || texel.y == 0 || texel.x + 1 == size.x || texel.y + 1 == size.y;
+        if on_edge {
+            break;
+        }
+        num_flooded += 1;
+        if num_flooded > max_texels {
+            return None;
+        }
+        for n in neighbours(texel) {
+            if !std::mem::replace(&mut visited[index(n)], true) {
+                shore.push(Lowest(h_bundle.height(n), n));
+            }
+        }
+    }
+
+    // Everything below the rim connected to the bottom
+    let mut wet = Vec::new();
+    let mut queue = VecDeque::new();
+    visited.fill(false);
+    if h_bundle.height(bottom) < level {
+        visited[index(bottom)] = true;
+        queue.push_back(bottom);
+    }
+    while let Some(texel) = queue.pop_front() {
+        wet.push(texel);
+        for n in neighbours(texel) {
+            if h_bundle.height(n) < level && !std::mem::replace(&mut visited[index(n)], true) {
+                queue.push_back(n);
+            }
+        }
+    }
+    if wet.is_empty() {
+        return None;
+    }
+
+    let min = wet.iter().copied().reduce(UVec2::min)?;
+    let max = wet.iter().copied().reduce(UVec2::max)?;
+    let extent_size = max - min + 1;
+    let mut mask = vec![false; (extent_size.x * extent_size.y) as usize];
+    for texel in wet {
+        let local = texel - min;
+        mask[(local.y * extent_size.x + local.x) as usize] = true;
+    }
+    Some(LakeExtent {
+        level,
+        min,
+        size: extent_size,
+        mask,
+    })
+}
+
+/// Even-odd rule
+pub fn polygon_contains(outline: &[Vec2], p: Vec2) -> bool {
+    let mut inside = false;
+    for (a, b) in outline.iter().zip(outline.iter().cycle().skip(1)) {
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Flat or ribbon shaped water surface, in the same space as the terrain cells.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WaterMesh {
+    pub vertices: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<u32>,
+}
+
+impl WaterMesh {
+    /// Covers each wet texel of `extent`, one quad per run of texels in a row
+    pub fn lake(extent: &LakeExtent, texel_size: Vec2) -> Self {
+        let mut mesh = Self::default();
+        for y in 0..extent.size.y {
+            let mut x = 0;
+            while x < extent.size.x {
+                let start = extent.min + uvec2(x, y);
+                if !extent.contains(start) {
+                    x += 1;
+                    continue;
+                }
+                let mut end = x;
+                while end + 1 < extent.size.x && extent.contains(extent.min + uvec2(end + 1, y)) {
+                    end += 1;
+                }
+
+                let min = (start.as_vec2() - 0.5) * texel_size;
+                let max = ((extent.min + uvec2(end, y)).as_vec2() + 0.5) * texel_size;
+                mesh.push_quad(min, max, extent.level);
+                x = end + 1;
+            }
+        }
+        mesh
+    }
+
+    /// Fills `outline`, in scanline bands `spacing` apart
+    pub fn polygon(outline: &[Vec2], level: f32, spacing: f32) -> Self {
+        let mut mesh = Self::default();
+        if outline.len() < 3 || spacing <= 0.0 {
+            return mesh;
+        }
+        let min_y = outline.iter().map(|p| p.y).fold(f32::INFINITY, f32::min);
+        let max_y = outline
+            .iter()
+            .map(|p| p.y)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        let mut y = min_y;
+        while y < max_y {
+            let band_max = (y + spacing).min(max_y);
+            let center = (y + band_max) / 2.0;
+            let mut crossings: Vec<f32> = outline
+                .iter()
+                .zip(outline.iter().cycle().skip(1))
+                .filter(|(a, b)| (a.y > center) != (b.y > center))
+                .map(|(a, b)| a.x + (center - a.y) / (b.y - a.y) * (b.x - a.x))
+                .collect();
+            crossings.sort_by(f32::total_cmp);
+            for span in crossings.chunks_exact(2) {
+                mesh.push_quad(vec2(span[0], y), vec2(span[1], band_max), level);
+            }
+            y = band_max;
+        }
+        mesh
+    }
+
+    /// Ribbon `width` wide along `path`, following its heights
+    pub fn river(path: &[Vec3], width: f32) -> Self {
+        let mut mesh = Self::default();
+        if path.len() < 2 {
+            return mesh;
+        }
+
+        let mut distance = 0.0;
+        for (i, p) in path.iter().enumerate() {
+            let prev = path[i.saturating_sub(1)];
+            let next = path[(i + 1).min(path.len() - 1)];
+            let direction = (next - prev).xz().normalize_or(Vec2::X);
+            let side = direction.perp() * width / 2.0;
+            if i > 0 {
+                distance += p.distance(prev);
+            }
+
+            let v = distance / width;
+            mesh.vertices.push(*p - side.extend(0.0).xzy());
+            mesh.vertices.push(*p + side.extend(0.0).xzy());
+            mesh.uvs.push(vec2(0.0, v));
+            mesh.uvs.push(vec2(1.0, v));
+        }
+        for i in 0..path.len() as u32 - 1 {
+            let (a, b, c, d) = (i * 2, i * 2 + 1, i * 2 + 2, i * 2 + 3);
+            mesh.indices.extend_from_slice(&[a, c, b, b, c, d]);
+        }
+        mesh
+    }
+
+    fn push_quad(&mut self, min: Vec2, max: Vec2, level: f32) {
+        let first = self.vertices.len() as u32;
+        for corner in [min, vec2(max.x, min.y), vec2(min.x, max.y), max] {
+            self.vertices.push(vec3(corner.x, level, corner.y));
+            self.uvs.push(corner);
+        }
+        self.indices.extend_from_slice(&[
+            first,
+            first + 2,
+            first + 1,
+            first + 1,
+            first + 2,
+            first + 3,
+        ]);
+    }
+
+    pub fn bevy_mesh(&self) -> Mesh {
+        use bevy::asset::RenderAssetUsages;
+        use bevy::mesh::Indices;
+        use bevy::mesh::PrimitiveTopology;
+
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![Vec3::Y; self.vertices.len()]);
+        mesh.insert_indices(Indices::U32(self.indices.clone()));
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain_processing::WorldSettings;
+    use crate::terrain_processing::heightmap::GrayF32Image;
+    use image::Luma;
+
+    /// 33x33 texels over a 32 unit world, 100 units high. A square pit 10 deep at
+    /// 10..20 with a 5 high rim around it, sloping down to the edges.
+    fn test_bundle() -> HeightmapBundle {
+        let map = GrayF32Image::from_fn(33, 33, |x, y| {
+            let h = match (x, y) {
+                (10..20, 10..20) => 10.0,
+                (9..=20, 9..=20) => 25.0,
+                (8..=21, 8..=21) => 20.0,
+                _ => 15.0,
+            };
+            // The rim dips to 22 on one side
+            let h = if (x, y) == (15, 9) { 22.0 } else { h };
+            Luma([h / 100.0])
+        });
+        let world = WorldSettings {
+            world_size: 32,
+            cell_size: 16,
+            world_height: 100.0,
+            world_height_offset: 0.0,
+        };
+        HeightmapBundle::new(map, world)
+    }
+
+    #[test]
+    fn test_flood_fill_stops_at_rim() {
+        let h_bundle = test_bundle();
+        let lake = flood_fill_lake(&h_bundle, vec2(14.0, 14.0), 10_000).unwrap();
+        assert!((lake.level - 22.0).abs() < 1e-4);
+        assert_eq!((lake.min, lake.size), (uvec2(10, 10), uvec2(10, 10)));
+        assert_eq!(lake.num_texels(), 100);
+        assert!(!lake.contains(uvec2(9, 9)));
+    }
+
+    #[test]
+    fn test_flood_fill_runs_downhill_first() {
+        let h_bundle = test_bundle();
+        // On the rim: water ends up in the pit or outside, never stays
+        let lake = flood_fill_lake(&h_bundle, vec2(12.0, 9.0), 10_000);
+        assert!(lake.is_none_or(|lake| lake.num_texels() == 100));
+        // Outside slopes straight off the map
+        assert_eq!(flood_fill_lake(&h_bundle, vec2(2.0, 2.0), 10_000), None);
+    }
+
+    #[test]
+    fn test_flood_fill_limit() {
+        let h_bundle = test_bundle();
+        assert_eq!(flood_fill_lake(&h_bundle, vec2(14.0, 14.0), 50), None);
+    }
+
+    #[test]
+    fn test_lake_mesh_merges_rows() {
+        let h_bundle = test_bundle();
+        let lake = flood_fill_lake(&h_bundle, vec2(14.0, 14.0), 10_000).unwrap();
+        let mesh = WaterMesh::lake(&lake, h_bundle.texel_size());
+        // One quad per row
+        assert_eq!(mesh.vertices.len(), 10 * 4);
+        assert!(mesh.vertices.iter().all(|v| v.y == lake.level));
+        assert_eq!(mesh.vertices[0], vec3(9.5, lake.level, 9.5));
+    }
+
+    #[test]
+    fn test_polygon_mesh_area() {
+        let triangle = [vec2(0.0, 0.0), vec2(10.0, 0.0), vec2(0.0, 10.0)];
+        assert!(polygon_contains(&triangle, vec2(2.0, 2.0)));
+        assert!(!polygon_contains(&triangle, vec2(8.0, 8.0)));
+
+        let mesh = WaterMesh::polygon(&triangle, 3.0, 0.5);
+        let area: f32 = mesh
+            .vertices
+            .chunks_exact(4)
+            .map(|quad| (quad[3].x - quad[0].x) * (quad[3].z - quad[0].z))
+            .sum();
+        assert!((area - 50.0).abs() < 0.1, "{area}");
+    }
+
+    #[test]
+    fn test_river_ribbon() {
+        let path = [
+            vec3(0.0, 1.0, 0.0),
+            vec3(10.0, 0.5, 0.0),
+            vec3(20.0, 0.0, 0.0),
+        ];
+        let mesh = WaterMesh::river(&path, 4.0);
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices.len(), 12);
+        assert_eq!(mesh.vertices[2], vec3(10.0, 0.5, -2.0));
+        // V runs along the path in widths, slope included
+        let length = 2.0 * 10f32.hypot(0.5);
+        assert_eq!(mesh.uvs[5], vec2(1.0, length / 4.0));
+    }
+
+    #[test]
+    fn test_shapes_save_load() {
+        let basin = flood_fill_lake(&test_bundle(), vec2(14.0, 14.0), 10_000).unwrap();
+        let shapes = vec![
+            WaterShape::Basin(basin),
+            WaterShape::Lake {
+                outline: vec![vec2(0.0, 0.0), vec2(8.0, 0.0), vec2(4.0, 6.0)],
+                level: 12.5,
+            },
+            WaterShape::River {
+                path: vec![vec3(0.0, 3.0, 0.0), vec3(10.0, 2.0, 5.0)],
+                width: 4.0,
+            },
+        ];
+
+        let path = std::env::temp_dir().join("worldedit_test_water.wbody");
+        WaterShape::save_all(&path, &shapes).unwrap();
+        assert_eq!(WaterShape::load_all(&path).unwrap(), shapes);
+    }
+
+    #[test]
+    fn test_shapes_load_rejects_huge_basin() {
+        let mut bytes = WaterShape::FILE_SIG.to_vec();
+        bytes.extend(WaterShape::FILE_VER.to_le_bytes());
+        for v in [1, 0, 0, 0, 0, u32::MAX, u32::MAX] {
+            bytes.extend(v.to_le_bytes());
+        }
+
+        let path = std::env::temp_dir().join("worldedit_test_water_huge.wbody");
+        std::fs::write(&path, bytes).unwrap();
+        let err = WaterShape::load_all(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use bevy::math::prelude::*;
+
+/// World dimensions, stored per project. All dimensions are in metres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldSettings {
+    pub world_size: u32,
+    pub cell_size: u32,
+    pub world_height: f32,
+    pub world_height_offset: f32,
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self {
+            world_size: 2048,
+            cell_size: 512,
+            world_height: 512.,
+            world_height_offset: -512. / 10.,
+        }
+    }
+}
+
+impl WorldSettings {
+    pub const FILE_NAME: &str = "world.wset";
+    pub const FILE_SIG: &[u8; 16] = b"WEdit-WorldSet  ";
+    pub const FILE_VER: u32 = 0;
+
+    pub const fn num_cells_row(&self) -> u32 {
+        self.world_size / self.cell_size
+    }
+
+    pub const fn num_cells(&self) -> u32 {
+        self.num_cells_row() * self.num_cells_row()
+    }
+
+    /// Min corner coordinate of a cell
+    pub const fn cell_position(&self, index: u32) -> UVec2 {
+        uvec2(
+            (index % self.num_cells_row()) * self.cell_size,
+            (index / self.num_cells_row()) * self.cell_size,
+        )
+    }
+
+    /// Index of the cell containing a world position, `None` outside the world. The far
+    /// edges belong to the last cells.
+    pub fn cell_index(&self, position: Vec2) -> Option<u32> {
+        let world_size = self.world_size as f32;
+        if position.cmplt(Vec2::ZERO).any() || position.cmpgt(Vec2::splat(world_size)).any() {
+            return None;
+        }
+        let last = self.num_cells_row() - 1;
+        let cell = (position / self.cell_size as f32)
+            .as_uvec2()
+            .min(UVec2::splat(last));
+        Some(cell.y * self.num_cells_row() + cell.x)
+    }
+
+    pub fn validate(&self) -> std::io::Result<()> {
+        let invalid = |msg: String| Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+
+        if self.cell_size == 0 || self.world_size == 0 {
+            return invalid(format!(
+                "world size {} and cell size {} must be non-zero",
+                self.world_size, self.cell_size
+            ));
+        }
+        if !self.world_size.is_multiple_of(self.cell_size) {
+            return invalid(format!(
+                "world size {} isn't a multiple of cell size {}",
+                self.world_size, self.cell_size
+            ));
+        }
+        if !self.world_height.is_finite() || !self.world_height_offset.is_finite() {
+            return invalid(format!(
+                "world height {} and offset {} must be finite",
+                self.world_height, self.world_height_offset
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the settings as a part of a file header.
+    pub fn write_header(&self, w: &mut impl Write) -> std::io::Result<()> {
+        w.write_all(&self.world_size.to_le_bytes())?;
+        w.write_all(&self.cell_size.to_le_bytes())?;
+        w.write_all(&self.world_height.to_le_bytes())?;
+        w.write_all(&self.world_height_offset.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Reads and validates settings written by [Self::write_header].
+    pub fn read_header(r: &mut impl Read) -> std::io::Result<Self> {
+        let mut buf = [0u8; 4];
+
+        r.read_exact(&mut buf)?;
+        let world_size = u32::from_le_bytes(buf);
+        r.read_exact(&mut buf)?;
+        let cell_size = u32::from_le_bytes(buf);
+        r.read_exact(&mut buf)?;
+        let world_height = f32::from_le_bytes(buf);
+        r.read_exact(&mut buf)?;
+        let world_height_offset = f32::from_le_bytes(buf);
+
+        let settings = Self {
+            world_size,
+            cell_size,
+            world_height,
+            world_height_offset,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(Self::FILE_SIG)?;
+        file.write_all(&Self::FILE_VER.to_le_bytes())?;
+        self.write_header(&mut file)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut buf = [0u8; 4];
+        let mut sig_buf = [0u8; 16];
+
+        file.read_exact(&mut sig_buf)?;
+        if &sig_buf != Self::FILE_SIG {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid FILE_SIG",
+            ));
+        }
+        file.read_exact(&mut buf)?;
+        let ver = u32::from_le_bytes(buf);
+        if ver != Self::FILE_VER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid version: exp '{}', got '{ver}'", Self::FILE_VER),
+            ));
+        }
+
+        Self::read_header(&mut file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_is_valid() {
+        assert!(WorldSettings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_world_size_is_multiple_of_cell_size() {
+        let settings = WorldSettings {
+            world_size: 1000,
+            cell_size: 512,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_cell_position() {
+        let settings = WorldSettings {
+            world_size: 1024,
+            cell_size: 256,
+            ..Default::default()
+        };
+        assert_eq!(settings.num_cells(), 16);
+        assert_eq!(settings.cell_position(0), uvec2(0, 0));
+        assert_eq!(settings.cell_position(5), uvec2(256, 256));
+        assert_eq!(settings.cell_position(15), uvec2(768, 768));
+
+        assert_eq!(settings.cell_index(vec2(300.0, 10.0)), Some(1));
+        assert_eq!(settings.cell_index(vec2(1024.0, 1024.0)), Some(15));
+        assert_eq!(settings.cell_index(vec2(-1.0, 10.0)), None);
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let settings = WorldSettings {
+            world_size: 8192,
+            cell_size: 512,
+            world_height: 1200.,
+            world_height_offset: -30.,
+        };
+        let mut bytes = vec![];
+        settings.write_header(&mut bytes).unwrap();
+        let read = WorldSettings::read_header(&mut bytes.as_slice()).unwrap();
+        assert_eq!(settings, read);
+    }
+}